@@ -0,0 +1,239 @@
+// Versioned JSON API under /api/v1/, with songs, tags, playlists and search as resources. Every
+// response, failures included, is one envelope:
+//   {"data": ...}
+//   {"error": {"code": "not_found", "message": "no song x.mp3"}}
+// The error code is for programs to branch on and always goes with the same status code.
+
+use kinode_process_lib::http::StatusCode;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::ingest::{self, IngestError};
+use crate::links::decode_component;
+use crate::playlist::PlaylistEdit;
+use crate::search::{self, SongFilter};
+use crate::structs::{Song, SongDb, Tag};
+
+pub const PREFIX: &str = "/api/v1/";
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> ApiError {
+        ApiError { status, code, message: message.into() }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::CONFLICT, "conflict", message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> ApiError {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal", message)
+    }
+
+    /// The ApiError an anyhow error was made from, otherwise an internal error
+    pub fn from_anyhow(error: &anyhow::Error) -> ApiError {
+        match error.downcast_ref::<ApiError>() {
+            Some(api_error) => ApiError::new(api_error.status, api_error.code, api_error.message.clone()),
+            None => ApiError::internal(error.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<IngestError> for ApiError {
+    fn from(error: IngestError) -> ApiError {
+        let code = match error {
+            IngestError::MissingFile => "missing_file",
+            IngestError::TooLarge { .. } => "payload_too_large",
+            IngestError::UnrecognizedFormat => "unsupported_format",
+            IngestError::Malformed { .. } => "malformed_file",
+            IngestError::MissingName => "missing_name",
+            IngestError::MissingTag => "missing_tag",
+        };
+        ApiError::new(error.status(), code, error.to_string())
+    }
+}
+
+/// A successful answer; `update` is pushed to websocket clients when the library changed
+pub struct Reply {
+    pub status: StatusCode,
+    pub data: Value,
+    pub update: Option<&'static str>,
+}
+
+impl Reply {
+    fn ok(data: impl Serialize) -> Result<Reply, ApiError> {
+        Reply::with_status(StatusCode::OK, data, None)
+    }
+
+    fn changed(status: StatusCode, data: impl Serialize, update: &'static str) -> Result<Reply, ApiError> {
+        Reply::with_status(status, data, Some(update))
+    }
+
+    fn with_status(status: StatusCode, data: impl Serialize, update: Option<&'static str>) -> Result<Reply, ApiError> {
+        let data = serde_json::to_value(data).map_err(|e| ApiError::internal(e.to_string()))?;
+        Ok(Reply { status, data, update })
+    }
+}
+
+/// Status and envelope for a result
+pub fn envelope(result: &Result<Reply, ApiError>) -> (StatusCode, Vec<u8>) {
+    let (status, body) = match result {
+        Ok(reply) => (reply.status, json!({ "data": reply.data })),
+        Err(e) => (e.status, json!({ "error": { "code": e.code, "message": e.message } })),
+    };
+    (status, body.to_string().into_bytes())
+}
+
+#[derive(Serialize)]
+struct TagSummary {
+    key: String,
+    name: Option<String>,
+    song_count: usize,
+}
+
+/// Routes a request by method and the path below /api/v1/
+pub fn handle(
+    method: &str,
+    path: &str,
+    params: &HashMap<String, String>,
+    body: Option<Vec<u8>>,
+    song_db: &mut SongDb,
+) -> Result<Reply, ApiError> {
+    let segments: Vec<String> = path.strip_prefix(PREFIX).unwrap_or_default()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(decode_component)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    match (method, segments.as_slice()) {
+        ("GET", ["songs"]) => {
+            let filter = song_filter(params)?;
+            let mut songs: Vec<&Song> = match params.get("tag") {
+                Some(tag_key) => song_db.songs.get(tag_key).into_iter().flatten().collect(),
+                None => song_db.songs.values().flatten().collect(),
+            };
+            songs.retain(|song| filter.matches(song));
+            songs.sort_by(|a, b| a.tag.key.cmp(&b.tag.key).then(a.id.cmp(&b.id)));
+            Reply::ok(songs)
+        }
+        ("POST", ["songs"]) => {
+            // the audio file is the body; ?name= and ?tag= default to the file's title and genre
+            let data = body.unwrap_or_default();
+            let tag_key = params.get("tag").cloned().unwrap_or_default();
+            let tag = Tag { key: tag_key.clone(), name: Some(tag_key).filter(|key| !key.is_empty()) };
+            let name = params.get("name").map_or("", String::as_str);
+            let mut song = ingest::song_from_upload(name, tag, data, song_db.settings.max_upload_bytes)?;
+            if song_db.contains_song(&song.id) {
+                // ?on_conflict=keep_both stores it under a free id instead
+                if params.get("on_conflict").is_none_or(|policy| policy != "keep_both") {
+                    return Err(ApiError::conflict(format!("there already is a song {}", song.id)));
+                }
+                song.id = song_db.free_song_id(&song.id);
+            }
+            let id = song.id.clone();
+            song_db.add_song(song).map_err(|e| ApiError::internal(format!("failed to store song: {}", e)))?;
+            Reply::changed(StatusCode::CREATED, song_db.get_song(&id), "Song uploaded successfully")
+        }
+        ("GET", ["songs", id]) => Reply::ok(song_db.get_song(id).ok_or_else(|| no_song(id))?),
+        ("DELETE", ["songs", id]) => {
+            if !song_db.contains_song(id) {
+                return Err(no_song(id));
+            }
+            song_db.delete_song(id).map_err(|e| ApiError::internal(format!("failed to delete song: {}", e)))?;
+            Reply::changed(StatusCode::OK, json!({ "id": id }), "Song moved to trash")
+        }
+        ("GET", ["tags"]) => {
+            let mut tags: Vec<TagSummary> = song_db.songs.iter()
+                .map(|(key, songs)| TagSummary {
+                    key: key.clone(),
+                    name: songs.first().and_then(|song| song.tag.name.clone()),
+                    song_count: songs.len(),
+                })
+                .collect();
+            tags.sort_by(|a, b| a.key.cmp(&b.key));
+            Reply::ok(tags)
+        }
+        ("GET", ["tags", key]) => {
+            let songs = song_db.songs.get(*key).ok_or_else(|| ApiError::not_found(format!("no tag {}", key)))?;
+            Reply::ok(json!({
+                "key": key,
+                "name": songs.first().and_then(|song| song.tag.name.clone()),
+                "songs": songs,
+            }))
+        }
+        ("GET", ["playlists"]) => Reply::ok(song_db.list_playlists()),
+        ("POST", ["playlists"]) => {
+            let edit = json_body::<PlaylistEdit>(body)?;
+            let playlist = song_db.save_playlist(None, edit).map_err(|e| ApiError::bad_request(e.to_string()))?;
+            Reply::changed(StatusCode::CREATED, playlist, "Playlists updated")
+        }
+        ("GET", ["playlists", id]) => {
+            let playlist = song_db.playlists.get(*id).ok_or_else(|| no_playlist(id))?;
+            let songs = song_db.playlist_songs(playlist);
+            Reply::ok(json!({ "playlist": playlist, "songs": songs }))
+        }
+        ("PUT" | "PATCH", ["playlists", id]) => {
+            if !song_db.playlists.contains_key(*id) {
+                return Err(no_playlist(id));
+            }
+            let edit = json_body::<PlaylistEdit>(body)?;
+            let playlist = song_db.save_playlist(Some(id), edit).map_err(|e| ApiError::bad_request(e.to_string()))?;
+            Reply::changed(StatusCode::OK, playlist, "Playlists updated")
+        }
+        ("DELETE", ["playlists", id]) => {
+            song_db.delete_playlist(id).map_err(|_| no_playlist(id))?;
+            Reply::changed(StatusCode::OK, json!({ "id": id }), "Playlists updated")
+        }
+        ("GET", ["search"]) => {
+            let query = params.get("q").map_or("", String::as_str);
+            Reply::ok(search::search(song_db, query, &song_filter(params)?))
+        }
+        (_, ["songs" | "tags" | "playlists" | "search", ..]) if segments.len() <= 2 => Err(ApiError::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method_not_allowed",
+            format!("{} is not supported on {}", method, path),
+        )),
+        _ => Err(ApiError::not_found(format!("no resource at {}", path))),
+    }
+}
+
+fn song_filter(params: &HashMap<String, String>) -> Result<SongFilter, ApiError> {
+    SongFilter::from_query(params).map_err(ApiError::bad_request)
+}
+
+fn json_body<T: serde::de::DeserializeOwned>(body: Option<Vec<u8>>) -> Result<T, ApiError> {
+    let body = body.filter(|body| !body.is_empty()).ok_or_else(|| ApiError::bad_request("request body is empty"))?;
+    serde_json::from_slice(&body).map_err(|e| ApiError::bad_request(format!("invalid JSON: {}", e)))
+}
+
+fn no_song(id: &str) -> ApiError {
+    ApiError::not_found(format!("no song {}", id))
+}
+
+fn no_playlist(id: &str) -> ApiError {
+    ApiError::not_found(format!("no playlist {}", id))
+}
@@ -0,0 +1,148 @@
+use kinode_process_lib::vfs;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Cursor, Read, Write};
+
+use crate::structs::{ConflictPolicy, ImportSummary, Song, SongDb};
+
+// archive layout:
+//   catalog.json        -> LibraryCatalog
+//   music_db/<song id>  -> raw audio file
+const CATALOG_ENTRY: &str = "catalog.json";
+const AUDIO_DIR: &str = "music_db";
+const CATALOG_VERSION: u32 = 1;
+/// Exports to vfs are appended in chunks of this size rather than built in memory first
+const WRITE_CHUNK: usize = 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LibraryCatalog {
+    version: u32,
+    songs: Vec<Song>,
+}
+
+/// Bundles the catalog and every audio file in music_db into a tar archive written to `out`,
+/// one song file at a time
+pub fn export_library<W: Write>(song_db: &SongDb, out: W) -> anyhow::Result<W> {
+    let songs: Vec<Song> = song_db.songs.values().flatten().cloned().collect();
+    let catalog = serde_json::to_vec_pretty(&LibraryCatalog {
+        version: CATALOG_VERSION,
+        songs: songs.clone(),
+    })?;
+
+    let mut builder = tar::Builder::new(out);
+    append_entry(&mut builder, CATALOG_ENTRY, &catalog)?;
+
+    for song in &songs {
+        let file = vfs::open_file(&song_db.song_path(&song.id), false, None)?;
+        let data = file.read()?;
+        append_entry(&mut builder, &format!("{}/{}", AUDIO_DIR, song.id), &data)?;
+    }
+
+    Ok(builder.into_inner()?)
+}
+
+/// Exports straight into a vfs file, so the archive is never held in memory as a whole
+pub fn export_library_to_file(song_db: &SongDb, path: &str) -> anyhow::Result<()> {
+    let file = vfs::create_file(path, None)?;
+    let out = export_library(song_db, BufWriter::with_capacity(WRITE_CHUNK, VfsAppender(file)))?;
+    out.into_inner().map_err(|e| e.into_error())?;
+    Ok(())
+}
+
+struct VfsAppender(vfs::File);
+
+impl Write for VfsAppender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.append(buf).map_err(|e| io::Error::other(format!("{:?}", e)))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Restores an archive produced by `export_library` into `song_db`
+pub fn import_library(
+    song_db: &mut SongDb,
+    archive_bytes: &[u8],
+    on_conflict: ConflictPolicy,
+) -> anyhow::Result<ImportSummary> {
+    let mut catalog: Option<LibraryCatalog> = None;
+    let mut files: HashMap<String, Vec<u8>> = HashMap::new();
+
+    let mut archive = tar::Archive::new(Cursor::new(archive_bytes));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+
+        if path == CATALOG_ENTRY {
+            catalog = Some(serde_json::from_slice(&data)?);
+        } else if let Some(song_id) = path.strip_prefix(&format!("{}/", AUDIO_DIR)) {
+            files.insert(song_id.to_string(), data);
+        }
+    }
+
+    let catalog = catalog.ok_or_else(|| anyhow::anyhow!("archive has no {}", CATALOG_ENTRY))?;
+    if catalog.version > CATALOG_VERSION {
+        return Err(anyhow::anyhow!("unsupported catalog version {}", catalog.version));
+    }
+    // checked up front so that a bad archive doesn't leave half an import behind
+    if let Some(song) = catalog.songs.iter().find(|song| !is_safe_song_id(&song.id)) {
+        return Err(anyhow::anyhow!("archive has a song with an unsafe id: {:?}", song.id));
+    }
+
+    let mut summary = ImportSummary::default();
+    for mut song in catalog.songs {
+        let mut replaced = None;
+        let Some(data) = files.remove(&song.id) else {
+            summary.missing.push(song.id);
+            continue;
+        };
+
+        if song_db.contains_song(&song.id) {
+            match on_conflict {
+                ConflictPolicy::Skip => {
+                    summary.skipped.push(song.id);
+                    continue;
+                }
+                ConflictPolicy::Overwrite => {
+                    replaced = song_db.remove_song(&song.id);
+                    summary.overwritten.push(song.id.clone());
+                }
+                ConflictPolicy::KeepBoth => {
+                    let new_id = song_db.free_song_id(&song.id);
+                    summary.renamed.push((song.id.clone(), new_id.clone()));
+                    song.id = new_id;
+                }
+            }
+        } else {
+            summary.imported.push(song.id.clone());
+        }
+
+        song.data = data;
+        song_db.add_song(song)?;
+        if let Some(old) = replaced {
+            song_db.remove_derived_files(&old);
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Song ids become vfs paths under music_db, so they can't hold a path separator or start
+/// with a dot (which covers "..")
+fn is_safe_song_id(id: &str) -> bool {
+    !id.is_empty() && !id.starts_with('.') && !id.contains(['/', '\\', '\0'])
+}
+
+fn append_entry<W: Write>(builder: &mut tar::Builder<W>, path: &str, data: &[u8]) -> anyhow::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
@@ -0,0 +1,110 @@
+// Virtual tracks inside one long file, e.g. the tracks of an hour-long DJ set. They come from a
+// cue sheet, uploaded alongside the audio or embedded as a CUESHEET comment, or from ID3 CHAP
+// frames. https://wiki.hydrogenaud.io/index.php?title=Cue_sheet
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Chapter {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub start_ms: u64,
+    pub end_ms: u64,
+}
+
+/// A chapter as read, before we know where it ends
+#[derive(Debug, Clone, Default)]
+pub struct ChapterMark {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub start_ms: u64,
+    pub end_ms: Option<u64>,
+}
+
+/// Id of a song's `index`th (1-based) chapter, as listed and streamed
+pub fn track_id(song_id: &str, index: usize) -> String {
+    format!("{}#{}", song_id, index)
+}
+
+/// Splits a track id back into the song id and chapter index
+pub fn parse_track_id(track_id: &str) -> Option<(&str, usize)> {
+    let (song_id, index) = track_id.rsplit_once('#')?;
+    Some((song_id, index.parse().ok().filter(|&index| index > 0)?))
+}
+
+/// Orders the marks and fills in the ends: the next chapter's start, or the end of the song. A
+/// single chapter covering the whole song isn't worth listing.
+pub fn close(mut marks: Vec<ChapterMark>, duration_ms: u64) -> Vec<Chapter> {
+    marks.retain(|mark| mark.start_ms < duration_ms);
+    marks.sort_by_key(|mark| mark.start_ms);
+    let starts: Vec<u64> = marks.iter().map(|mark| mark.start_ms).skip(1).chain([duration_ms]).collect();
+    let chapters: Vec<Chapter> = marks.into_iter().zip(starts)
+        .map(|(mark, next_start)| Chapter {
+            end_ms: mark.end_ms.filter(|&end| end > mark.start_ms).unwrap_or(next_start).min(duration_ms),
+            title: mark.title,
+            performer: mark.performer,
+            start_ms: mark.start_ms,
+        })
+        .collect();
+    if chapters.len() < 2 {
+        return Vec::new();
+    }
+    chapters
+}
+
+/// TRACK entries with their TITLE, PERFORMER and INDEX 01. FILE lines are ignored: the sheet is
+/// taken to describe the one file it came with.
+pub fn parse_cue(text: &str) -> Vec<ChapterMark> {
+    let mut marks: Vec<ChapterMark> = Vec::new();
+    let mut album_performer = None;
+    let mut in_track = false;
+    for line in text.lines() {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match command.to_ascii_uppercase().as_str() {
+            "TRACK" => {
+                in_track = true;
+                marks.push(ChapterMark { start_ms: u64::MAX, ..Default::default() });
+            }
+            "TITLE" if in_track => {
+                if let Some(mark) = marks.last_mut() {
+                    mark.title = unquote(rest);
+                }
+            }
+            "PERFORMER" if in_track => {
+                if let Some(mark) = marks.last_mut() {
+                    mark.performer = unquote(rest);
+                }
+            }
+            "PERFORMER" => album_performer = unquote(rest),
+            "INDEX" => {
+                let mut parts = rest.split_whitespace();
+                if parts.next() == Some("01") {
+                    if let (Some(mark), Some(start_ms)) = (marks.last_mut(), parts.next().and_then(parse_cue_time)) {
+                        mark.start_ms = start_ms;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    // a track without an INDEX 01 has nowhere to start
+    marks.retain(|mark| mark.start_ms != u64::MAX);
+    for mark in &mut marks {
+        mark.performer = mark.performer.take().or_else(|| album_performer.clone());
+    }
+    marks
+}
+
+/// "mm:ss:ff" with 75 frames a second
+fn parse_cue_time(time: &str) -> Option<u64> {
+    let mut parts = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(minutes * 60_000 + seconds * 1000 + frames * 1000 / 75)
+}
+
+fn unquote(text: &str) -> Option<String> {
+    let text = text.trim();
+    let text = text.strip_prefix('"').and_then(|text| text.strip_suffix('"')).unwrap_or(text).trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
@@ -0,0 +1,61 @@
+// Decodes a song to PCM with symphonia, for the analysis jobs that need actual samples
+// rather than what the container headers claim.
+
+use std::io::Cursor;
+use std::ops::ControlFlow;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::metadata::AudioFormat;
+
+/// Decodes the first audio track, handing each packet's samples to `on_samples` as
+/// interleaved f32 along with the channel count and sample rate, until it breaks. Packets
+/// that fail to decode are skipped, the way a player would.
+pub fn for_each_block(
+    format: AudioFormat,
+    data: Vec<u8>,
+    mut on_samples: impl FnMut(&[f32], usize, u32) -> ControlFlow<()>,
+) -> anyhow::Result<()> {
+    let stream = MediaSourceStream::new(Box::new(Cursor::new(data)), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(format.extension());
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())?;
+    let mut reader = probed.format;
+
+    let track = reader.tracks().iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| anyhow::anyhow!("No audio track found"))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(Error::ResetRequired) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(Error::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        if on_samples(buffer.samples(), spec.channels.count(), spec.rate).is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
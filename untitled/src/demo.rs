@@ -0,0 +1,60 @@
+use kinode_process_lib::{vfs, Address};
+
+use crate::ingest;
+use crate::structs::{Song, SongDb, Tag};
+
+pub const DEMO_TAG: &str = "demo";
+
+/// Installed with the package from pkg/demo rather than built into the process; a package
+/// built without that directory simply has no demo tracks to seed
+const DEMO_TRACKS: &[&str] = &["egba", "winston"];
+
+fn demo_track_path(our: &Address, name: &str) -> String {
+    format!("/{}/pkg/demo/{}.mp3", our.package_id(), name)
+}
+
+fn demo_song_id(name: &str) -> String {
+    format!("{}.mp3", name)
+}
+
+fn is_demo_song(song: &Song) -> bool {
+    song.tag.key == DEMO_TAG
+        && DEMO_TRACKS.iter().any(|name| demo_song_id(name) == song.id)
+}
+
+/// Copies the installed demo tracks into music_db under the `demo` tag, returns how many were added
+pub fn seed_demo_tracks(our: &Address, song_db: &mut SongDb) -> anyhow::Result<usize> {
+    let mut added = 0;
+    for name in DEMO_TRACKS {
+        let id = demo_song_id(name);
+        if song_db.contains_song(&id) {
+            continue;
+        }
+        let Ok(data) = vfs::open_file(&demo_track_path(our, name), false, None).and_then(|file| file.read()) else {
+            continue;
+        };
+        let tag = Tag { key: DEMO_TAG.to_string(), name: Some("Demo".to_string()) };
+        let mut song = ingest::song_from_upload(name, tag, data, song_db.settings.max_upload_bytes)?;
+        song.id = id;
+        song_db.add_song(song)?;
+        added += 1;
+    }
+    Ok(added)
+}
+
+/// Removes every seeded demo track from the catalog and vfs, returns how many were removed
+pub fn remove_demo_tracks(song_db: &mut SongDb) -> anyhow::Result<usize> {
+    let demo_ids: Vec<String> = song_db.songs.values().flatten()
+        .filter(|song| is_demo_song(song))
+        .map(|song| song.id.clone())
+        .collect();
+
+    for id in &demo_ids {
+        // the file goes first, so a failed removal leaves the song in the catalog
+        vfs::remove_file(&song_db.song_path(id), None)?;
+        if let Some(song) = song_db.remove_song(id) {
+            song_db.remove_derived_files(&song);
+        }
+    }
+    Ok(demo_ids.len())
+}
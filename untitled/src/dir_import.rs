@@ -0,0 +1,139 @@
+// Bulk import of a vfs directory tree, e.g. a folder copied onto the node by hand. Folders
+// between the chosen root and each file become the song's tag, and progress is reported per
+// file so the UI can follow a long import.
+
+use kinode_process_lib::vfs::{self, FileType};
+use serde::Serialize;
+
+use crate::chapters;
+use crate::ingest;
+use crate::lyrics::Lyrics;
+use crate::metadata::AudioFormat;
+use crate::structs::{DirectoryImportSummary, SongDb, Tag};
+
+#[derive(Debug, Serialize)]
+pub struct ImportProgress<'a> {
+    pub done: usize,
+    pub total: usize,
+    pub path: &'a str,
+    pub status: ImportStatus,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    Imported(String), // song id
+    Skipped,
+    Failed(String),
+}
+
+/// Recursively imports every audio file under `root` into the SongDb.
+/// Each song is tagged with the folders between `root` and the file,
+/// e.g. `<root>/Artist/Album/01.mp3` gets the tag `Artist/Album`.
+pub fn import_directory(
+    song_db: &mut SongDb,
+    root: &str,
+    mut on_progress: impl FnMut(ImportProgress),
+) -> anyhow::Result<DirectoryImportSummary> {
+    let root = normalize_path(root.trim_end_matches('/'));
+    // whole path components only: a sibling like music_db_old is fine to import from
+    let library = normalize_path(song_db.vfs_dir_path.trim_end_matches('/'));
+    if root == library || root.starts_with(&format!("{}/", library)) {
+        return Err(anyhow::anyhow!("can't import from the library's own drive"));
+    }
+
+    let mut files = Vec::new();
+    collect_audio_files(&root, &mut files)?;
+
+    let total = files.len();
+    let mut summary = DirectoryImportSummary::default();
+
+    for (i, path) in files.iter().enumerate() {
+        let already_imported = song_db.songs.values().flatten()
+            .any(|song| song.source_path.as_deref() == Some(path.as_str()));
+
+        let status = if already_imported {
+            summary.skipped.push(path.clone());
+            ImportStatus::Skipped
+        } else {
+            match import_file(song_db, &root, path) {
+                Ok(song_id) => {
+                    summary.imported.push(song_id.clone());
+                    ImportStatus::Imported(song_id)
+                }
+                Err(e) => {
+                    summary.failed.push((path.clone(), e.to_string()));
+                    ImportStatus::Failed(e.to_string())
+                }
+            }
+        };
+
+        on_progress(ImportProgress { done: i + 1, total, path, status });
+    }
+
+    Ok(summary)
+}
+
+fn import_file(song_db: &mut SongDb, root: &str, path: &str) -> anyhow::Result<String> {
+    let data = vfs::open_file(path, false, None)?.read()?;
+
+    let relative = path.strip_prefix(root).unwrap_or(path).trim_start_matches('/');
+    let mut folders: Vec<&str> = relative.split('/').collect();
+    let file_name = folders.pop().unwrap_or(relative);
+    let name = file_name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(file_name).to_string();
+
+    let tag = match folders.last() {
+        Some(folder) => Tag { key: folders.join("/"), name: Some(folder.to_string()) },
+        None => {
+            let root_name = root.rsplit('/').next().unwrap_or(root).to_string();
+            Tag { key: root_name.clone(), name: Some(root_name) }
+        }
+    };
+
+    let mut song = ingest::song_from_upload(&name, tag, data, song_db.settings.max_upload_bytes)?;
+    song.id = song_db.free_song_id(&song.id);
+    song.source_path = Some(path.to_string());
+    if let Some(lyrics) = read_beside(path, "lrc").and_then(|text| Lyrics::parse(&text)) {
+        song.lyrics = Some(lyrics);
+    }
+    if let (Some(cue_sheet), Some(properties)) = (read_beside(path, "cue"), &song.properties) {
+        song.chapters = chapters::close(chapters::parse_cue(&cue_sheet), properties.duration_ms);
+    }
+    let song_id = song.id.clone();
+    song_db.add_song(song)?;
+    Ok(song_id)
+}
+
+/// The text of a file next to the audio, e.g. "Set/mix.cue" for "Set/mix.mp3"
+fn read_beside(path: &str, extension: &str) -> Option<String> {
+    let stem = path.rsplit_once('.').map(|(stem, _)| stem)?;
+    let data = vfs::open_file(&format!("{}.{}", stem, extension), false, None).ok()?.read().ok()?;
+    Some(String::from_utf8_lossy(&data).into_owned())
+}
+
+fn collect_audio_files(dir_path: &str, files: &mut Vec<String>) -> anyhow::Result<()> {
+    for entry in vfs::open_dir(dir_path, false, None)?.read()? {
+        let path = normalize_path(&entry.path);
+        match entry.file_type {
+            FileType::Directory => collect_audio_files(&path, files)?,
+            FileType::File if is_audio_file(&path) => files.push(path),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+pub fn is_audio_file(path: &str) -> bool {
+    path.rsplit_once('.')
+        .map(|(_, ext)| AudioFormat::from_extension(ext).is_some())
+        .unwrap_or(false)
+}
+
+// vfs hands back dir entries without the leading slash
+fn normalize_path(path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    }
+}
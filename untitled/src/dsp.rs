@@ -0,0 +1,154 @@
+// Signal helpers shared by the analyses that work on a low sample rate mono mixdown rather
+// than the song as stored: fingerprints, tempo and key.
+
+use std::f64::consts::PI;
+use std::ops::ControlFlow;
+
+use crate::decode;
+use crate::metadata::AudioFormat;
+
+/// Up to `max_secs` of the song mixed down to mono and resampled to `rate`. Decoding stops if
+/// the stream changes sample rate partway.
+pub fn decode_mono(format: AudioFormat, data: Vec<u8>, rate: u32, max_secs: u32) -> anyhow::Result<Vec<f32>> {
+    let mut resampler: Option<Resampler> = None;
+    let mut mono = Vec::new();
+    decode::for_each_block(format, data, |samples, channels, sample_rate| {
+        let resampler = resampler.get_or_insert_with(|| Resampler::new(sample_rate, rate));
+        if resampler.from_rate != sample_rate {
+            return ControlFlow::Break(());
+        }
+        for frame in samples.chunks(channels.max(1)) {
+            let sample = frame.iter().sum::<f32>() / frame.len() as f32;
+            if let Some(sample) = resampler.push(sample) {
+                mono.push(sample);
+            }
+        }
+        if mono.len() >= (max_secs * rate) as usize {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })?;
+    Ok(mono)
+}
+
+/// Averages the input over each output sample's span, which doubles as the low-pass filter
+struct Resampler {
+    from_rate: u32,
+    step: f64,
+    position: f64,
+    sum: f64,
+    count: u32,
+}
+
+impl Resampler {
+    fn new(from_rate: u32, to_rate: u32) -> Self {
+        Resampler { from_rate, step: to_rate as f64 / from_rate as f64, position: 0.0, sum: 0.0, count: 0 }
+    }
+
+    fn push(&mut self, sample: f32) -> Option<f32> {
+        self.sum += sample as f64;
+        self.count += 1;
+        self.position += self.step;
+        if self.position < 1.0 {
+            return None;
+        }
+        self.position -= 1.0;
+        let average = self.sum / self.count as f64;
+        self.sum = 0.0;
+        self.count = 0;
+        Some(average as f32)
+    }
+}
+
+/// Power spectra of overlapping Hann-windowed frames, `len` / 2 bins each
+pub struct Spectrogram {
+    len: usize,
+    window: Vec<f64>,
+    twiddles: Vec<(f64, f64)>,
+    re: Vec<f64>,
+    im: Vec<f64>,
+}
+
+impl Spectrogram {
+    /// `len` must be a power of two
+    pub fn new(len: usize) -> Self {
+        Spectrogram {
+            len,
+            window: (0..len).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / (len - 1) as f64).cos()).collect(),
+            twiddles: (0..len / 2)
+                .map(|k| {
+                    let angle = -2.0 * PI * k as f64 / len as f64;
+                    (angle.cos(), angle.sin())
+                })
+                .collect(),
+            re: vec![0.0; len],
+            im: vec![0.0; len],
+        }
+    }
+
+    /// Calls `on_frame` with the power spectrum of every frame starting `hop` samples apart
+    pub fn for_each_frame(&mut self, samples: &[f32], hop: usize, mut on_frame: impl FnMut(&[f64])) {
+        let mut power = vec![0.0; self.len / 2];
+        for start in (0..samples.len().saturating_sub(self.len - 1)).step_by(hop) {
+            for i in 0..self.len {
+                self.re[i] = samples[start + i] as f64 * self.window[i];
+                self.im[i] = 0.0;
+            }
+            fft(&mut self.re, &mut self.im, &self.twiddles);
+            for (bin, power) in power.iter_mut().enumerate() {
+                *power = self.re[bin] * self.re[bin] + self.im[bin] * self.im[bin];
+            }
+            on_frame(&power);
+        }
+    }
+}
+
+/// Pitch class (0 is C) of every FFT bin between `min_freq` and `max_freq`
+pub fn pitch_classes(len: usize, rate: u32, min_freq: f64, max_freq: f64) -> Vec<Option<usize>> {
+    (0..len / 2)
+        .map(|bin| {
+            let freq = bin as f64 * rate as f64 / len as f64;
+            (min_freq..=max_freq).contains(&freq).then(|| {
+                let note = 12.0 * (freq / 440.0).log2() + 69.0;
+                (note.round() as i64).rem_euclid(12) as usize
+            })
+        })
+        .collect()
+}
+
+/// In-place iterative radix-2 FFT; the length must be a power of two and `twiddles` hold
+/// e^(-2πik/n) for k below n/2
+fn fft(re: &mut [f64], im: &mut [f64], twiddles: &[(f64, f64)]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let stride = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_re, w_im) = twiddles[k * stride];
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
@@ -0,0 +1,211 @@
+// Finding songs that are the same recording, and merging them into one. Identical files share a
+// content hash; re-encodes and retagged copies are caught by their acoustic fingerprints.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::fingerprint;
+use crate::metadata::AudioFormat;
+use crate::structs::{Song, SongDb};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DuplicateGroup {
+    /// best quality first
+    pub song_ids: Vec<String>,
+    /// what a merge would keep
+    pub keep: String,
+    /// every song in the group is the same file
+    pub identical: bool,
+    /// lowest fingerprint similarity that joined the group, 1.0 for identical files
+    pub similarity: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MergeSummary {
+    pub kept: String,
+    /// moved to the trash
+    pub merged: Vec<String>,
+}
+
+/// Groups of possible duplicates, found by content hash and fingerprint. Songs whose
+/// fingerprint job hasn't run yet are only matched by hash.
+pub fn find_duplicates(song_db: &mut SongDb) -> Vec<DuplicateGroup> {
+    song_db.load_fingerprints();
+    let song_db = &*song_db;
+    let songs: Vec<&Song> = song_db.songs.values().flatten().collect();
+
+    // union-find over song indices, remembering the weakest link of each group
+    let mut parent: Vec<usize> = (0..songs.len()).collect();
+    let mut weakest: HashMap<usize, f32> = HashMap::new();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let mut join = |parent: &mut Vec<usize>, a: usize, b: usize, similarity: f32| {
+        let (root_a, root_b) = (root(parent, a), root(parent, b));
+        if root_a == root_b {
+            return;
+        }
+        let lowest = [weakest.remove(&root_a), weakest.remove(&root_b), Some(similarity)]
+            .into_iter()
+            .flatten()
+            .fold(1.0f32, f32::min);
+        parent[root_b] = root_a;
+        weakest.insert(root_a, lowest);
+    };
+
+    // identical files, bucketed by hash
+    let mut by_hash: HashMap<&str, usize> = HashMap::new();
+    for (i, song) in songs.iter().enumerate() {
+        if song.content_hash.is_empty() {
+            continue;
+        }
+        match by_hash.get(song.content_hash.as_str()) {
+            Some(&first) => join(&mut parent, first, i, 1.0),
+            None => {
+                by_hash.insert(&song.content_hash, i);
+            }
+        }
+    }
+
+    // fingerprints are only compared between songs of about the same length, so sorted by
+    // duration each song is checked against a short window after it. Songs of unknown length
+    // could match anything and are checked against every other song.
+    let fingerprinted: Vec<(usize, &[u32])> = songs.iter().enumerate()
+        .filter_map(|(i, song)| Some((i, song_db.cached_fingerprint(&song.id)?)))
+        .collect();
+    let (mut timed, untimed): (Vec<_>, Vec<_>) = fingerprinted.iter()
+        .partition(|(i, _)| songs[*i].properties.is_some());
+    timed.sort_by_key(|(i, _)| duration_ms(songs[*i]));
+
+    let mut compare = |parent: &mut Vec<usize>, (a, fa): (usize, &[u32]), (b, fb): (usize, &[u32])| {
+        if root(parent, a) == root(parent, b) || !similar_length(songs[a], songs[b]) {
+            return;
+        }
+        if let Some(similarity) = fingerprint::similarity(fa, fb).filter(|&similarity| similarity >= fingerprint::MATCH_THRESHOLD) {
+            join(parent, a, b, similarity);
+        }
+    };
+    for (n, &a) in timed.iter().enumerate() {
+        for &b in &timed[n + 1..] {
+            let (shorter, longer) = (duration_ms(songs[a.0]), duration_ms(songs[b.0]));
+            // past here the gap only grows faster than the allowed difference
+            if longer - shorter > (longer / 20).max(5_000) {
+                break;
+            }
+            compare(&mut parent, a, b);
+        }
+    }
+    for (n, &a) in untimed.iter().enumerate() {
+        for &b in fingerprinted.iter().filter(|b| b.0 != a.0) {
+            // untimed pairs are met twice, once from each side
+            if songs[b.0].properties.is_none() && untimed[..n].iter().any(|earlier| earlier.0 == b.0) {
+                continue;
+            }
+            compare(&mut parent, a, b);
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<&Song>> = HashMap::new();
+    for (i, song) in songs.iter().enumerate() {
+        let group = root(&mut parent, i);
+        groups.entry(group).or_default().push(song);
+    }
+    let mut report: Vec<DuplicateGroup> = groups.into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(group, mut members)| {
+            members.sort_by_key(|song| std::cmp::Reverse(quality(song)));
+            DuplicateGroup {
+                keep: members[0].id.clone(),
+                identical: members.iter().all(|song| song.content_hash == members[0].content_hash),
+                similarity: weakest.get(&group).copied().unwrap_or(1.0),
+                song_ids: members.iter().map(|song| song.id.clone()).collect(),
+            }
+        })
+        .collect();
+    report.sort_by(|a, b| a.keep.cmp(&b.keep));
+    report
+}
+
+/// Songs in the library that sound like `fingerprint`, most similar first; lets peers link
+/// the same recording across nodes
+pub fn find_recording(song_db: &mut SongDb, fingerprint: &[u32]) -> Vec<(String, f32)> {
+    song_db.load_fingerprints();
+    let mut matches: Vec<(String, f32)> = song_db.songs.values().flatten()
+        .filter_map(|song| {
+            let ours = song_db.cached_fingerprint(&song.id)?;
+            let similarity = fingerprint::similarity(fingerprint, ours)?;
+            (similarity >= fingerprint::MATCH_THRESHOLD).then(|| (song.id.clone(), similarity))
+        })
+        .collect();
+    matches.sort_by(|a, b| b.1.total_cmp(&a.1));
+    matches
+}
+
+/// Keeps one song, by default the best quality one, and moves the others to the trash. Tags
+/// the kept song is missing are filled in from the others, as are its lyrics and artwork.
+pub fn merge(song_db: &mut SongDb, song_ids: &[String], keep: Option<&str>) -> anyhow::Result<MergeSummary> {
+    let mut songs: Vec<Song> = Vec::new();
+    for song_id in song_ids {
+        if songs.iter().any(|song| &song.id == song_id) {
+            continue;
+        }
+        let song = song_db.get_song(song_id).ok_or_else(|| anyhow::anyhow!("no song with id {}", song_id))?;
+        songs.push(song.clone());
+    }
+    if songs.len() < 2 {
+        anyhow::bail!("merging needs at least two songs");
+    }
+    let kept = match keep {
+        Some(keep) if songs.iter().any(|song| song.id == keep) => keep.to_string(),
+        Some(keep) => anyhow::bail!("{} isn't one of the songs being merged", keep),
+        None => songs.iter().max_by_key(|song| quality(song)).map(|song| song.id.clone()).unwrap_or_default(),
+    };
+    let others: Vec<Song> = songs.into_iter().filter(|song| song.id != kept).collect();
+
+    if let Some(keeper) = song_db.songs.values_mut().flatten().find(|song| song.id == kept) {
+        for other in &others {
+            keeper.metadata.fill_missing(other.metadata.clone());
+            keeper.lyrics = keeper.lyrics.take().or_else(|| other.lyrics.clone());
+            keeper.bpm = keeper.bpm.or(other.bpm);
+            keeper.key = keeper.key.or(other.key);
+            keeper.play_count += other.play_count;
+            keeper.last_played = keeper.last_played.max(other.last_played);
+            // artwork files are only removed once nothing points at them, so sharing is fine
+            keeper.artwork = keeper.artwork.take().or_else(|| other.artwork.clone());
+        }
+        let tag_key = keeper.tag.key.clone();
+        song_db.update_replay_gain(&tag_key);
+    }
+    let mut merged = Vec::new();
+    for other in others {
+        song_db.delete_song(&other.id)?;
+        merged.push(other.id);
+    }
+    song_db.save();
+    Ok(MergeSummary { kept, merged })
+}
+
+/// Lossless beats lossy, then the higher bitrate and sample rate, then the bigger file
+fn quality(song: &Song) -> (bool, u32, u32, u64) {
+    let lossless = matches!(song.format, AudioFormat::Flac | AudioFormat::Wav);
+    let (bitrate, sample_rate) = song.properties.as_ref()
+        .map(|properties| (properties.bitrate_kbps, properties.sample_rate))
+        .unwrap_or_default();
+    (lossless, bitrate, sample_rate, song.size)
+}
+
+fn duration_ms(song: &Song) -> u64 {
+    song.properties.as_ref().map_or(0, |properties| properties.duration_ms)
+}
+
+/// Re-encodes keep their length; a different edit of the song is a different song
+fn similar_length(a: &Song, b: &Song) -> bool {
+    match (&a.properties, &b.properties) {
+        (Some(a), Some(b)) => a.duration_ms.abs_diff(b.duration_ms) <= (a.duration_ms / 20).max(5_000),
+        _ => true,
+    }
+}
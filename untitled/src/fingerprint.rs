@@ -0,0 +1,125 @@
+// Chromaprint-style acoustic fingerprints: the same recording gives close to the same bits
+// whatever it was encoded with. The start of the song is resampled to 11025 Hz mono and folded
+// into 12 pitch classes per overlapping frame; each frame becomes a u32 of how the pitch classes
+// compare to each other and to the frames before. https://oxygene.sk/2011/01/how-does-chromaprint-work/
+//
+// Sidecar: "FPRT", a version byte, the item count as u32 LE, then the items as u32 LE.
+
+use crate::dsp::{self, Spectrogram};
+use crate::metadata::AudioFormat;
+
+const SAMPLE_RATE: u32 = 11_025;
+const FRAME_LEN: usize = 4096;
+const HOP: usize = FRAME_LEN / 3;
+/// Enough to tell recordings apart without decoding hour-long mixes
+const MAX_SECS: u32 = 120;
+const MIN_FREQ: f64 = 28.0;
+const MAX_FREQ: f64 = 3520.0;
+/// Frames a bit's "before" is compared against, and averaged over to ride out noise
+const SMOOTHING: usize = 4;
+
+/// Items per second of audio
+pub const ITEMS_PER_SEC: f64 = SAMPLE_RATE as f64 / HOP as f64;
+/// Items two fingerprints may be shifted by, e.g. a few seconds of extra silence up front
+const MAX_OFFSET: usize = 80;
+/// Overlap needed before a comparison means anything, about 10 s
+const MIN_OVERLAP: usize = 80;
+/// Unrelated songs come out around 0.5, re-encodes of the same one well above this
+pub const MATCH_THRESHOLD: f32 = 0.8;
+
+const MAGIC: &[u8; 4] = b"FPRT";
+const VERSION: u8 = 1;
+
+pub fn compute(format: AudioFormat, data: Vec<u8>) -> anyhow::Result<Vec<u32>> {
+    let fingerprint = from_samples(&dsp::decode_mono(format, data, SAMPLE_RATE, MAX_SECS)?);
+    if fingerprint.len() < MIN_OVERLAP {
+        anyhow::bail!("Song is too short to fingerprint");
+    }
+    Ok(fingerprint)
+}
+
+/// Fingerprint of 11025 Hz mono samples
+pub fn from_samples(samples: &[f32]) -> Vec<u32> {
+    let pitch_class = dsp::pitch_classes(FRAME_LEN, SAMPLE_RATE, MIN_FREQ, MAX_FREQ);
+    let mut chroma: Vec<[f64; 12]> = Vec::new();
+    Spectrogram::new(FRAME_LEN).for_each_frame(samples, HOP, |power| {
+        let mut frame = [0.0; 12];
+        for (bin, class) in pitch_class.iter().enumerate() {
+            if let Some(class) = class {
+                frame[*class] += power[bin];
+            }
+        }
+        let norm = frame.iter().map(|energy| energy * energy).sum::<f64>().sqrt();
+        if norm > 1e-9 {
+            frame.iter_mut().for_each(|energy| *energy /= norm);
+        }
+        chroma.push(frame);
+    });
+
+    // moving average over SMOOTHING frames
+    let smoothed: Vec<[f64; 12]> = chroma.windows(SMOOTHING)
+        .map(|frames| {
+            let mut average = [0.0; 12];
+            for frame in frames {
+                average.iter_mut().zip(frame).for_each(|(average, energy)| *average += energy);
+            }
+            average
+        })
+        .collect();
+
+    smoothed.iter().enumerate().skip(SMOOTHING)
+        .map(|(t, frame)| {
+            let before = &smoothed[t - SMOOTHING];
+            let mut item = 0u32;
+            for class in 0..12 {
+                item |= ((frame[class] > before[class]) as u32) << class;
+                item |= ((frame[class] > frame[(class + 1) % 12]) as u32) << (12 + class);
+            }
+            for class in 0..8 {
+                item |= ((frame[class] > frame[(class + 4) % 12]) as u32) << (24 + class);
+            }
+            item
+        })
+        .collect()
+}
+
+/// 1.0 for identical fingerprints, about 0.5 for unrelated ones: the share of matching bits at
+/// the best alignment. None when they barely overlap.
+pub fn similarity(a: &[u32], b: &[u32]) -> Option<f32> {
+    let mut best: Option<f32> = None;
+    for shift in -(MAX_OFFSET as isize)..=MAX_OFFSET as isize {
+        let (a, b) = if shift >= 0 {
+            (a.get(shift as usize..).unwrap_or_default(), b)
+        } else {
+            (a, b.get(shift.unsigned_abs()..).unwrap_or_default())
+        };
+        let overlap = a.len().min(b.len());
+        if overlap < MIN_OVERLAP {
+            continue;
+        }
+        let differing: u32 = a.iter().zip(b).map(|(a, b)| (a ^ b).count_ones()).sum();
+        let score = 1.0 - differing as f32 / (overlap * 32) as f32;
+        best = Some(best.map_or(score, |best| best.max(score)));
+    }
+    best
+}
+
+pub fn encode(fingerprint: &[u32]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(9 + fingerprint.len() * 4);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&(fingerprint.len() as u32).to_le_bytes());
+    for item in fingerprint {
+        bytes.extend_from_slice(&item.to_le_bytes());
+    }
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> Option<Vec<u32>> {
+    if bytes.get(0..4)? != MAGIC || *bytes.get(4)? != VERSION {
+        return None;
+    }
+    let count = u32::from_le_bytes(bytes.get(5..9)?.try_into().ok()?) as usize;
+    let body = bytes.get(9..9 + count.checked_mul(4)?)?;
+    Some(body.chunks_exact(4).map(|item| u32::from_le_bytes([item[0], item[1], item[2], item[3]])).collect())
+}
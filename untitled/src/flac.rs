@@ -0,0 +1,209 @@
+// FLAC metadata blocks. https://xiph.org/flac/format.html#metadata_block
+
+use crate::id3;
+use crate::metadata::{self, AudioProperties, BitrateMode, Picture, SongMetadata};
+use crate::vorbis_comment::VorbisComments;
+
+pub const STREAMINFO: u8 = 0;
+pub const VORBIS_COMMENT: u8 = 4;
+pub const PICTURE: u8 = 6;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Block<'a> {
+    pub block_type: u8,
+    pub data: &'a [u8],
+}
+
+/// Metadata blocks in file order, plus the offset where the audio frames start
+pub fn blocks(data: &[u8]) -> Option<(Vec<Block<'_>>, usize)> {
+    // a few taggers put an ID3v2 tag in front of the stream marker
+    let start = id3::v2_tag_len(data);
+    if data.get(start..start + 4)? != b"fLaC" {
+        return None;
+    }
+
+    let mut pos = start + 4;
+    let mut blocks = Vec::new();
+    loop {
+        let header = data.get(pos..pos + 4)?;
+        let last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let len = ((header[1] as usize) << 16) | ((header[2] as usize) << 8) | header[3] as usize;
+        pos += 4;
+        blocks.push(Block { block_type, data: data.get(pos..pos + len)? });
+        pos += len;
+        if last {
+            return Some((blocks, pos));
+        }
+    }
+}
+
+/// Where an audio frame starts and the number of its first sample
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStart {
+    pub offset: usize,
+    pub sample: u64,
+}
+
+/// Audio frames, found by their sync code and kept when the header's CRC-8 checks out and the
+/// sample numbers keep going up. https://xiph.org/flac/format.html#frame_header
+pub fn frame_starts(data: &[u8]) -> Vec<FrameStart> {
+    let mut starts: Vec<FrameStart> = Vec::new();
+    let Some((blocks, audio_start)) = blocks(data) else {
+        return starts;
+    };
+    let Some(info) = blocks.first().filter(|block| block.block_type == STREAMINFO && block.data.len() == 34) else {
+        return starts;
+    };
+    // fixed-blocksize streams number frames rather than samples
+    let block_size = u16::from_be_bytes([info.data[2], info.data[3]]) as u64;
+
+    let mut pos = audio_start;
+    while pos + 2 <= data.len() {
+        if data[pos] == 0xFF && data[pos + 1] & 0xFE == 0xF8 {
+            let sample = frame_first_sample(&data[pos..], block_size)
+                .filter(|&sample| starts.last().is_none_or(|last| sample > last.sample));
+            if let Some(sample) = sample {
+                starts.push(FrameStart { offset: pos, sample });
+            }
+        }
+        pos += 1;
+    }
+    starts
+}
+
+fn frame_first_sample(header: &[u8], block_size: u64) -> Option<u64> {
+    let variable_block_size = header[1] & 0x01 != 0;
+    let block_size_code = *header.get(2)? >> 4;
+    let sample_rate_code = header[2] & 0x0F;
+    let channels_etc = *header.get(3)?;
+    if block_size_code == 0 || sample_rate_code == 15 || channels_etc >> 4 > 10 || channels_etc & 0x01 != 0 {
+        return None;
+    }
+    let (number, number_len) = coded_number(header.get(4..)?)?;
+    let mut crc_at = 4 + number_len;
+    crc_at += match block_size_code {
+        6 => 1,
+        7 => 2,
+        _ => 0,
+    };
+    crc_at += match sample_rate_code {
+        12 => 1,
+        13 | 14 => 2,
+        _ => 0,
+    };
+    if crc8(header.get(..crc_at)?) != *header.get(crc_at)? {
+        return None;
+    }
+    Some(if variable_block_size { number } else { number * block_size })
+}
+
+/// The frame or sample number, coded like UTF-8 but up to 36 bits; (value, bytes used)
+fn coded_number(bytes: &[u8]) -> Option<(u64, usize)> {
+    let first = *bytes.first()?;
+    let len = first.leading_ones() as usize;
+    if len == 0 {
+        return Some((first as u64, 1));
+    }
+    if len == 1 || len > 7 {
+        return None;
+    }
+    let mut value = (first & (0x7F >> len)) as u64;
+    for &byte in bytes.get(1..len)? {
+        if byte & 0xC0 != 0x80 {
+            return None;
+        }
+        value = (value << 6) | (byte & 0x3F) as u64;
+    }
+    Some((value, len))
+}
+
+/// CRC-8, polynomial 0x07
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn block(data: &[u8], block_type: u8) -> Option<Block<'_>> {
+    blocks(data)?.0.into_iter().find(|block| block.block_type == block_type)
+}
+
+/// STREAMINFO must come first, and the audio must start with a frame sync code
+pub fn validate(data: &[u8]) -> Result<(), String> {
+    let (blocks, audio_offset) = blocks(data).ok_or("FLAC metadata blocks are truncated")?;
+    match blocks.first() {
+        Some(block) if block.block_type == STREAMINFO && block.data.len() == 34 => {}
+        _ => return Err("FLAC stream doesn't start with a STREAMINFO block".to_string()),
+    }
+    match data.get(audio_offset..audio_offset + 2) {
+        Some([0xFF, second]) if second & 0xFE == 0xF8 => Ok(()),
+        _ => Err("no FLAC frame after the metadata blocks".to_string()),
+    }
+}
+
+pub fn read_comments(data: &[u8]) -> Option<VorbisComments> {
+    VorbisComments::parse(block(data, VORBIS_COMMENT)?.data)
+}
+
+/// PICTURE block body, also used base64-encoded in Ogg's METADATA_BLOCK_PICTURE comment.
+/// Returns the picture type along with the image.
+pub fn parse_picture_block(data: &[u8]) -> Option<(u32, Picture)> {
+    let be_u32 = |pos: usize| data.get(pos..pos.checked_add(4)?).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    let picture_type = be_u32(0)?;
+    // lengths are read from the file, so adding them up can overflow usize on wasm32
+    let mime_end = 8usize.checked_add(be_u32(4)? as usize)?;
+    let mime = String::from_utf8_lossy(data.get(8..mime_end)?).into_owned();
+    let desc_len = be_u32(mime_end)? as usize;
+    // skip width, height, color depth and palette size
+    let data_len_at = (mime_end + 4).checked_add(desc_len)?.checked_add(16)?;
+    let data_len = be_u32(data_len_at)? as usize;
+    let image = data.get(data_len_at + 4..(data_len_at + 4).checked_add(data_len)?)?;
+    Some((picture_type, Picture::new(&mime, image)?))
+}
+
+pub fn read_picture(data: &[u8]) -> Option<Picture> {
+    let (blocks, _) = blocks(data)?;
+    let pictures = blocks.iter()
+        .filter(|block| block.block_type == PICTURE)
+        .filter_map(|block| parse_picture_block(block.data));
+    metadata::pick_cover(pictures)
+}
+
+pub fn read_metadata(data: &[u8]) -> SongMetadata {
+    let mut metadata = read_comments(data).map(|comments| comments.to_metadata()).unwrap_or_default();
+    metadata.fill_missing(id3::read_metadata(data));
+    metadata
+}
+
+pub fn read_properties(data: &[u8]) -> Option<AudioProperties> {
+    let (blocks, audio_offset) = blocks(data)?;
+    let info = blocks.iter().find(|block| block.block_type == STREAMINFO)?.data;
+    if info.len() < 18 {
+        return None;
+    }
+
+    let sample_rate = ((info[10] as u32) << 12) | ((info[11] as u32) << 4) | (info[12] as u32 >> 4);
+    let channels = ((info[12] >> 1) & 0x07) + 1;
+    let total_samples = (((info[13] & 0x0F) as u64) << 32)
+        | u32::from_be_bytes([info[14], info[15], info[16], info[17]]) as u64;
+    if sample_rate == 0 {
+        return None;
+    }
+
+    let duration_ms = total_samples * 1000 / sample_rate as u64;
+    let audio_bytes = data.len().saturating_sub(audio_offset) as u64;
+    Some(AudioProperties {
+        duration_ms,
+        bitrate_kbps: if duration_ms > 0 { (audio_bytes * 8 / duration_ms) as u32 } else { 0 },
+        bitrate_mode: BitrateMode::Vbr,
+        sample_rate,
+        channels,
+        channel_mode: None,
+    })
+}
@@ -0,0 +1,114 @@
+// Gapless MP3 variant: whole frames of encoder delay and padding are dropped so that songs
+// meant to run into each other don't pause at the joins. What's left over (less than a frame,
+// which with LAME's usual 1105 samples of delay is always something) is written back into the
+// LAME tag, and reported alongside the stream so players can trim it to the sample.
+
+use crate::metadata::Gapless;
+use crate::mp3::{self, LameTag};
+
+pub struct Trim {
+    /// the stream with whole frames dropped, None when not a single frame could be
+    pub data: Option<Vec<u8>>,
+    /// samples still to skip at the start and end of the stream that is served
+    pub delay: u32,
+    pub padding: u32,
+    /// samples in the stream that is served, delay and padding included
+    pub samples: u64,
+    pub sample_rate: u32,
+}
+
+impl Trim {
+    /// Where the music starts and ends in the served stream
+    pub fn start_ms(&self) -> u64 {
+        self.delay as u64 * 1000 / self.sample_rate as u64
+    }
+
+    pub fn end_ms(&self) -> u64 {
+        self.samples.saturating_sub(self.padding as u64) * 1000 / self.sample_rate as u64
+    }
+}
+
+/// None when the stream has no frames to go by
+pub fn trim_mp3(data: &[u8], gapless: &Gapless) -> Option<Trim> {
+    let mut frames: Vec<mp3::Frame> = mp3::frames(data).collect();
+    let first = *frames.first()?;
+    let vbr_header = mp3::read_vbr_header(data, &first);
+    if vbr_header.is_some() {
+        frames.remove(0);
+    }
+
+    let samples = frames.first()?.header.samples;
+    let sample_rate = first.header.sample_rate;
+    if samples == 0 || sample_rate == 0 {
+        return None;
+    }
+    let drop_front = (gapless.encoder_delay / samples) as usize;
+    let drop_back = (gapless.padding / samples) as usize;
+    if drop_front + drop_back == 0 || drop_front + drop_back >= frames.len() {
+        return Some(Trim {
+            data: None,
+            delay: gapless.encoder_delay,
+            padding: gapless.padding,
+            samples: frames.len() as u64 * samples as u64,
+            sample_rate,
+        });
+    }
+    let delay = gapless.encoder_delay - drop_front as u32 * samples;
+    let padding = gapless.padding - drop_back as u32 * samples;
+    let kept = &frames[drop_front..frames.len() - drop_back];
+    let audio_start = kept.first()?.offset;
+    let last = kept.last()?;
+    let audio_end = (last.offset + last.header.frame_len).min(data.len());
+
+    // the ID3 tag is left out: an iTunSMPB comment in it would still describe the untrimmed stream
+    let mut trimmed = Vec::with_capacity(first.header.frame_len + audio_end - audio_start);
+    if let Some(header) = vbr_header {
+        let mut info = data.get(first.offset..first.offset + first.header.frame_len)?.to_vec();
+        // not every encoder's tag CRC follows the spec; only keep one up to date that did
+        let crc_valid = header.lame.is_some_and(|lame| {
+            let at = lame.offset + LameTag::TAG_CRC_AT;
+            info.get(at..at + 2) == Some(&crc16(&info[..at]).to_be_bytes()[..])
+        });
+        let total_bytes = (info.len() + audio_end - audio_start) as u32;
+        if let Some(at) = header.frames_at {
+            info[at..at + 4].copy_from_slice(&(kept.len() as u32).to_be_bytes());
+        }
+        if let Some(at) = header.bytes_at {
+            info[at..at + 4].copy_from_slice(&total_bytes.to_be_bytes());
+        }
+        if let Some(lame) = header.lame {
+            let at = lame.offset + LameTag::DELAY_AT;
+            info[at] = (delay >> 4) as u8;
+            info[at + 1] = ((delay & 0x0F) << 4) as u8 | (padding >> 8) as u8;
+            info[at + 2] = padding as u8;
+            let at = lame.offset + LameTag::MUSIC_LENGTH_AT;
+            info[at..at + 4].copy_from_slice(&total_bytes.to_be_bytes());
+            if crc_valid {
+                let at = lame.offset + LameTag::TAG_CRC_AT;
+                let crc = crc16(&info[..at]);
+                info[at..at + 2].copy_from_slice(&crc.to_be_bytes());
+            }
+        }
+        trimmed.extend_from_slice(&info);
+    }
+    trimmed.extend_from_slice(&data[audio_start..audio_end]);
+    Some(Trim {
+        data: Some(trimmed),
+        delay,
+        padding,
+        samples: kept.len() as u64 * samples as u64,
+        sample_rate,
+    })
+}
+
+/// CRC-16/ARC, which the LAME tag uses
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
@@ -0,0 +1,489 @@
+// Minimal ID3v1 / ID3v2.2-2.4 reader, just enough to pull song metadata out of uploads.
+// https://id3.org/id3v2.3.0 and https://id3.org/id3v2.4.0-structure
+
+use crate::chapters::ChapterMark;
+use crate::key::Key;
+use crate::lyrics::{LyricLine, Lyrics};
+use crate::metadata::{self, Gapless, Picture, ReplayGain, SongMetadata};
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// four character id; v2.2 ids are mapped to their v2.3 equivalents where one exists
+    pub id: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Id3v2 {
+    /// major version, 2 to 4
+    pub version: u8,
+    pub frames: Vec<Frame>,
+}
+
+impl Id3v2 {
+    pub fn frame(&self, id: &str) -> Option<&Frame> {
+        self.frames.iter().find(|frame| frame.id == id)
+    }
+
+    /// First value of a text frame (T***)
+    pub fn text(&self, id: &str) -> Option<String> {
+        let frame = self.frame(id)?;
+        let (&encoding, text) = frame.data.split_first()?;
+        decode_text(encoding, text)
+            .split('\0')
+            .map(str::trim)
+            .find(|value| !value.is_empty())
+            .map(str::to_string)
+    }
+
+    /// (description, text) of every COMM frame
+    pub fn comments(&self) -> Vec<(String, String)> {
+        self.frames.iter()
+            .filter(|frame| frame.id == "COMM")
+            .filter_map(|frame| {
+                // encoding, 3 letter language, description, text
+                let (&encoding, rest) = frame.data.split_first()?;
+                let (description, text) = split_terminated(encoding, rest.get(3..)?)?;
+                let text = decode_text(encoding, text).trim_end_matches('\0').to_string();
+                Some((description, text))
+            })
+            .collect()
+    }
+
+    /// (description, value) of every TXXX frame
+    pub fn user_texts(&self) -> Vec<(String, String)> {
+        self.frames.iter()
+            .filter(|frame| frame.id == "TXXX")
+            .filter_map(|frame| {
+                let (&encoding, rest) = frame.data.split_first()?;
+                let (description, value) = split_terminated(encoding, rest)?;
+                let value = decode_text(encoding, value).trim_end_matches('\0').trim().to_string();
+                Some((description, value))
+            })
+            .collect()
+    }
+}
+
+/// Length of the ID3v2 tag at the start of `data` (header, body and footer), 0 if there is none
+pub fn v2_tag_len(data: &[u8]) -> usize {
+    if data.len() < 10 || &data[0..3] != b"ID3" {
+        return 0;
+    }
+    let size = syncsafe(&data[6..10]) as usize;
+    let footer = if data[3] == 4 && data[5] & 0x10 != 0 { 10 } else { 0 };
+    (10 + size + footer).min(data.len())
+}
+
+pub fn read_v2(data: &[u8]) -> Option<Id3v2> {
+    let tag_len = v2_tag_len(data);
+    if tag_len == 0 {
+        return None;
+    }
+    let major_version = data[3];
+    let flags = data[5];
+    if !(2..=4).contains(&major_version) {
+        return None;
+    }
+
+    let body_end = (10 + syncsafe(&data[6..10]) as usize).min(data.len());
+    let mut body = data[10..body_end].to_vec();
+    // v2.4 unsynchronises frame by frame instead
+    if flags & 0x80 != 0 && major_version < 4 {
+        body = remove_unsync(&body);
+    }
+
+    let mut pos = 0;
+    if flags & 0x40 != 0 && major_version >= 3 && body.len() >= 4 {
+        pos = match major_version {
+            3 => 4 + be_u32(&body[0..4]) as usize,
+            _ => syncsafe(&body[0..4]) as usize,
+        };
+    }
+
+    let frames = parse_frames(body.get(pos..).unwrap_or_default(), major_version);
+    Some(Id3v2 { version: major_version, frames })
+}
+
+/// Frames laid out back to back, as in the tag body or embedded in CHAP/CTOC frames
+fn parse_frames(body: &[u8], major_version: u8) -> Vec<Frame> {
+    let (id_len, header_len) = if major_version == 2 { (3, 6) } else { (4, 10) };
+    let mut frames = Vec::new();
+    let mut pos = 0;
+
+    while pos + header_len <= body.len() {
+        let header = &body[pos..pos + header_len];
+        if header[0] == 0 {
+            break; // padding
+        }
+        let id = String::from_utf8_lossy(&header[..id_len]).into_owned();
+        let size = match major_version {
+            2 => ((header[3] as usize) << 16) | ((header[4] as usize) << 8) | header[5] as usize,
+            3 => be_u32(&header[4..8]) as usize,
+            _ => syncsafe(&header[4..8]) as usize,
+        };
+        let format_flags = if major_version == 2 { 0 } else { header[9] };
+        pos += header_len;
+        let Some(mut frame_data) = pos.checked_add(size).and_then(|end| body.get(pos..end)) else {
+            break;
+        };
+        pos += size;
+
+        let (compressed, encrypted, grouped, unsync, data_len_indicator) = match major_version {
+            2 => (false, false, false, false, false),
+            3 => (format_flags & 0x80 != 0, format_flags & 0x40 != 0, format_flags & 0x20 != 0, false, false),
+            _ => (
+                format_flags & 0x08 != 0,
+                format_flags & 0x04 != 0,
+                format_flags & 0x40 != 0,
+                format_flags & 0x02 != 0,
+                format_flags & 0x01 != 0,
+            ),
+        };
+        if compressed || encrypted {
+            continue;
+        }
+        if grouped && !frame_data.is_empty() {
+            frame_data = &frame_data[1..];
+        }
+        if data_len_indicator && frame_data.len() >= 4 {
+            frame_data = &frame_data[4..];
+        }
+        let frame_data = if unsync { remove_unsync(frame_data) } else { frame_data.to_vec() };
+
+        let id = if major_version == 2 { v22_to_v23(&id).unwrap_or(&id).to_string() } else { id };
+        frames.push(Frame { id, data: frame_data });
+    }
+    frames
+}
+
+/// Reads the ID3v1 tag from the last 128 bytes of the file
+pub fn read_v1(data: &[u8]) -> Option<SongMetadata> {
+    if data.len() < 128 {
+        return None;
+    }
+    let tag = &data[data.len() - 128..];
+    if &tag[0..3] != b"TAG" {
+        return None;
+    }
+
+    let field = |bytes: &[u8]| {
+        let text = latin1(bytes);
+        let text = text.trim_end_matches('\0').trim();
+        (!text.is_empty()).then(|| text.to_string())
+    };
+    let comment = &tag[97..127];
+    // ID3v1.1 puts the track number in the last byte of the comment
+    let track = (comment[28] == 0 && comment[29] != 0).then(|| comment[29] as u32);
+
+    Some(SongMetadata {
+        title: field(&tag[3..33]),
+        artist: field(&tag[33..63]),
+        album: field(&tag[63..93]),
+        year: field(&tag[93..97]).and_then(|year| parse_year(&year)),
+        track,
+        genre: GENRES.get(tag[127] as usize).map(|genre| genre.to_string()),
+        ..Default::default()
+    })
+}
+
+/// ID3v2 values win, ID3v1 fills whatever is missing
+pub fn read_metadata(data: &[u8]) -> SongMetadata {
+    let mut metadata = SongMetadata::default();
+
+    if let Some(tag) = read_v2(data) {
+        metadata.title = tag.text("TIT2");
+        metadata.artist = tag.text("TPE1");
+        metadata.album_artist = tag.text("TPE2");
+        metadata.album = tag.text("TALB");
+        metadata.track = tag.text("TRCK").and_then(|track| parse_number(&track));
+        metadata.year = tag.text("TDRC")
+            .or_else(|| tag.text("TYER"))
+            .and_then(|year| parse_year(&year));
+        metadata.genre = tag.text("TCON").and_then(|genre| parse_genre(&genre));
+        let user_texts = tag.user_texts();
+        metadata.replay_gain = ReplayGain::from_fields(user_texts.iter().map(|(key, value)| (key.as_str(), value.as_str())));
+        metadata.gapless = tag.comments().into_iter()
+            .find(|(description, _)| description == "iTunSMPB")
+            .and_then(|(_, value)| Gapless::from_itunsmpb(&value));
+        metadata.bpm = tag.text("TBPM").and_then(|bpm| SongMetadata::parse_bpm(&bpm));
+        metadata.key = tag.text("TKEY").and_then(|key| Key::parse(&key));
+    }
+
+    if let Some(v1) = read_v1(data) {
+        metadata.fill_missing(v1);
+    }
+
+    metadata
+}
+
+/// Cover art from APIC frames (PIC in v2.2), preferring the front cover
+pub fn read_picture(data: &[u8]) -> Option<Picture> {
+    let tag = read_v2(data)?;
+    let pictures = tag.frames.iter().filter_map(|frame| match frame.id.as_str() {
+        "APIC" => parse_apic(&frame.data),
+        "PIC" => parse_pic(&frame.data),
+        _ => None,
+    });
+    metadata::pick_cover(pictures)
+}
+
+/// CHAP frames, in the order of the top level CTOC when there is one.
+/// https://id3.org/id3v2-chapters-1.0
+pub fn read_chapters(data: &[u8]) -> Vec<ChapterMark> {
+    let Some(tag) = read_v2(data) else {
+        return Vec::new();
+    };
+    let chapters: Vec<(String, ChapterMark)> = tag.frames.iter()
+        .filter(|frame| frame.id == "CHAP")
+        .filter_map(|frame| parse_chap(&frame.data, tag.version))
+        .collect();
+
+    let top_level = tag.frames.iter()
+        .filter(|frame| frame.id == "CTOC")
+        .filter_map(|frame| parse_ctoc(&frame.data))
+        .find(|(top_level, _)| *top_level);
+    match top_level {
+        Some((_, children)) => children.iter()
+            .filter_map(|child| chapters.iter().find(|(id, _)| id == child))
+            .map(|(_, mark)| mark.clone())
+            .collect(),
+        None => chapters.into_iter().map(|(_, mark)| mark).collect(),
+    }
+}
+
+/// element id, start and end in ms, start and end byte offsets, then frames describing it
+fn parse_chap(data: &[u8], version: u8) -> Option<(String, ChapterMark)> {
+    let (element_id, rest) = split_terminated(0, data)?;
+    let times = rest.get(..16)?;
+    let sub_frames = Id3v2 { version, frames: parse_frames(&rest[16..], version) };
+    let end_ms = be_u32(&times[4..8]);
+    Some((element_id, ChapterMark {
+        title: sub_frames.text("TIT2"),
+        performer: sub_frames.text("TPE1"),
+        start_ms: be_u32(&times[0..4]) as u64,
+        end_ms: (end_ms != u32::MAX).then_some(end_ms as u64),
+    }))
+}
+
+/// element id, flags, entry count, child element ids; returns (is top level, children)
+fn parse_ctoc(data: &[u8]) -> Option<(bool, Vec<String>)> {
+    let (_, rest) = split_terminated(0, data)?;
+    let (&flags, rest) = rest.split_first()?;
+    let (&count, mut rest) = rest.split_first()?;
+    let mut children = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let (child, after) = split_terminated(0, rest)?;
+        children.push(child);
+        rest = after;
+    }
+    Some((flags & 0x02 != 0, children))
+}
+
+/// Synced lyrics from SYLT if there are any, otherwise USLT (SLT/ULT in v2.2)
+pub fn read_lyrics(data: &[u8]) -> Option<Lyrics> {
+    let tag = read_v2(data)?;
+    let frames = |id: &'static str| tag.frames.iter().filter(move |frame| frame.id == id);
+    frames("SYLT").find_map(|frame| parse_sylt(&frame.data))
+        .or_else(|| frames("USLT").find_map(|frame| parse_uslt(&frame.data)))
+}
+
+/// encoding, 3 letter language, description, text; the text is sometimes LRC itself
+fn parse_uslt(data: &[u8]) -> Option<Lyrics> {
+    let (&encoding, rest) = data.split_first()?;
+    let (_, text) = split_terminated(encoding, rest.get(3..)?)?;
+    Lyrics::parse(decode_text(encoding, text).trim_end_matches('\0'))
+}
+
+/// encoding, 3 letter language, timestamp format, content type, description, then
+/// (terminated text, u32 time) pairs. Entries can be whole lines or single syllables, in which
+/// case a new line starts with "\n".
+fn parse_sylt(data: &[u8]) -> Option<Lyrics> {
+    let (&encoding, rest) = data.split_first()?;
+    // times in MPEG frames (format 1) would need the frame length; milliseconds is what's used
+    if *rest.get(3)? != 2 {
+        return None;
+    }
+    let (_, mut rest) = split_terminated(encoding, rest.get(5..)?)?;
+    let mut entries = Vec::new();
+    while let Some((text, after)) = split_terminated(encoding, rest) {
+        let Some(time) = after.get(..4) else {
+            break;
+        };
+        entries.push((be_u32(time), text));
+        rest = &after[4..];
+    }
+
+    let syllables = entries.iter().any(|(_, text)| text.starts_with(['\n', '\r']));
+    let mut lines: Vec<LyricLine> = Vec::new();
+    for (time, text) in entries {
+        match lines.last_mut() {
+            Some(line) if syllables && !text.starts_with(['\n', '\r']) => line.text.push_str(&text),
+            _ => lines.push(LyricLine { time_ms: Some(time), text }),
+        }
+    }
+    lines.iter_mut().for_each(|line| line.text = line.text.trim().to_string());
+    lines.retain(|line| !line.text.is_empty());
+    lines.sort_by_key(|line| line.time_ms);
+    (!lines.is_empty()).then_some(Lyrics { synced: true, lines })
+}
+
+/// encoding, MIME type, picture type, description, image
+fn parse_apic(data: &[u8]) -> Option<(u32, Picture)> {
+    let (&encoding, rest) = data.split_first()?;
+    let mime_end = rest.iter().position(|&b| b == 0)?;
+    let mime = latin1(&rest[..mime_end]);
+    let (&picture_type, rest) = rest[mime_end + 1..].split_first()?;
+    let (_, image) = split_terminated(encoding, rest)?;
+    Some((picture_type as u32, Picture::new(&mime, image)?))
+}
+
+/// v2.2 has a three letter image format instead of a MIME type
+fn parse_pic(data: &[u8]) -> Option<(u32, Picture)> {
+    let (&encoding, rest) = data.split_first()?;
+    let image_format = latin1(rest.get(..3)?);
+    let (&picture_type, rest) = rest.get(3..)?.split_first()?;
+    let (_, image) = split_terminated(encoding, rest)?;
+    Some((picture_type as u32, Picture::new(&image_format, image)?))
+}
+
+/// Splits off a NUL-terminated string in the given text encoding, returns it and what follows
+pub fn split_terminated(encoding: u8, bytes: &[u8]) -> Option<(String, &[u8])> {
+    if encoding == 1 || encoding == 2 {
+        let end = (0..bytes.len().saturating_sub(1)).step_by(2).find(|&i| bytes[i] == 0 && bytes[i + 1] == 0)?;
+        Some((decode_text(encoding, &bytes[..end]), &bytes[end + 2..]))
+    } else {
+        let end = bytes.iter().position(|&b| b == 0)?;
+        Some((decode_text(encoding, &bytes[..end]), &bytes[end + 1..]))
+    }
+}
+
+pub fn decode_text(encoding: u8, bytes: &[u8]) -> String {
+    match encoding {
+        1 => utf16(bytes, None),
+        2 => utf16(bytes, Some(false)),
+        3 => String::from_utf8_lossy(bytes).into_owned(),
+        _ => latin1(bytes),
+    }
+}
+
+/// `little_endian` None means a BOM decides (big endian if it's missing)
+fn utf16(bytes: &[u8], little_endian: Option<bool>) -> String {
+    let (little_endian, bytes) = match (little_endian, bytes) {
+        (None, [0xFF, 0xFE, rest @ ..]) => (true, rest),
+        (None, [0xFE, 0xFF, rest @ ..]) => (false, rest),
+        (le, bytes) => (le.unwrap_or(false), bytes),
+    };
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| if little_endian { u16::from_le_bytes([pair[0], pair[1]]) } else { u16::from_be_bytes([pair[0], pair[1]]) })
+        .collect();
+    // multi-value frames repeat the BOM after each separator
+    String::from_utf16_lossy(&units).replace('\u{feff}', "")
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// "3/12" -> 3
+fn parse_number(text: &str) -> Option<u32> {
+    text.split('/').next()?.trim().parse().ok()
+}
+
+/// "2001", "2001-05-02T12:00" -> 2001
+fn parse_year(text: &str) -> Option<i32> {
+    let digits: String = text.trim().chars().take_while(|c| c.is_ascii_digit()).collect();
+    (digits.len() == 4).then(|| digits.parse().ok()).flatten()
+}
+
+/// TCON is free text, but may also reference ID3v1 genres: "(17)", "(17)Rock", "17", "(RX)"
+fn parse_genre(text: &str) -> Option<String> {
+    let text = text.trim();
+    if let Some(rest) = text.strip_prefix('(') {
+        if let Some((reference, refinement)) = rest.split_once(')') {
+            if !refinement.trim().is_empty() {
+                return Some(refinement.trim().to_string());
+            }
+            return match reference {
+                "RX" => Some("Remix".to_string()),
+                "CR" => Some("Cover".to_string()),
+                n => n.parse::<usize>().ok().and_then(|n| GENRES.get(n)).map(|g| g.to_string()),
+            };
+        }
+    }
+    if let Ok(n) = text.parse::<usize>() {
+        return GENRES.get(n).map(|g| g.to_string());
+    }
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn v22_to_v23(id: &str) -> Option<&'static str> {
+    Some(match id {
+        "TT2" => "TIT2",
+        "TP1" => "TPE1",
+        "TP2" => "TPE2",
+        "TAL" => "TALB",
+        "TRK" => "TRCK",
+        "TYE" => "TYER",
+        "TCO" => "TCON",
+        "TLE" => "TLEN",
+        "TBP" => "TBPM",
+        "TKE" => "TKEY",
+        "TXX" => "TXXX",
+        "COM" => "COMM",
+        "ULT" => "USLT",
+        "SLT" => "SYLT",
+        _ => return None,
+    })
+}
+
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |acc, &b| (acc << 7) | (b & 0x7F) as u32)
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Drops the 0x00 that unsynchronisation inserts after every 0xFF
+fn remove_unsync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut prev_ff = false;
+    for &b in data {
+        if !(prev_ff && b == 0x00) {
+            out.push(b);
+        }
+        prev_ff = b == 0xFF;
+    }
+    out
+}
+
+// ID3v1 genre list, including the Winamp extensions
+const GENRES: &[&str] = &[
+    "Blues", "Classic Rock", "Country", "Dance", "Disco", "Funk", "Grunge", "Hip-Hop", "Jazz",
+    "Metal", "New Age", "Oldies", "Other", "Pop", "R&B", "Rap", "Reggae", "Rock", "Techno",
+    "Industrial", "Alternative", "Ska", "Death Metal", "Pranks", "Soundtrack", "Euro-Techno",
+    "Ambient", "Trip-Hop", "Vocal", "Jazz+Funk", "Fusion", "Trance", "Classical", "Instrumental",
+    "Acid", "House", "Game", "Sound Clip", "Gospel", "Noise", "Alternative Rock", "Bass", "Soul",
+    "Punk", "Space", "Meditative", "Instrumental Pop", "Instrumental Rock", "Ethnic", "Gothic",
+    "Darkwave", "Techno-Industrial", "Electronic", "Pop-Folk", "Eurodance", "Dream",
+    "Southern Rock", "Comedy", "Cult", "Gangsta", "Top 40", "Christian Rap", "Pop/Funk", "Jungle",
+    "Native American", "Cabaret", "New Wave", "Psychedelic", "Rave", "Showtunes", "Trailer",
+    "Lo-Fi", "Tribal", "Acid Punk", "Acid Jazz", "Polka", "Retro", "Musical", "Rock & Roll",
+    "Hard Rock", "Folk", "Folk-Rock", "National Folk", "Swing", "Fast Fusion", "Bebop", "Latin",
+    "Revival", "Celtic", "Bluegrass", "Avantgarde", "Gothic Rock", "Progressive Rock",
+    "Psychedelic Rock", "Symphonic Rock", "Slow Rock", "Big Band", "Chorus", "Easy Listening",
+    "Acoustic", "Humour", "Speech", "Chanson", "Opera", "Chamber Music", "Sonata", "Symphony",
+    "Booty Bass", "Primus", "Porn Groove", "Satire", "Slow Jam", "Club", "Tango", "Samba",
+    "Folklore", "Ballad", "Power Ballad", "Rhythmic Soul", "Freestyle", "Duet", "Punk Rock",
+    "Drum Solo", "A Cappella", "Euro-House", "Dance Hall", "Goa", "Drum & Bass", "Club-House",
+    "Hardcore Techno", "Terror", "Indie", "BritPop", "Negerpunk", "Polsk Punk", "Beat",
+    "Christian Gangsta Rap", "Heavy Metal", "Black Metal", "Crossover", "Contemporary Christian",
+    "Christian Rock", "Merengue", "Salsa", "Thrash Metal", "Anime", "Jpop", "Synthpop", "Abstract",
+    "Art Rock", "Baroque", "Bhangra", "Big Beat", "Breakbeat", "Chillout", "Downtempo", "Dub",
+    "EBM", "Eclectic", "Electro", "Electroclash", "Emo", "Experimental", "Garage", "Global", "IDM",
+    "Illbient", "Industro-Goth", "Jam Band", "Krautrock", "Leftfield", "Lounge", "Math Rock",
+    "New Romantic", "Nu-Breakz", "Post-Punk", "Post-Rock", "Psytrance", "Shoegaze", "Space Rock",
+    "Trop Rock", "World Music", "Neoclassical", "Audiobook", "Audio Theatre", "Neue Deutsche Welle",
+    "Podcast", "Indie Rock", "G-Funk", "Dubstep", "Garage Rock", "Psybient",
+];
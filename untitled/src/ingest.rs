@@ -0,0 +1,103 @@
+use kinode_process_lib::http::StatusCode;
+use serde::{Serialize, Deserialize};
+
+use crate::chapters;
+use crate::metadata::{self, AudioFormat};
+use crate::structs::{Song, Tag};
+
+/// Why an upload was refused, returned to the uploader as-is
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum IngestError {
+    MissingFile,
+    TooLarge { size: u64, max_size: u64 },
+    UnrecognizedFormat,
+    Malformed { format: AudioFormat, reason: String },
+    MissingName,
+    MissingTag,
+}
+
+impl IngestError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            IngestError::MissingFile | IngestError::MissingName | IngestError::MissingTag => StatusCode::BAD_REQUEST,
+            IngestError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            IngestError::UnrecognizedFormat => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            IngestError::Malformed { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl std::fmt::Display for IngestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IngestError::MissingFile => write!(f, "no file was uploaded, or it is empty"),
+            IngestError::TooLarge { size, max_size } => write!(f, "file is {} bytes, the limit is {}", size, max_size),
+            IngestError::UnrecognizedFormat => write!(f, "file is not a supported audio format (mp3, flac, ogg, opus, wav, m4a)"),
+            IngestError::Malformed { format, reason } => write!(f, "file looks like {} but is not valid: {}", format.extension(), reason),
+            IngestError::MissingName => write!(f, "no name given and the file has no title tag"),
+            IngestError::MissingTag => write!(f, "no tag given and the file has no genre tag"),
+        }
+    }
+}
+
+impl std::error::Error for IngestError {}
+
+/// Validates uploaded audio and builds a Song from it. An empty `name` or `tag`
+/// falls back to the title and genre found in the file's own tags.
+pub fn song_from_upload(name: &str, tag: Tag, data: Vec<u8>, max_size: u64) -> Result<Song, IngestError> {
+    if data.is_empty() {
+        return Err(IngestError::MissingFile);
+    }
+    if data.len() as u64 > max_size {
+        return Err(IngestError::TooLarge { size: data.len() as u64, max_size });
+    }
+    let format = AudioFormat::detect(&data).ok_or(IngestError::UnrecognizedFormat)?;
+    metadata::validate(format, &data).map_err(|reason| IngestError::Malformed { format, reason })?;
+
+    let metadata = metadata::read_metadata(format, &data);
+    let properties = metadata::read_properties(format, &data);
+    let lyrics = metadata::read_lyrics(format, &data);
+    let chapters = properties.as_ref()
+        .map(|properties| chapters::close(metadata::read_chapters(format, &data), properties.duration_ms))
+        .unwrap_or_default();
+
+    let name = match name.trim() {
+        "" => metadata.title.clone().ok_or(IngestError::MissingName)?,
+        name => name.to_string(),
+    };
+
+    let tag = if tag.key.trim().is_empty() {
+        let genre = metadata.genre.clone().ok_or(IngestError::MissingTag)?;
+        Tag { key: genre.clone(), name: Some(genre) }
+    } else {
+        tag
+    };
+
+    Ok(Song {
+        id: format!("{}.{}", file_stem(&name), format.extension()),
+        name,
+        data,
+        tag,
+        format,
+        bpm: metadata.bpm,
+        key: metadata.key,
+        metadata,
+        properties,
+        lyrics,
+        chapters,
+        ..Default::default()
+    })
+}
+
+/// The song name made safe to use as a vfs file name: no path separators, control
+/// characters or leading dots, so it can't climb out of music_db or hide itself
+fn file_stem(name: &str) -> String {
+    let stem: String = name.chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() { '_' } else { c })
+        .collect();
+    match stem.trim().trim_start_matches('.').trim_start() {
+        "" => "untitled".to_string(),
+        stem => stem.to_string(),
+    }
+}
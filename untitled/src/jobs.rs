@@ -0,0 +1,104 @@
+// Background analysis that's too slow to do while an upload waits. Jobs sit in a queue that's
+// part of the saved state and are only dropped once they've finished, so a restart picks up
+// wherever we were.
+
+use kinode_process_lib::vfs;
+use serde::{Deserialize, Serialize};
+
+use crate::fingerprint;
+use crate::key;
+use crate::loudness;
+use crate::silence;
+use crate::structs::SongDb;
+use crate::tempo;
+use crate::waveform;
+
+/// A job that keeps failing (or takes the process down) is given up on after this many tries
+pub const MAX_ATTEMPTS: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    Waveform,
+    Loudness,
+    Preview,
+    Fingerprint,
+    Tempo,
+    Key,
+    Silence,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub kind: JobKind,
+    pub song_id: String,
+    pub attempts: u8,
+}
+
+impl Job {
+    pub fn new(kind: JobKind, song_id: &str) -> Self {
+        Job { kind, song_id: song_id.to_string(), attempts: 0 }
+    }
+}
+
+/// Runs the job at the front of the queue. The attempt is saved before starting so a crash
+/// mid-job still counts against it. Returns the finished job, or None if the queue is empty.
+pub fn run_next(song_db: &mut SongDb) -> Option<(Job, anyhow::Result<()>)> {
+    let job = song_db.jobs.front_mut()?;
+    job.attempts += 1;
+    let job = job.clone();
+    song_db.save_queue();
+
+    let result = run(song_db, &job);
+    if result.is_ok() || job.attempts >= MAX_ATTEMPTS {
+        song_db.jobs.pop_front();
+    } else if let Some(retry) = song_db.jobs.pop_front() {
+        // let the rest of the queue go first
+        song_db.jobs.push_back(retry);
+    }
+    song_db.save_queue();
+    Some((job, result))
+}
+
+fn run(song_db: &mut SongDb, job: &Job) -> anyhow::Result<()> {
+    // the song may have been deleted since it was queued
+    let Some(format) = song_db.get_song(&job.song_id).map(|song| song.format) else {
+        return Ok(());
+    };
+    let song_path = song_db.song_path(&job.song_id);
+    let read_song = || vfs::open_file(&song_path, false, None).and_then(|file| file.read());
+
+    match job.kind {
+        JobKind::Waveform => {
+            let peaks = waveform::compute_peaks(format, read_song()?, waveform::PEAK_COUNT)?;
+            let mut file = vfs::create_file(&song_db.waveform_path(&job.song_id), None)?;
+            file.write_all(&waveform::encode(&peaks))?;
+        }
+        JobKind::Loudness => {
+            let (loudness, histogram) = loudness::analyze(format, read_song()?)?;
+            let mut file = vfs::create_file(&song_db.loudness_path(&job.song_id), None)?;
+            file.write_all(&histogram.encode())?;
+            song_db.set_loudness(&job.song_id, loudness);
+        }
+        JobKind::Preview => {
+            song_db.make_preview(&job.song_id)?;
+        }
+        JobKind::Fingerprint => {
+            let fingerprint = fingerprint::compute(format, read_song()?)?;
+            song_db.set_fingerprint(&job.song_id, fingerprint)?;
+        }
+        JobKind::Tempo => {
+            let bpm = tempo::detect(format, read_song()?)?;
+            song_db.set_tempo(&job.song_id, bpm);
+        }
+        JobKind::Key => {
+            let key = key::detect(format, read_song()?)?;
+            song_db.set_key(&job.song_id, key);
+        }
+        JobKind::Silence => {
+            let silence = silence::analyze(format, read_song()?)?;
+            song_db.set_silence(&job.song_id, silence);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+// Musical key, kept as a position on the Camelot wheel so that harmonic mixing is simple
+// arithmetic: neighbouring numbers are a fifth apart, A is minor and B is major, and 8A (A minor)
+// is the relative minor of 8B (C major).
+//
+// Detection sums a chromagram over the song and picks the key whose Krumhansl-Kessler profile
+// correlates best with it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::dsp::{self, Spectrogram};
+use crate::metadata::AudioFormat;
+
+const SAMPLE_RATE: u32 = 11_025;
+const FRAME_LEN: usize = 4096;
+const MAX_SECS: u32 = 300;
+/// C2 to C7, below that a frame's bins are too wide to tell semitones apart
+const MIN_FREQ: f64 = 65.0;
+const MAX_FREQ: f64 = 2093.0;
+
+/// Probe tone ratings for each scale degree, starting on the tonic
+const MAJOR_PROFILE: [f64; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+const MINOR_PROFILE: [f64; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+
+const NOTE_NAMES: [&str; 12] = ["C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B"];
+
+/// Serialized in Camelot notation, e.g. "8A"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Key {
+    /// 1 to 12
+    pub number: u8,
+    pub minor: bool,
+}
+
+impl Key {
+    /// `tonic` is a pitch class, 0 is C
+    pub fn from_tonic(tonic: usize, minor: bool) -> Key {
+        // a minor key sits on the same number as its relative major, three semitones up
+        let major_tonic = if minor { tonic + 3 } else { tonic };
+        Key { number: ((major_tonic * 7 + 7) % 12 + 1) as u8, minor }
+    }
+
+    pub fn tonic(&self) -> usize {
+        let major_tonic = ((self.number as usize + 4) * 7) % 12;
+        if self.minor { (major_tonic + 9) % 12 } else { major_tonic }
+    }
+
+    /// Camelot ("8A"), Open Key ("1m") or a note name ("Am", "F# major", "Ebmin"), as found in
+    /// TKEY and INITIALKEY tags. None for anything else, including ID3's "o" for off key.
+    pub fn parse(text: &str) -> Option<Key> {
+        let text = text.trim();
+        let digits = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+        if digits > 0 {
+            let number: u8 = text[..digits].parse().ok().filter(|number| (1..=12).contains(number))?;
+            return match text[digits..].to_ascii_lowercase().as_str() {
+                "a" => Some(Key { number, minor: true }),
+                "b" => Some(Key { number, minor: false }),
+                // Open Key starts at C major where Camelot has 8
+                "m" => Some(Key { number: (number + 6) % 12 + 1, minor: true }),
+                "d" => Some(Key { number: (number + 6) % 12 + 1, minor: false }),
+                _ => None,
+            };
+        }
+
+        let mut chars = text.chars();
+        let letter = chars.next()?.to_ascii_uppercase();
+        let natural = "C D EF G A B".find(letter).filter(|_| letter != ' ')?;
+        let rest = chars.as_str();
+        let (tonic, rest) = if let Some(rest) = rest.strip_prefix(['#', '♯']) {
+            (natural + 1, rest)
+        } else if let Some(rest) = rest.strip_prefix(['b', '♭']) {
+            (natural + 11, rest)
+        } else {
+            (natural, rest)
+        };
+        let minor = match rest.trim().to_ascii_lowercase().as_str() {
+            "" | "maj" | "major" => false,
+            "m" | "min" | "minor" => true,
+            _ => return None,
+        };
+        Some(Key::from_tonic(tonic % 12, minor))
+    }
+
+    pub fn camelot(&self) -> String {
+        format!("{}{}", self.number, if self.minor { 'A' } else { 'B' })
+    }
+
+    /// "A minor"
+    pub fn name(&self) -> String {
+        format!("{} {}", NOTE_NAMES[self.tonic()], if self.minor { "minor" } else { "major" })
+    }
+
+    /// The same key, a step either way round the wheel, or the relative major/minor
+    pub fn mixes_with(&self, other: &Key) -> bool {
+        let distance = (self.number as i32 - other.number as i32).rem_euclid(12);
+        if self.minor == other.minor {
+            matches!(distance, 0 | 1 | 11)
+        } else {
+            distance == 0
+        }
+    }
+}
+
+impl TryFrom<String> for Key {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Key, String> {
+        Key::parse(&text).ok_or_else(|| format!("not a key: {}", text))
+    }
+}
+
+impl From<Key> for String {
+    fn from(key: Key) -> String {
+        key.camelot()
+    }
+}
+
+pub fn detect(format: AudioFormat, data: Vec<u8>) -> anyhow::Result<Option<Key>> {
+    Ok(from_samples(&dsp::decode_mono(format, data, SAMPLE_RATE, MAX_SECS)?))
+}
+
+/// Key of 11025 Hz mono samples, None when there's nothing tonal to go on
+pub fn from_samples(samples: &[f32]) -> Option<Key> {
+    let pitch_class = dsp::pitch_classes(FRAME_LEN, SAMPLE_RATE, MIN_FREQ, MAX_FREQ);
+    let mut chroma = [0.0; 12];
+    Spectrogram::new(FRAME_LEN).for_each_frame(samples, FRAME_LEN / 2, |power| {
+        let mut frame = [0.0; 12];
+        for (bin, class) in pitch_class.iter().enumerate() {
+            if let Some(class) = class {
+                frame[*class] += power[bin].sqrt();
+            }
+        }
+        // each frame counts the same, so a loud chorus doesn't outvote the rest of the song
+        let total: f64 = frame.iter().sum();
+        if total > 1e-6 {
+            chroma.iter_mut().zip(frame).for_each(|(chroma, energy)| *chroma += energy / total);
+        }
+    });
+    if chroma.iter().all(|&energy| energy == 0.0) {
+        return None;
+    }
+
+    (0..12)
+        .flat_map(|tonic| [(tonic, false, &MAJOR_PROFILE), (tonic, true, &MINOR_PROFILE)])
+        .map(|(tonic, minor, profile)| {
+            let rotated: Vec<f64> = (0..12).map(|class| profile[(class + 12 - tonic) % 12]).collect();
+            (Key::from_tonic(tonic, minor), correlation(&chroma, &rotated))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(key, _)| key)
+}
+
+fn correlation(a: &[f64], b: &[f64]) -> f64 {
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+    for (a, b) in a.iter().zip(b) {
+        covariance += (a - mean_a) * (b - mean_b);
+        variance_a += (a - mean_a).powi(2);
+        variance_b += (b - mean_b).powi(2);
+    }
+    covariance / (variance_a * variance_b).sqrt().max(1e-12)
+}
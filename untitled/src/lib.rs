@@ -1,8 +1,8 @@
 use kinode_process_lib::{
-    await_message, call_init, clear_state, get_blob, get_typed_state, http::{
+    await_message, call_init, clear_state, get_blob, get_state, get_typed_state, http::{
         bind_http_path, bind_ws_path, send_response, send_ws_push, serve_ui, HttpServerRequest,
-        StatusCode, WsMessageType,
-    }, our_capabilities, println, set_state, spawn, vfs::{
+        IncomingHttpRequest, StatusCode, WsMessageType,
+    }, our_capabilities, println, set_state, spawn, timer::set_timer, vfs::{
         create_drive, create_file, metadata, open_dir, open_file, remove_file, Directory, FileType
     }, Address, LazyLoadBlob, Message, OnExit, ProcessId, Request, Response
 };
@@ -10,8 +10,53 @@ use serde_json;
 use std::collections::{HashMap, HashSet};
 use std::io::Read;
 
+mod api;
+mod archive;
+mod chapters;
+mod decode;
+mod demo;
+mod dir_import;
+mod dsp;
+mod duplicates;
+mod fingerprint;
+mod flac;
+mod gapless;
+mod id3;
+mod ingest;
+mod jobs;
+mod key;
+mod links;
+mod loudness;
+mod lyrics;
+mod metadata;
+mod mp3;
+mod mp4;
+mod ogg;
+mod playlist;
+mod podcast;
+mod preview;
+mod search;
+mod silence;
+mod stream;
 mod structs;
-use structs::{SongDb, SongDbRequest, SongDbResponse, Song, Tag};
+mod subsonic;
+mod tempo;
+mod vorbis_comment;
+mod wav;
+mod waveform;
+mod window;
+use jobs::JobKind;
+use metadata::AudioFormat;
+use playlist::PlaylistFormat;
+use search::SongFilter;
+use structs::{ConflictPolicy, Settings, Song, SongDb, SongDbRequest, SongDbResponse, Tag, ARTWORK_DIR, FINGERPRINT_DIR, LOUDNESS_DIR, PREVIEW_DIR, TRASH_DIR, WAVEFORM_DIR};
+
+const PURGE_TRASH_TIMER: &[u8] = b"purge_trash";
+const PURGE_TRASH_INTERVAL_MS: u64 = 60 * 60 * 1000;
+const JOB_TIMER: &[u8] = b"jobs";
+/// One job per tick, so requests queued behind a long backlog still get answered in between
+const JOB_INTERVAL_MS: u64 = 100;
+const DEFAULT_LARGEST_FILES: usize = 10;
 
 wit_bindgen::generate!({
     path: "target/wit",
@@ -26,7 +71,28 @@ fn init(our: Address) {
 
     let drive_path = create_drive(our.package_id(), "music_db", None).unwrap();
     let files_dir = open_dir(&drive_path, false, None).unwrap();
+    open_dir(&format!("{}/{}", drive_path, TRASH_DIR), true, None).unwrap();
+    open_dir(&format!("{}/{}", drive_path, ARTWORK_DIR), true, None).unwrap();
+    open_dir(&format!("{}/{}", drive_path, WAVEFORM_DIR), true, None).unwrap();
+    open_dir(&format!("{}/{}", drive_path, LOUDNESS_DIR), true, None).unwrap();
+    open_dir(&format!("{}/{}", drive_path, PREVIEW_DIR), true, None).unwrap();
+    open_dir(&format!("{}/{}", drive_path, FINGERPRINT_DIR), true, None).unwrap();
+    let fresh_install = get_state().is_none();
     let mut song_db = SongDb::load(&files_dir);
+    if fresh_install {
+        song_db.settings = Settings::initial(&our);
+    }
+
+    if song_db.settings.seed_demo_tracks && !song_db.demo_tracks_seeded {
+        match demo::seed_demo_tracks(&our, &mut song_db) {
+            Ok(added) => {
+                println!("Seeded {} demo track(s)", added);
+                song_db.demo_tracks_seeded = true;
+                song_db.save();
+            }
+            Err(e) => println!("Failed to seed demo tracks: {:?}", e),
+        }
+    }
     let mut ws_channels: HashSet<u32> = HashSet::new();
 
     // Serve UI files
@@ -37,10 +103,49 @@ fn init(our: Address) {
     bind_http_path("/upload_song", true, false).unwrap();
     bind_http_path("/list_all_songs", true, false).unwrap();
     bind_http_path("/stream_audio", true, false).unwrap();
+    bind_http_path("/export_library", true, false).unwrap();
+    bind_http_path("/import_library", true, false).unwrap();
+    bind_http_path("/delete_song", true, false).unwrap();
+    bind_http_path("/restore_song", true, false).unwrap();
+    bind_http_path("/list_trash", true, false).unwrap();
+    bind_http_path("/settings", true, false).unwrap();
+    bind_http_path("/stats", true, false).unwrap();
+    bind_http_path("/artwork", true, false).unwrap();
+    bind_http_path("/waveform", true, false).unwrap();
+    bind_http_path("/preview", true, false).unwrap();
+    bind_http_path("/lyrics", true, false).unwrap();
+    bind_http_path("/search", true, false).unwrap();
+    bind_http_path("/chapters", true, false).unwrap();
+    bind_http_path("/fingerprint", true, false).unwrap();
+    bind_http_path("/duplicates", true, false).unwrap();
+    bind_http_path("/merge_songs", true, false).unwrap();
+    bind_http_path("/silence", true, false).unwrap();
+    bind_http_path("/split_song", true, false).unwrap();
+    bind_http_path("/podcasts", true, false).unwrap();
+    bind_http_path("/playlists", true, false).unwrap();
+    bind_http_path("/delete_playlist", true, false).unwrap();
+    bind_http_path("/export_playlist", true, false).unwrap();
+    bind_http_path("/import_playlist", true, false).unwrap();
+    // podcast feeds and what they link to are for podcast apps, which can't log in
+    bind_http_path("/feed/:feed", false, false).unwrap();
+    bind_http_path("/public_stream", false, false).unwrap();
+    bind_http_path("/public_artwork", false, false).unwrap();
+    bind_http_path("/subsonic_password", true, false).unwrap();
+    bind_http_path("/api/v1/:resource", true, false).unwrap();
+    bind_http_path("/api/v1/:resource/:id", true, false).unwrap();
+    // Subsonic clients log in with their own scheme, see subsonic::authenticate
+    bind_http_path("/rest/:method", false, false).unwrap();
+    bind_http_path("/import_directory", true, false).unwrap();
+    bind_http_path("/seed_demo_tracks", true, false).unwrap();
+    bind_http_path("/remove_demo_tracks", true, false).unwrap();
 
     // Bind WebSocket path
     bind_ws_path("/", true, false).unwrap();
 
+    // Purge anything that expired while we were down, then keep checking periodically
+    song_db.purge_expired_trash();
+    set_timer(PURGE_TRASH_INTERVAL_MS, Some(PURGE_TRASH_TIMER.to_vec()));
+
     loop {
         match handle_message(&our, &mut song_db, &mut ws_channels) {
             Ok(()) => {}
@@ -49,6 +154,11 @@ fn init(our: Address) {
                 push_update_via_ws(&ws_channels, &format!("Error: {:?}", e));
             }
         }
+        // anything that added songs also queued jobs for them; this also resumes a queue saved before a restart
+        if !song_db.jobs.is_empty() && !song_db.job_timer_armed {
+            set_timer(JOB_INTERVAL_MS, Some(JOB_TIMER.to_vec()));
+            song_db.job_timer_armed = true;
+        }
     }
 }
 
@@ -62,13 +172,53 @@ fn handle_message(
     match message {
         Message::Request { source, body, .. } => {
             if source.process.to_string() == "http_server:distro:sys" {
-                handle_http_request(our, &source, &body, song_db, ws_channels)
+                let result = handle_http_request(our, &source, &body, song_db, ws_channels);
+                if let Err(e) = &result {
+                    answer_failed_http_request(&body, e);
+                }
+                result
             } else {
                 handle_songdb_request(our, &source, &body, song_db, ws_channels)
             }
         }
-        Message::Response { .. } => Ok(()),
+        Message::Response { source, context, .. } => {
+            if source.process.to_string() == "timer:distro:sys" {
+                handle_timer(song_db, ws_channels, context.as_deref())
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+fn handle_timer(
+    song_db: &mut SongDb,
+    ws_channels: &mut HashSet<u32>,
+    context: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    match context {
+        Some(PURGE_TRASH_TIMER) => {
+            let purged = song_db.purge_expired_trash();
+            if !purged.is_empty() {
+                push_update_via_ws(ws_channels, &format!("Purged {} song(s) from trash", purged.len()));
+            }
+            set_timer(PURGE_TRASH_INTERVAL_MS, Some(PURGE_TRASH_TIMER.to_vec()));
+        }
+        Some(JOB_TIMER) => {
+            song_db.job_timer_armed = false;
+            match jobs::run_next(song_db) {
+                Some((job, Ok(()))) => {
+                    push_event_via_ws(ws_channels, "job_finished", serde_json::json!({ "kind": job.kind, "id": job.song_id }));
+                }
+                Some((job, Err(e))) => {
+                    println!("{:?} job for {} failed (attempt {}): {:?}", job.kind, job.song_id, job.attempts, e);
+                }
+                None => {}
+            }
+        }
+        _ => println!("Unknown timer context: {:?}", context),
     }
+    Ok(())
 }
 
 fn handle_songdb_request(
@@ -81,6 +231,13 @@ fn handle_songdb_request(
     let request = serde_json::from_slice::<SongDbRequest>(body)?;
     println!("song_db_request handler:");
 
+    if request.is_owner_only() && source.node != our.node {
+        Response::new()
+            .body(serde_json::to_vec(&SongDbResponse::Error("Request is only available to the node owner".to_string()))?)
+            .send()?;
+        return Ok(());
+    }
+
     match request {
         SongDbRequest::GetSongsByTag(tag) => {
             let songs = song_db.get_songs_by_tag(&tag);
@@ -90,12 +247,16 @@ fn handle_songdb_request(
         }
         SongDbRequest::UploadSong(upload_request) => {
             let blob = get_blob().ok_or_else(|| anyhow::anyhow!("No blob provided for song upload"))?;
-            let song = Song {
-                id: format!("{}.mp3", upload_request.name),
-                name: upload_request.name,
-                data: blob.bytes,
-                tag: upload_request.tag,
+            let mut song = match ingest::song_from_upload(&upload_request.name, upload_request.tag, blob.bytes, song_db.settings.max_upload_bytes) {
+                Ok(song) => song,
+                Err(e) => {
+                    Response::new()
+                        .body(serde_json::to_vec(&SongDbResponse::UploadRejected(e))?)
+                        .send()?;
+                    return Ok(());
+                }
             };
+            song.id = song_db.free_song_id(&song.id);
             match song_db.add_song(song) {
                 Ok(_) => {
                     Response::new()
@@ -116,6 +277,325 @@ fn handle_songdb_request(
                 .body(serde_json::to_vec(&SongDbResponse::Tags(tags.into_iter().cloned().collect()))?)
                 .send()?;
         }
+        SongDbRequest::ExportLibrary(export_request) => {
+            let songs = song_db.songs.values().map(Vec::len).sum();
+            match export_request.path {
+                Some(path) => {
+                    archive::export_library_to_file(song_db, &path)?;
+                    Response::new()
+                        .body(serde_json::to_vec(&SongDbResponse::LibraryExported { path: Some(path), songs })?)
+                        .send()?;
+                }
+                None => {
+                    let archive_bytes = archive::export_library(song_db, Vec::new())?;
+                    Response::new()
+                        .body(serde_json::to_vec(&SongDbResponse::LibraryExported { path: None, songs })?)
+                        .blob_bytes(archive_bytes)
+                        .send()?;
+                }
+            }
+        }
+        SongDbRequest::ImportLibrary(import_request) => {
+            let archive_bytes = match import_request.path {
+                Some(path) => open_file(&path, false, None)?.read()?,
+                None => get_blob().ok_or_else(|| anyhow::anyhow!("No blob provided for library import"))?.bytes,
+            };
+            match archive::import_library(song_db, &archive_bytes, import_request.on_conflict) {
+                Ok(summary) => {
+                    Response::new()
+                        .body(serde_json::to_vec(&SongDbResponse::LibraryImported(summary))?)
+                        .send()?;
+                    push_update_via_ws(ws_channels, "Library imported successfully");
+                }
+                Err(e) => {
+                    Response::new()
+                        .body(serde_json::to_vec(&SongDbResponse::Error(format!("Failed to import library: {}", e)))?)
+                        .send()?;
+                }
+            }
+        }
+        SongDbRequest::DeleteSong(song_id) => {
+            let response = match song_db.delete_song(&song_id) {
+                Ok(()) => {
+                    push_update_via_ws(ws_channels, "Song moved to trash");
+                    SongDbResponse::SongDeleted
+                }
+                Err(e) => SongDbResponse::Error(format!("Failed to delete song: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::RestoreSong(song_id) => {
+            let response = match song_db.restore_song(&song_id) {
+                Ok(()) => {
+                    push_update_via_ws(ws_channels, "Song restored from trash");
+                    SongDbResponse::SongRestored
+                }
+                Err(e) => SongDbResponse::Error(format!("Failed to restore song: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::ListTrash => {
+            let trash = song_db.trash.values().cloned().collect();
+            Response::new()
+                .body(serde_json::to_vec(&SongDbResponse::Trash(trash))?)
+                .send()?;
+        }
+        SongDbRequest::GetStats => {
+            Response::new()
+                .body(serde_json::to_vec(&SongDbResponse::Stats(song_db.stats(DEFAULT_LARGEST_FILES)))?)
+                .send()?;
+        }
+        SongDbRequest::ImportDirectory(path) => {
+            let response = match import_directory(song_db, ws_channels, &path) {
+                Ok(summary) => SongDbResponse::DirectoryImported(summary),
+                Err(e) => SongDbResponse::Error(format!("Failed to import directory: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::SeedDemoTracks => {
+            let response = match demo::seed_demo_tracks(our, song_db) {
+                Ok(added) => {
+                    push_update_via_ws(ws_channels, "Demo tracks added");
+                    SongDbResponse::DemoTracksSeeded(added)
+                }
+                Err(e) => SongDbResponse::Error(format!("Failed to seed demo tracks: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::RemoveDemoTracks => {
+            let response = match demo::remove_demo_tracks(song_db) {
+                Ok(removed) => {
+                    push_update_via_ws(ws_channels, "Demo tracks removed");
+                    SongDbResponse::DemoTracksRemoved(removed)
+                }
+                Err(e) => SongDbResponse::Error(format!("Failed to remove demo tracks: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::GetSettings => {
+            Response::new()
+                .body(serde_json::to_vec(&SongDbResponse::Settings(song_db.settings.clone()))?)
+                .send()?;
+        }
+        SongDbRequest::UpdateSettings(settings) => {
+            song_db.update_settings(settings);
+            Response::new()
+                .body(serde_json::to_vec(&SongDbResponse::Settings(song_db.settings.clone()))?)
+                .send()?;
+        }
+        SongDbRequest::GetWaveform(song_id) => {
+            let response = match song_db.get_waveform(&song_id) {
+                Ok(Some(peaks)) => SongDbResponse::Waveform(peaks),
+                Ok(None) => SongDbResponse::WaveformPending,
+                Err(e) => SongDbResponse::Error(format!("Failed to get waveform: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::GetPreview(song_id) => {
+            if source.node != our.node && !song_db.settings.share_previews {
+                Response::new()
+                    .body(serde_json::to_vec(&SongDbResponse::Error("Previews are not shared with peers".to_string()))?)
+                    .send()?;
+                return Ok(());
+            }
+            match song_db.get_preview(&song_id) {
+                Ok(clip) => {
+                    Response::new()
+                        .body(serde_json::to_vec(&SongDbResponse::Preview)?)
+                        .blob_bytes(clip)
+                        .send()?;
+                }
+                Err(e) => {
+                    Response::new()
+                        .body(serde_json::to_vec(&SongDbResponse::Error(format!("Failed to get preview: {}", e)))?)
+                        .send()?;
+                }
+            }
+        }
+        SongDbRequest::GetLyrics(song_id) => {
+            let response = match song_db.get_song(&song_id) {
+                Some(song) => SongDbResponse::Lyrics(song.lyrics.clone()),
+                None => SongDbResponse::Error(format!("No song with id {}", song_id)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::SetLyrics { song_id, lyrics } => {
+            let response = match song_db.set_lyrics(&song_id, &lyrics) {
+                Ok(lyrics) => {
+                    push_update_via_ws(ws_channels, "Lyrics updated");
+                    SongDbResponse::Lyrics(lyrics)
+                }
+                Err(e) => SongDbResponse::Error(format!("Failed to set lyrics: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::Search(query) => {
+            Response::new()
+                .body(serde_json::to_vec(&SongDbResponse::SearchResults(search::search(song_db, &query, &SongFilter::default())))?)
+                .send()?;
+        }
+        SongDbRequest::SetCueSheet { song_id, cue_sheet } => {
+            let response = match song_db.set_cue_sheet(&song_id, &cue_sheet) {
+                Ok(chapters) => {
+                    push_update_via_ws(ws_channels, "Chapters updated");
+                    SongDbResponse::Chapters(chapters)
+                }
+                Err(e) => SongDbResponse::Error(format!("Failed to set chapters: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::GetFingerprint(song_id) => {
+            let response = match song_db.get_fingerprint(&song_id) {
+                Ok(Some(fingerprint)) => SongDbResponse::Fingerprint(fingerprint),
+                Ok(None) => SongDbResponse::FingerprintPending,
+                Err(e) => SongDbResponse::Error(format!("Failed to get fingerprint: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::FindRecording(fingerprint) => {
+            Response::new()
+                .body(serde_json::to_vec(&SongDbResponse::Recordings(duplicates::find_recording(song_db, &fingerprint)))?)
+                .send()?;
+        }
+        SongDbRequest::FindDuplicates => {
+            Response::new()
+                .body(serde_json::to_vec(&SongDbResponse::Duplicates(duplicates::find_duplicates(song_db)))?)
+                .send()?;
+        }
+        SongDbRequest::MergeSongs { song_ids, keep } => {
+            let response = match duplicates::merge(song_db, &song_ids, keep.as_deref()) {
+                Ok(summary) => {
+                    push_update_via_ws(ws_channels, "Songs merged");
+                    SongDbResponse::SongsMerged(summary)
+                }
+                Err(e) => SongDbResponse::Error(format!("Failed to merge songs: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::GetPodcasts => {
+            Response::new()
+                .body(serde_json::to_vec(&SongDbResponse::Podcasts(song_db.podcasts.clone()))?)
+                .send()?;
+        }
+        SongDbRequest::PublishPodcast { tag_key, podcast } => {
+            let response = match song_db.set_podcast(&tag_key, podcast) {
+                Ok(()) => {
+                    push_update_via_ws(ws_channels, "Podcasts updated");
+                    SongDbResponse::Podcasts(song_db.podcasts.clone())
+                }
+                Err(e) => SongDbResponse::Error(format!("Failed to publish podcast: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::GetPlaylists => {
+            Response::new()
+                .body(serde_json::to_vec(&SongDbResponse::Playlists(song_db.list_playlists()))?)
+                .send()?;
+        }
+        SongDbRequest::SavePlaylist { id, edit } => {
+            let response = match song_db.save_playlist(id.as_deref(), edit) {
+                Ok(playlist) => {
+                    push_update_via_ws(ws_channels, "Playlists updated");
+                    SongDbResponse::Playlist(playlist)
+                }
+                Err(e) => SongDbResponse::Error(format!("Failed to save playlist: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::DeletePlaylist(id) => {
+            let response = match song_db.delete_playlist(&id) {
+                Ok(()) => {
+                    push_update_via_ws(ws_channels, "Playlists updated");
+                    SongDbResponse::PlaylistDeleted
+                }
+                Err(e) => SongDbResponse::Error(format!("Failed to delete playlist: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::ImportPlaylist { name, format } => {
+            let text = get_blob().map(|blob| String::from_utf8_lossy(&blob.bytes).into_owned()).unwrap_or_default();
+            let response = match song_db.import_playlist(name, format, &text) {
+                Ok(summary) => {
+                    push_update_via_ws(ws_channels, "Playlists updated");
+                    SongDbResponse::PlaylistImported(summary)
+                }
+                Err(e) => SongDbResponse::Error(format!("Failed to import playlist: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::SetSubsonicPassword(password) => {
+            song_db.set_subsonic_password(password);
+            Response::new()
+                .body(serde_json::to_vec(&SongDbResponse::SubsonicEnabled(song_db.subsonic_password.is_some()))?)
+                .send()?;
+        }
+        SongDbRequest::GetSilence(song_id) => {
+            let response = match song_db.get_song(&song_id) {
+                Some(_) if song_db.has_queued_job(JobKind::Silence, &song_id) => SongDbResponse::SilencePending,
+                Some(song) => SongDbResponse::Silence(song.silence.clone()),
+                None => SongDbResponse::Error(format!("No song with id {}", song_id)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::SplitSong { song_id, split_points } => {
+            let response = match song_db.split_song(&song_id, split_points) {
+                Ok(chapters) => {
+                    push_update_via_ws(ws_channels, "Chapters updated");
+                    SongDbResponse::Chapters(chapters)
+                }
+                Err(e) => SongDbResponse::Error(format!("Failed to split song: {}", e)),
+            };
+            Response::new()
+                .body(serde_json::to_vec(&response)?)
+                .send()?;
+        }
+        SongDbRequest::GetArtwork(artwork_id) => {
+            match song_db.get_artwork(&artwork_id) {
+                Ok((mime, data)) => {
+                    Response::new()
+                        .body(serde_json::to_vec(&SongDbResponse::Artwork { mime: mime.to_string() })?)
+                        .blob_bytes(data)
+                        .send()?;
+                }
+                Err(e) => {
+                    Response::new()
+                        .body(serde_json::to_vec(&SongDbResponse::Error(format!("Failed to get artwork: {}", e)))?)
+                        .send()?;
+                }
+            }
+        }
     }
 
     Ok(())
@@ -137,60 +617,410 @@ fn handle_http_request(
 
             match (method.as_str(), path.as_str()) {
                 ("GET", "/get_songs_from_tag") => {
-                    let tag = request.query_params().get("tag").ok_or_else(|| anyhow::anyhow!("No tag provided"))?;
-                    let songs = song_db.get_songs_by_tag(tag);
+                    let tag = request.query_params().get("tag").ok_or_else(|| api::ApiError::bad_request("No tag provided"))?;
+                    let filter = match SongFilter::from_query(request.query_params()) {
+                        Ok(filter) => filter,
+                        Err(e) => {
+                            send_response(StatusCode::BAD_REQUEST, None, e.into_bytes());
+                            return Ok(());
+                        }
+                    };
+                    if catalog_not_modified(&request, song_db) {
+                        return Ok(());
+                    }
+                    let mut songs = song_db.get_songs_by_tag(tag);
+                    songs.retain(|song| filter.matches(song));
                     let response = serde_json::to_vec(&songs)?;
-                    send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), response);
+                    send_catalog_json(song_db, response);
                 }
 
                 ("GET", "/list_all_songs") => {
-                    let all_songs: Vec<_> = song_db.songs.values().flatten().map(|song| {
-                        serde_json::json!({
+                    if catalog_not_modified(&request, song_db) {
+                        return Ok(());
+                    }
+                    // chapters are listed as tracks of their own right after their song
+                    let all_songs: Vec<_> = song_db.songs.values().flatten().flat_map(|song| {
+                        let tracks = song.chapters.iter().enumerate().map(|(i, chapter)| {
+                            serde_json::json!({
+                                "id": chapters::track_id(&song.id, i + 1),
+                                "parent": song.id,
+                                "name": chapter.title.clone().unwrap_or_else(|| format!("{} ({})", song.name, i + 1)),
+                                "performer": chapter.performer,
+                                "tag": song.tag,
+                                "format": song.format,
+                                "artwork": song.artwork,
+                                "start_ms": chapter.start_ms,
+                                "end_ms": chapter.end_ms,
+                            })
+                        });
+                        std::iter::once(serde_json::json!({
                             "id": song.id,
                             "name": song.name,
                             "tag": song.tag,
-                        })
+                            "format": song.format,
+                            "metadata": song.metadata,
+                            "properties": song.properties,
+                            "artwork": song.artwork,
+                            "loudness": song.loudness,
+                            "replay_gain": song.replay_gain,
+                            "lyrics": song.lyrics.as_ref().map(|lyrics| serde_json::json!({ "synced": lyrics.synced, "lines": lyrics.lines.len() })),
+                            "chapters": song.chapters.len(),
+                            "bpm": song.bpm,
+                            "key": song.key,
+                            "trim": song.silence.as_ref().map(|silence| serde_json::json!({ "start_ms": silence.trim_start_ms, "end_ms": silence.trim_end_ms })),
+                            "proposed_splits": song.silence.as_ref().map_or(0, |silence| silence.proposed_splits.len()),
+                        })).chain(tracks)
                     }).collect();
                     
                     let response = serde_json::to_vec(&all_songs)?;
-                    send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), response);
+                    send_catalog_json(song_db, response);
                 }
                 ("GET", "/stream_audio") => {
-                    let song_id = request.query_params().get("id").ok_or_else(|| anyhow::anyhow!("No song ID provided"))?;
-                    let file_path = format!("{}/{}", song_db.vfs_dir_path, song_id);
-                    
-                    match open_file(&file_path, false, None) {
-                        Ok(file) => {
-                            let data = file.read()?;
+                    stream_audio(&request, song_db)?;
+                }
+                ("GET", "/public_stream") => {
+                    let song_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+                    if !podcast::is_public_song(song_db, song_id) {
+                        send_response(StatusCode::NOT_FOUND, None, b"Audio file not found".to_vec());
+                        return Ok(());
+                    }
+                    stream_audio(&request, song_db)?;
+                }
+                ("GET", "/artwork" | "/public_artwork") => {
+                    let artwork_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No artwork ID provided"))?;
+                    if path == "/public_artwork" && !podcast::is_public_artwork(song_db, artwork_id) {
+                        send_response(StatusCode::NOT_FOUND, None, b"Artwork not found".to_vec());
+                        return Ok(());
+                    }
+                    send_artwork(&request, song_db, artwork_id);
+                }
+                ("GET", "/preview") => {
+                    let song_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+                    match song_db.get_preview(song_id) {
+                        Ok(clip) => {
                             let mut headers = HashMap::new();
-                            headers.insert("Content-Type".to_string(), "audio/mpeg".to_string());
-                            headers.insert("Content-Length".to_string(), data.len().to_string());
-                            send_response(StatusCode::OK, Some(headers), data);
+                            headers.insert("Content-Type".to_string(), AudioFormat::Mp3.mime_type().to_string());
+                            headers.insert("Content-Length".to_string(), clip.len().to_string());
+                            send_response(StatusCode::OK, Some(headers), clip);
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::NOT_FOUND, None, format!("Preview not available: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("GET", "/waveform") => {
+                    let song_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+                    match song_db.get_waveform(song_id) {
+                        Ok(Some(peaks)) => {
+                            let body = serde_json::json!({ "id": song_id, "count": peaks.len(), "peaks": peaks });
+                            send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&body)?);
+                        }
+                        Ok(None) => {
+                            let body = serde_json::json!({ "id": song_id, "status": "pending" });
+                            send_response(StatusCode::ACCEPTED, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&body)?);
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::NOT_FOUND, None, format!("Waveform not found: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("GET", "/lyrics") => {
+                    let song_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+                    let Some(song) = song_db.get_song(song_id) else {
+                        send_response(StatusCode::NOT_FOUND, None, b"Song not found".to_vec());
+                        return Ok(());
+                    };
+                    let Some(lyrics) = &song.lyrics else {
+                        send_response(StatusCode::NOT_FOUND, None, b"Song has no lyrics".to_vec());
+                        return Ok(());
+                    };
+                    if request.query_params().get("format").is_some_and(|format| format == "lrc") {
+                        send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "text/plain; charset=utf-8".to_string())])), lyrics.to_lrc().into_bytes());
+                    } else {
+                        let body = serde_json::json!({ "id": song_id, "synced": lyrics.synced, "lines": lyrics.lines });
+                        send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&body)?);
+                    }
+                }
+                ("POST", "/lyrics") => {
+                    let song_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+                    let text = get_blob().map(|blob| String::from_utf8_lossy(&blob.bytes).into_owned()).unwrap_or_default();
+                    match song_db.set_lyrics(song_id, &text) {
+                        Ok(lyrics) => {
+                            let body = serde_json::json!({ "id": song_id, "lyrics": lyrics });
+                            send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&body)?);
+                            push_update_via_ws(ws_channels, "Lyrics updated");
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::NOT_FOUND, None, format!("Failed to set lyrics: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("GET", "/chapters") => {
+                    let song_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+                    let Some(song) = song_db.get_song(song_id) else {
+                        send_response(StatusCode::NOT_FOUND, None, b"Song not found".to_vec());
+                        return Ok(());
+                    };
+                    let tracks: Vec<_> = song.chapters.iter().enumerate().map(|(i, chapter)| {
+                        serde_json::json!({ "id": chapters::track_id(song_id, i + 1), "chapter": chapter })
+                    }).collect();
+                    send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&tracks)?);
+                }
+                ("POST", "/chapters") => {
+                    let song_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+                    let cue_sheet = get_blob().map(|blob| String::from_utf8_lossy(&blob.bytes).into_owned()).unwrap_or_default();
+                    match song_db.set_cue_sheet(song_id, &cue_sheet) {
+                        Ok(chapters) => {
+                            send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&chapters)?);
+                            push_update_via_ws(ws_channels, "Chapters updated");
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::BAD_REQUEST, None, format!("Failed to set chapters: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("GET", "/fingerprint") => {
+                    let song_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+                    match song_db.get_fingerprint(song_id) {
+                        Ok(Some(fingerprint)) => {
+                            let body = serde_json::json!({ "id": song_id, "items_per_sec": fingerprint::ITEMS_PER_SEC, "fingerprint": fingerprint });
+                            send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&body)?);
+                        }
+                        Ok(None) => {
+                            let body = serde_json::json!({ "id": song_id, "status": "pending" });
+                            send_response(StatusCode::ACCEPTED, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&body)?);
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::NOT_FOUND, None, format!("Fingerprint not found: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("GET", "/duplicates") => {
+                    let report = duplicates::find_duplicates(song_db);
+                    send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&report)?);
+                }
+                ("POST", "/merge_songs") => {
+                    let song_ids: Vec<String> = request.query_params().get("ids")
+                        .map(|ids| ids.split(',').filter(|id| !id.is_empty()).map(str::to_string).collect())
+                        .unwrap_or_default();
+                    let keep = request.query_params().get("keep").map(String::as_str);
+                    match duplicates::merge(song_db, &song_ids, keep) {
+                        Ok(summary) => {
+                            send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&summary)?);
+                            push_update_via_ws(ws_channels, "Songs merged");
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::BAD_REQUEST, None, format!("Failed to merge songs: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("GET", "/silence") => {
+                    let song_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+                    let Some(song) = song_db.get_song(song_id) else {
+                        send_response(StatusCode::NOT_FOUND, None, b"Song not found".to_vec());
+                        return Ok(());
+                    };
+                    if song_db.has_queued_job(JobKind::Silence, song_id) {
+                        let body = serde_json::json!({ "id": song_id, "status": "pending" });
+                        send_response(StatusCode::ACCEPTED, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&body)?);
+                        return Ok(());
+                    }
+                    let body = serde_json::json!({ "id": song_id, "silence": song.silence });
+                    send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&body)?);
+                }
+                ("POST", "/split_song") => {
+                    let song_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+                    // ?at=ms,ms,... overrides the proposed split points
+                    let split_points = match request.query_params().get("at") {
+                        Some(at) => match at.split(',').map(|point| point.trim().parse::<u64>()).collect::<Result<Vec<_>, _>>() {
+                            Ok(points) => Some(points),
+                            Err(_) => {
+                                send_response(StatusCode::BAD_REQUEST, None, format!("Split points must be milliseconds: {}", at).into_bytes());
+                                return Ok(());
+                            }
                         },
-                        Err(_) => {
-                            send_response(StatusCode::NOT_FOUND, None, b"Audio file not found".to_vec());
+                        None => None,
+                    };
+                    match song_db.split_song(song_id, split_points) {
+                        Ok(chapters) => {
+                            send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&chapters)?);
+                            push_update_via_ws(ws_channels, "Chapters updated");
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::BAD_REQUEST, None, format!("Failed to split song: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("GET", "/search") => {
+                    let query = request.query_params().get("q").cloned().unwrap_or_default();
+                    let filter = match SongFilter::from_query(request.query_params()) {
+                        Ok(filter) => filter,
+                        Err(e) => {
+                            send_response(StatusCode::BAD_REQUEST, None, e.into_bytes());
+                            return Ok(());
+                        }
+                    };
+                    if catalog_not_modified(&request, song_db) {
+                        return Ok(());
+                    }
+                    let response = serde_json::to_vec(&search::search(song_db, &query, &filter))?;
+                    send_catalog_json(song_db, response);
+                }
+                ("GET", "/export_library") => {
+                    // an HTTP response goes out as one body, so this one is built in memory
+                    let archive_bytes = archive::export_library(song_db, Vec::new())?;
+                    let mut headers = HashMap::new();
+                    headers.insert("Content-Type".to_string(), "application/x-tar".to_string());
+                    headers.insert("Content-Disposition".to_string(), "attachment; filename=\"music_db.tar\"".to_string());
+                    headers.insert("Content-Length".to_string(), archive_bytes.len().to_string());
+                    send_response(StatusCode::OK, Some(headers), archive_bytes);
+                }
+                ("POST", "/import_library") => {
+                    let blob = get_blob().ok_or_else(|| api::ApiError::bad_request("No blob provided for library import"))?;
+                    let on_conflict = match request.query_params().get("on_conflict") {
+                        Some(policy) => match policy.parse::<ConflictPolicy>() {
+                            Ok(policy) => policy,
+                            Err(e) => {
+                                send_response(StatusCode::BAD_REQUEST, None, e.to_string().into_bytes());
+                                return Ok(());
+                            }
+                        },
+                        None => ConflictPolicy::Skip,
+                    };
+
+                    match archive::import_library(song_db, &blob.bytes, on_conflict) {
+                        Ok(summary) => {
+                            send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&summary)?);
+                            push_update_via_ws(ws_channels, "Library imported successfully");
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::BAD_REQUEST, None, format!("Failed to import library: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("POST", "/delete_song") => {
+                    let song_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+                    match song_db.delete_song(song_id) {
+                        Ok(()) => {
+                            send_response(StatusCode::OK, None, b"Song moved to trash".to_vec());
+                            push_update_via_ws(ws_channels, "Song moved to trash");
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::NOT_FOUND, None, format!("Failed to delete song: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("POST", "/restore_song") => {
+                    let song_id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+                    match song_db.restore_song(song_id) {
+                        Ok(()) => {
+                            send_response(StatusCode::OK, None, b"Song restored from trash".to_vec());
+                            push_update_via_ws(ws_channels, "Song restored from trash");
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::CONFLICT, None, format!("Failed to restore song: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("GET", "/list_trash") => {
+                    let retention = song_db.settings.trash_retention_secs;
+                    let trash: Vec<_> = song_db.trash.values().map(|trashed| {
+                        serde_json::json!({
+                            "trash_id": trashed.trash_id,
+                            "id": trashed.song.id,
+                            "name": trashed.song.name,
+                            "tag": trashed.song.tag,
+                            "deleted_at": trashed.deleted_at,
+                            "expires_at": trashed.deleted_at.saturating_add(retention),
+                        })
+                    }).collect();
+
+                    let response = serde_json::to_vec(&trash)?;
+                    send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), response);
+                }
+                ("GET", "/stats") => {
+                    let largest = request.query_params().get("largest")
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .unwrap_or(DEFAULT_LARGEST_FILES);
+                    let response = serde_json::to_vec(&song_db.stats(largest))?;
+                    send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), response);
+                }
+                ("POST", "/import_directory") => {
+                    let path = request.query_params().get("path").ok_or_else(|| api::ApiError::bad_request("No directory path provided"))?;
+                    match import_directory(song_db, ws_channels, path) {
+                        Ok(summary) => {
+                            send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&summary)?);
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::BAD_REQUEST, None, format!("Failed to import directory: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("POST", "/seed_demo_tracks") => {
+                    match demo::seed_demo_tracks(our, song_db) {
+                        Ok(added) => {
+                            send_response(StatusCode::OK, None, format!("Added {} demo track(s)", added).into_bytes());
+                            push_update_via_ws(ws_channels, "Demo tracks added");
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::INTERNAL_SERVER_ERROR, None, format!("Failed to seed demo tracks: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("POST", "/remove_demo_tracks") => {
+                    match demo::remove_demo_tracks(song_db) {
+                        Ok(removed) => {
+                            send_response(StatusCode::OK, None, format!("Removed {} demo track(s)", removed).into_bytes());
+                            push_update_via_ws(ws_channels, "Demo tracks removed");
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::INTERNAL_SERVER_ERROR, None, format!("Failed to remove demo tracks: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("GET", "/settings") => {
+                    let response = serde_json::to_vec(&song_db.settings)?;
+                    send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), response);
+                }
+                ("POST", "/settings") => {
+                    let blob = get_blob().ok_or_else(|| api::ApiError::bad_request("No blob provided for settings update"))?;
+                    match serde_json::from_slice::<Settings>(&blob.bytes) {
+                        Ok(settings) => {
+                            song_db.update_settings(settings);
+                            let response = serde_json::to_vec(&song_db.settings)?;
+                            send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), response);
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::BAD_REQUEST, None, format!("Invalid settings: {}", e).into_bytes());
                         }
                     }
                 }
                 ("POST", "/upload_song") => {
 
-                    let blob = get_blob().ok_or_else(|| anyhow::anyhow!("No blob provided for song upload"))?;
+                    let blob = get_blob().ok_or_else(|| api::ApiError::bad_request("No blob provided for song upload"))?;
 
                     println!("Received upload request with blob size: {}", blob.bytes.len());
+
+                    // the multipart body wraps the file, leave some room for the other fields
+                    let max_size = song_db.settings.max_upload_bytes;
+                    if blob.bytes.len() as u64 > max_size.saturating_add(64 * 1024) {
+                        send_ingest_error(&ingest::IngestError::TooLarge { size: blob.bytes.len() as u64, max_size })?;
+                        return Ok(());
+                    }
     
                     // Create a longer-lived value for content_type
                     let content_type = request.headers()
                         .get("Content-Type")
-                        .ok_or_else(|| anyhow::anyhow!("upload, Content-Type header not found"))?
+                        .ok_or_else(|| api::ApiError::bad_request("upload, Content-Type header not found"))?
                         .to_str()
-                        .map_err(|_| anyhow::anyhow!("failed to convert Content-Type to string"))?
+                        .map_err(|_| api::ApiError::bad_request("failed to convert Content-Type to string"))?
                         .to_owned(); // Convert to an owned String
 
                     println!("Content-Type: {}", content_type);
                 
                     let boundary_parts: Vec<&str> = content_type.split("boundary=").collect();
                     let boundary = boundary_parts.get(1)
-                        .ok_or_else(|| anyhow::anyhow!("upload fail, no boundary found in POST content type"))?;
+                        .ok_or_else(|| api::ApiError::bad_request("upload fail, no boundary found in POST content type"))?;
 
                     println!("Boundary: {}", boundary);
                 
@@ -200,14 +1030,28 @@ fn handle_http_request(
                     let mut name = String::new();
                     let mut tag_key = String::new();
                     let mut song_data = Vec::new();
+                    let mut lyrics = String::new();
+                    let mut cue_sheet = String::new();
 
 
 
-                    while let Some(mut field) = multipart.read_entry().map_err(|e| anyhow::anyhow!("Error reading multipart entry: {:?}", e))? {
+                    while let Some(mut field) = multipart.read_entry().map_err(|e| api::ApiError::bad_request(format!("Error reading multipart entry: {:?}", e)))? {
                         println!("Processing field: {}", field.headers.name);
                         match field.headers.name.as_ref() {
                             "name" => { field.data.read_to_string(&mut name)?; }
                             "tag" => { field.data.read_to_string(&mut tag_key)?; }
+                            // an .lrc file or plain text, replacing any lyrics in the audio's tags
+                            "lyrics" => {
+                                let mut bytes = Vec::new();
+                                field.data.read_to_end(&mut bytes)?;
+                                lyrics = String::from_utf8_lossy(&bytes).into_owned();
+                            }
+                            // a .cue sheet splitting the file into tracks
+                            "cue" => {
+                                let mut bytes = Vec::new();
+                                field.data.read_to_end(&mut bytes)?;
+                                cue_sheet = String::from_utf8_lossy(&bytes).into_owned();
+                            }
                             "file" => { 
                                 if let Some(filename) = field.headers.filename.clone() {
                                     field.data.read_to_end(&mut song_data)?;
@@ -220,29 +1064,26 @@ fn handle_http_request(
                         }
                     }
 
-                    if name.is_empty() {
-                        println!("Error: 'name' field is missing.");
-                    }
-                    if tag_key.is_empty() {
-                        println!("Error: 'tag' field is missing.");
+                    // empty name/tag fall back to the file's own title/genre tags
+                    let tag = Tag { key: tag_key.clone(), name: Some(tag_key) };
+                    let mut song = match ingest::song_from_upload(&name, tag, song_data, song_db.settings.max_upload_bytes) {
+                        Ok(song) => song,
+                        Err(e) => {
+                            println!("Rejected upload: {}", e);
+                            send_ingest_error(&e)?;
+                            return Ok(());
+                        }
+                    };
+
+                    if let Some(lyrics) = lyrics::Lyrics::parse(&lyrics) {
+                        song.lyrics = Some(lyrics);
                     }
-                    if song_data.is_empty() {
-                        println!("Error: 'file' field is missing.");
-                    }
-                    
-                    if name.is_empty() || tag_key.is_empty() || song_data.is_empty() {
-                        send_response(StatusCode::BAD_REQUEST, None, b"Missing required fields for song upload".to_vec());
-                        return Ok(());
+                    if let (false, Some(properties)) = (cue_sheet.trim().is_empty(), &song.properties) {
+                        song.chapters = chapters::close(chapters::parse_cue(&cue_sheet), properties.duration_ms);
                     }
 
-                    println!("Creating song with name: {}, tag: {}, data size: {}", name, tag_key, song_data.len());
-
-                    let song = Song {
-                        id: format!("{}.mp3", name),
-                        name,
-                        data: song_data,
-                        tag: Tag { key: tag_key.clone(), name: Some(tag_key) },
-                    };
+                    song.id = song_db.free_song_id(&song.id);
+                    println!("Creating song with name: {}, tag: {}, data size: {}", song.name, song.tag.key, song.data.len());
 
                     match song_db.add_song(song) {
                         Ok(_) => {
@@ -254,6 +1095,189 @@ fn handle_http_request(
                         }
                     }
                 }
+                ("GET", "/podcasts") => {
+                    send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&song_db.podcasts)?);
+                }
+                ("POST", "/podcasts") => {
+                    // a Podcast as JSON publishes the tag, an empty body or null unpublishes it
+                    let tag_key = request.query_params().get("tag").ok_or_else(|| api::ApiError::bad_request("No tag provided"))?;
+                    let body = get_blob().map(|blob| blob.bytes).unwrap_or_default();
+                    let podcast = if body.iter().all(u8::is_ascii_whitespace) {
+                        None
+                    } else {
+                        match serde_json::from_slice::<Option<podcast::Podcast>>(&body) {
+                            Ok(podcast) => podcast,
+                            Err(e) => {
+                                send_response(StatusCode::BAD_REQUEST, None, format!("Invalid podcast: {}", e).into_bytes());
+                                return Ok(());
+                            }
+                        }
+                    };
+                    match song_db.set_podcast(tag_key, podcast) {
+                        Ok(()) => {
+                            send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&song_db.podcasts)?);
+                            push_update_via_ws(ws_channels, "Podcasts updated");
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::NOT_FOUND, None, format!("Failed to publish podcast: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("GET", "/playlists") => {
+                    if catalog_not_modified(&request, song_db) {
+                        return Ok(());
+                    }
+                    send_catalog_json(song_db, serde_json::to_vec(&song_db.list_playlists())?);
+                }
+                ("POST", "/playlists") => {
+                    // a PlaylistEdit as JSON; with ?id= it changes that playlist, otherwise it creates one
+                    let body = get_blob().map(|blob| blob.bytes).unwrap_or_default();
+                    let edit = match serde_json::from_slice::<playlist::PlaylistEdit>(&body) {
+                        Ok(edit) => edit,
+                        Err(e) => {
+                            send_response(StatusCode::BAD_REQUEST, None, format!("Invalid playlist: {}", e).into_bytes());
+                            return Ok(());
+                        }
+                    };
+                    let id = request.query_params().get("id").cloned();
+                    match song_db.save_playlist(id.as_deref(), edit) {
+                        Ok(playlist) => {
+                            send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&playlist)?);
+                            push_update_via_ws(ws_channels, "Playlists updated");
+                        }
+                        Err(e) => {
+                            let status = if id.is_some_and(|id| !song_db.playlists.contains_key(&id)) { StatusCode::NOT_FOUND } else { StatusCode::BAD_REQUEST };
+                            send_response(status, None, format!("Failed to save playlist: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("POST", "/delete_playlist") => {
+                    let id = request.query_params().get("id").ok_or_else(|| api::ApiError::bad_request("No playlist ID provided"))?;
+                    match song_db.delete_playlist(id) {
+                        Ok(()) => {
+                            send_response(StatusCode::OK, None, b"Playlist deleted".to_vec());
+                            push_update_via_ws(ws_channels, "Playlists updated");
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::NOT_FOUND, None, format!("Failed to delete playlist: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("GET", "/export_playlist") => {
+                    // one of ?playlist=id, ?tag=key or a search (?q= and/or filters), as ?format=m3u8|pls|xspf
+                    let params = request.query_params();
+                    let format = match params.get("format") {
+                        Some(name) => match PlaylistFormat::from_name(name) {
+                            Some(format) => format,
+                            None => {
+                                send_response(StatusCode::BAD_REQUEST, None, format!("Unknown playlist format: {}", name).into_bytes());
+                                return Ok(());
+                            }
+                        },
+                        None => PlaylistFormat::M3u8,
+                    };
+                    let filter = match SongFilter::from_query(params) {
+                        Ok(filter) => filter,
+                        Err(e) => {
+                            send_response(StatusCode::BAD_REQUEST, None, e.into_bytes());
+                            return Ok(());
+                        }
+                    };
+                    let (name, songs): (String, Vec<&Song>) = if let Some(playlist_id) = params.get("playlist") {
+                        let Some(playlist) = song_db.playlists.get(playlist_id) else {
+                            send_response(StatusCode::NOT_FOUND, None, format!("No playlist {}", playlist_id).into_bytes());
+                            return Ok(());
+                        };
+                        (playlist.name.clone(), song_db.playlist_songs(playlist))
+                    } else if let Some(tag_key) = params.get("tag") {
+                        let Some(tag_songs) = song_db.songs.get(tag_key) else {
+                            send_response(StatusCode::NOT_FOUND, None, format!("No tag {}", tag_key).into_bytes());
+                            return Ok(());
+                        };
+                        let name = tag_songs.first().and_then(|song| song.tag.name.clone()).unwrap_or_else(|| tag_key.clone());
+                        (name, tag_songs.iter().filter(|song| filter.matches(song)).collect())
+                    } else if params.contains_key("q") || !filter.is_empty() {
+                        let query = params.get("q").cloned().unwrap_or_default();
+                        let songs = search::search(song_db, &query, &filter)
+                            .iter()
+                            .filter_map(|hit| song_db.get_song(&hit.song.id))
+                            .collect();
+                        (format!("Search: {}", query.trim()), songs)
+                    } else {
+                        send_response(StatusCode::BAD_REQUEST, None, b"Give a playlist, tag or search to export".to_vec());
+                        return Ok(());
+                    };
+                    let document = playlist::export(format, &name, &songs, &links::base_url(&request)?);
+                    // header values are ASCII only
+                    let file_name: String = name.chars()
+                        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.') { c } else { '_' })
+                        .collect();
+                    let mut headers = HashMap::new();
+                    headers.insert("Content-Type".to_string(), format!("{}; charset=utf-8", format.mime_type()));
+                    headers.insert("Content-Disposition".to_string(), format!("attachment; filename=\"{}.{}\"", file_name.trim(), format.extension()));
+                    send_response(StatusCode::OK, Some(headers), document.into_bytes());
+                }
+                ("POST", "/import_playlist") => {
+                    // the playlist file is the body; ?name= overrides the name it carries, ?format= the detected format
+                    let text = get_blob().map(|blob| String::from_utf8_lossy(&blob.bytes).into_owned()).unwrap_or_default();
+                    let format = match request.query_params().get("format") {
+                        Some(name) => match PlaylistFormat::from_name(name) {
+                            Some(format) => Some(format),
+                            None => {
+                                send_response(StatusCode::BAD_REQUEST, None, format!("Unknown playlist format: {}", name).into_bytes());
+                                return Ok(());
+                            }
+                        },
+                        None => None,
+                    };
+                    let name = request.query_params().get("name").cloned();
+                    match song_db.import_playlist(name, format, &text) {
+                        Ok(summary) => {
+                            send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&summary)?);
+                            push_update_via_ws(ws_channels, "Playlists updated");
+                        }
+                        Err(e) => {
+                            send_response(StatusCode::BAD_REQUEST, None, format!("Failed to import playlist: {}", e).into_bytes());
+                        }
+                    }
+                }
+                ("GET", "/subsonic_password") => {
+                    let body = serde_json::json!({ "enabled": song_db.subsonic_password.is_some(), "username": our.node });
+                    send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&body)?);
+                }
+                ("POST", "/subsonic_password") => {
+                    // the password is the body; an empty body turns the Subsonic API off
+                    let password = get_blob().map(|blob| String::from_utf8_lossy(&blob.bytes).trim().to_string());
+                    song_db.set_subsonic_password(password);
+                    let body = serde_json::json!({ "enabled": song_db.subsonic_password.is_some(), "username": our.node });
+                    send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), serde_json::to_vec(&body)?);
+                }
+                (method, api_path) if api_path.starts_with(api::PREFIX) => {
+                    let body = get_blob().map(|blob| blob.bytes);
+                    let result = api::handle(method, api_path, request.query_params(), body, song_db);
+                    if let Ok(api::Reply { update: Some(update), .. }) = &result {
+                        push_update_via_ws(ws_channels, update);
+                    }
+                    send_api_response(&result);
+                }
+                (_, rest_path) if rest_path.starts_with(subsonic::PREFIX) => {
+                    handle_subsonic_request(our, &request, song_db)?;
+                }
+                ("GET", feed_path) if feed_path.starts_with(podcast::FEED_PREFIX) => {
+                    let published = podcast::tag_from_path(feed_path)
+                        .and_then(|tag_key| Some((song_db.podcasts.get(&tag_key)?, tag_key)));
+                    let Some((podcast, tag_key)) = published else {
+                        send_response(StatusCode::NOT_FOUND, None, b"Feed not found".to_vec());
+                        return Ok(());
+                    };
+                    if catalog_not_modified(&request, song_db) {
+                        return Ok(());
+                    }
+                    let feed = podcast::feed(song_db, &tag_key, podcast, &links::base_url(&request)?);
+                    let mut headers = catalog_validators(song_db).headers(stream::CATALOG_CACHE_CONTROL);
+                    headers.insert("Content-Type".to_string(), "application/rss+xml; charset=utf-8".to_string());
+                    send_response(StatusCode::OK, Some(headers), feed.into_bytes());
+                }
                 _ => {
                     send_response(StatusCode::NOT_FOUND, None, b"Not Found".to_vec());
                 }
@@ -271,7 +1295,277 @@ fn handle_http_request(
     Ok(())
 }
 
+fn send_ingest_error(error: &ingest::IngestError) -> anyhow::Result<()> {
+    let mut body = serde_json::to_value(error)?;
+    body["message"] = serde_json::Value::String(error.to_string());
+    send_response(
+        error.status(),
+        Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])),
+        serde_json::to_vec(&body)?,
+    );
+    Ok(())
+}
+
+fn import_directory(
+    song_db: &mut SongDb,
+    ws_channels: &HashSet<u32>,
+    path: &str,
+) -> anyhow::Result<structs::DirectoryImportSummary> {
+    let summary = dir_import::import_directory(song_db, path, |progress| {
+        push_event_via_ws(ws_channels, "import_progress", serde_json::json!(progress));
+    })?;
+    push_event_via_ws(ws_channels, "import_finished", serde_json::json!(summary));
+    Ok(summary)
+}
+
+/// Whatever went wrong, the client gets an answer instead of waiting for a timeout. Routes fail
+/// before answering almost always, on a missing parameter or a body that doesn't parse.
+fn answer_failed_http_request(body: &[u8], error: &anyhow::Error) {
+    let Ok(HttpServerRequest::Http(request)) = serde_json::from_slice::<HttpServerRequest>(body) else {
+        return;
+    };
+    let path = request.path().unwrap_or_default();
+    if path.starts_with(subsonic::PREFIX) {
+        let format = subsonic::Format::from_query(request.query_params());
+        send_subsonic_response(&format, Err(subsonic::Error::generic(error.to_string())));
+        return;
+    }
+    let error = api::ApiError::from_anyhow(error);
+    if path.starts_with(api::PREFIX) {
+        send_api_response(&Err(error));
+    } else {
+        send_response(error.status, None, error.message.into_bytes());
+    }
+}
+
+fn send_api_response(result: &Result<api::Reply, api::ApiError>) {
+    let (status, body) = api::envelope(result);
+    send_response(status, Some(HashMap::from([("Content-Type".to_string(), "application/json".to_string())])), body);
+}
+
+/// Subsonic answers everything, failures included, with 200 and a response document
+fn handle_subsonic_request(our: &Address, request: &IncomingHttpRequest, song_db: &mut SongDb) -> anyhow::Result<()> {
+    let params = request.query_params();
+    let format = subsonic::Format::from_query(params);
+    let path = request.path()?;
+    let method = subsonic::method(&path).unwrap_or_default();
+    let reply = match subsonic::authenticate(params, &our.node, song_db.subsonic_password.as_deref()) {
+        Err(e) => Err(e),
+        Ok(()) => match method {
+            // no transcoding, clients get the file as uploaded
+            "stream" | "download" => match params.get("id") {
+                Some(id) if song_db.get_song(id).is_some() => return stream_audio(request, song_db),
+                Some(id) => Err(subsonic::Error::not_found("Song", id)),
+                None => Err(subsonic::Error::missing("id")),
+            },
+            "getCoverArt" => match params.get("id") {
+                Some(id) => match subsonic::cover_art_id(song_db, id) {
+                    Some(artwork_id) => {
+                        send_artwork(request, song_db, &artwork_id);
+                        return Ok(());
+                    }
+                    None => Err(subsonic::Error::not_found("Cover art", id)),
+                },
+                None => Err(subsonic::Error::missing("id")),
+            },
+            method => subsonic::handle(method, params, &our.node, song_db),
+        },
+    };
+    send_subsonic_response(&format, reply);
+    Ok(())
+}
+
+fn send_subsonic_response(format: &subsonic::Format, reply: subsonic::Reply) {
+    let (content_type, body) = subsonic::render(format, reply);
+    send_response(StatusCode::OK, Some(HashMap::from([("Content-Type".to_string(), content_type.to_string())])), body);
+}
+
+fn send_artwork(request: &IncomingHttpRequest, song_db: &SongDb, artwork_id: &str) {
+    // artwork files are named by their content hash, so the id doubles as a strong ETag
+    let etag = format!("\"{}\"", artwork_id);
+    let not_modified = request.headers()
+        .get("If-None-Match")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+
+    let mut headers = HashMap::new();
+    headers.insert("ETag".to_string(), etag.clone());
+    headers.insert("Cache-Control".to_string(), "private, max-age=31536000, immutable".to_string());
+    if not_modified {
+        send_response(StatusCode::NOT_MODIFIED, Some(headers), Vec::new());
+        return;
+    }
+    match song_db.get_artwork(artwork_id) {
+        Ok((mime, data)) => {
+            headers.insert("Content-Type".to_string(), mime.to_string());
+            headers.insert("Content-Length".to_string(), data.len().to_string());
+            send_response(StatusCode::OK, Some(headers), data);
+        }
+        Err(_) => {
+            send_response(StatusCode::NOT_FOUND, None, b"Artwork not found".to_vec());
+        }
+    }
+}
+
+/// Serves a song file, honoring Range and conditional requests. With `gapless=1` an MP3 with
+/// encoder delay/padding info is served with whole frames of that silence trimmed off, and the
+/// rest given in X-Start-Ms and X-End-Ms, and to the sample in X-Encoder-Delay/X-Encoder-Padding.
+///
+/// A chapter's track id ("<song id>#<n>"), or `start` and `end` in ms, serves just that window
+/// of the song. Formats that can't be cut get the whole file with the window in X-Start-Ms and
+/// X-End-Ms for the player to seek to.
+fn stream_audio(request: &IncomingHttpRequest, song_db: &SongDb) -> anyhow::Result<()> {
+    let query_params = request.query_params();
+    let id = query_params.get("id").ok_or_else(|| api::ApiError::bad_request("No song ID provided"))?;
+    let gapless = query_params.get("gapless").is_some_and(|value| value == "1" || value == "true");
+    let trim = query_params.get("trim").is_some_and(|value| value == "1" || value == "true");
+    let (song_id, window) = match song_db.get_track(id) {
+        Some((song, chapter)) => (song.id.as_str(), Some((chapter.start_ms, chapter.end_ms))),
+        None => {
+            let ms = |name: &str| query_params.get(name).and_then(|value| value.parse::<u64>().ok());
+            let silence = song_db.get_song(id).and_then(|song| song.silence.as_ref()).filter(|_| trim);
+            // a window is kept within the song, which also keeps client-chosen times small
+            let duration_ms = song_db.get_song(id).and_then(|song| song.properties.as_ref()).map(|properties| properties.duration_ms);
+            let window = match (ms("start"), ms("end")) {
+                (None, None) => silence.map(|silence| (silence.trim_start_ms, silence.trim_end_ms)),
+                (start, end) => {
+                    let end = match (end, duration_ms) {
+                        (Some(end), Some(duration_ms)) => end.min(duration_ms),
+                        (end, duration_ms) => end.or(duration_ms).unwrap_or(u64::MAX),
+                    };
+                    Some((start.unwrap_or(0).min(end), end))
+                }
+            };
+            (id.as_str(), window)
+        }
+    };
+    let file_path = format!("{}/{}", song_db.vfs_dir_path, song_id);
+    let song = song_db.get_song(song_id);
+
+    let format = song
+        .map(|song| song.format)
+        .or_else(|| song_id.rsplit_once('.').and_then(|(_, ext)| AudioFormat::from_extension(ext)))
+        .unwrap_or_default();
+
+    // files outside the catalog have nothing to validate against and are just served
+    let validators = song.map(|song| {
+        let tag = match window {
+            Some((start, end)) => format!("{}-{}-{}", song.content_hash, start, end),
+            None if gapless => format!("{}-gapless", song.content_hash),
+            None => song.content_hash.clone(),
+        };
+        stream::Validators::new(&tag, song.uploaded_at)
+    });
+    if let Some(validators) = &validators {
+        if validators.not_modified(request_header(request, "If-None-Match").as_deref(), request_header(request, "If-Modified-Since").as_deref()) {
+            send_response(StatusCode::NOT_MODIFIED, Some(validators.headers(stream::AUDIO_CACHE_CONTROL)), Vec::new());
+            return Ok(());
+        }
+    }
+
+    let file = match open_file(&file_path, false, None) {
+        Ok(file) => file,
+        Err(_) => {
+            send_response(StatusCode::NOT_FOUND, None, b"Audio file not found".to_vec());
+            return Ok(());
+        }
+    };
+    let mut headers = validators
+        .as_ref()
+        .map(|validators| validators.headers(stream::AUDIO_CACHE_CONTROL))
+        .unwrap_or_default();
+
+    let mut body = stream::Body::File { path: file_path.clone(), size: file.metadata()?.len };
+    if let Some((start, end)) = window {
+        match window::cut(format, &file.read()?, start, end) {
+            Some(cut) => body = stream::Body::Memory(cut),
+            None => {
+                headers.insert("X-Start-Ms".to_string(), start.to_string());
+                if end != u64::MAX {
+                    headers.insert("X-End-Ms".to_string(), end.to_string());
+                }
+            }
+        }
+    } else {
+        let gapless_info = song
+            .filter(|song| gapless && song.format == AudioFormat::Mp3)
+            .and_then(|song| song.metadata.gapless.as_ref());
+        if let Some(gapless_info) = gapless_info {
+            if let Some(trim) = gapless::trim_mp3(&file.read()?, gapless_info) {
+                // less than a frame of delay and padding is left in; this is where to cut it
+                headers.insert("X-Start-Ms".to_string(), trim.start_ms().to_string());
+                headers.insert("X-End-Ms".to_string(), trim.end_ms().to_string());
+                headers.insert("X-Encoder-Delay".to_string(), trim.delay.to_string());
+                headers.insert("X-Encoder-Padding".to_string(), trim.padding.to_string());
+                if let Some(trimmed) = trim.data {
+                    body = stream::Body::Memory(trimmed);
+                }
+            }
+        }
+    }
+    let len = body.size();
+
+    headers.insert("Content-Type".to_string(), format.mime_type().to_string());
+    headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+
+    let range_applies = validators.as_ref()
+        .is_none_or(|validators| validators.range_applies(request_header(request, "If-Range").as_deref()));
+    let range = match request_header(request, "Range") {
+        Some(value) if range_applies => stream::parse_range(&value, len),
+        _ => Ok(None),
+    };
+    match range {
+        Ok(Some(range)) => {
+            let data = body.read(Some(range))?;
+            headers.insert("Content-Range".to_string(), range.content_range(len));
+            headers.insert("Content-Length".to_string(), data.len().to_string());
+            send_response(StatusCode::PARTIAL_CONTENT, Some(headers), data);
+        }
+        Ok(None) => {
+            let data = body.read(None)?;
+            headers.insert("Content-Length".to_string(), data.len().to_string());
+            send_response(StatusCode::OK, Some(headers), data);
+        }
+        Err(stream::RangeNotSatisfiable) => {
+            headers.insert("Content-Range".to_string(), format!("bytes */{}", len));
+            send_response(StatusCode::RANGE_NOT_SATISFIABLE, Some(headers), Vec::new());
+        }
+    }
+    Ok(())
+}
+
+fn request_header(request: &IncomingHttpRequest, name: &str) -> Option<String> {
+    request.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string)
+}
+
+fn catalog_validators(song_db: &SongDb) -> stream::Validators {
+    stream::Validators::new(&format!("catalog-{}-{}", song_db.catalog_modified_at, song_db.catalog_version), song_db.catalog_modified_at)
+}
+
+/// Answers with 304 and returns true when the client's cached listing is still current
+fn catalog_not_modified(request: &IncomingHttpRequest, song_db: &SongDb) -> bool {
+    let validators = catalog_validators(song_db);
+    let not_modified = validators.not_modified(
+        request_header(request, "If-None-Match").as_deref(),
+        request_header(request, "If-Modified-Since").as_deref(),
+    );
+    if not_modified {
+        send_response(StatusCode::NOT_MODIFIED, Some(validators.headers(stream::CATALOG_CACHE_CONTROL)), Vec::new());
+    }
+    not_modified
+}
+
+fn send_catalog_json(song_db: &SongDb, body: Vec<u8>) {
+    let mut headers = catalog_validators(song_db).headers(stream::CATALOG_CACHE_CONTROL);
+    headers.insert("Content-Type".to_string(), "application/json".to_string());
+    send_response(StatusCode::OK, Some(headers), body);
+}
+
 fn push_update_via_ws(ws_channels: &HashSet<u32>, update: &str) {
+    push_event_via_ws(ws_channels, "update", serde_json::json!(update));
+}
+
+fn push_event_via_ws(ws_channels: &HashSet<u32>, event_type: &str, data: serde_json::Value) {
     for &channel_id in ws_channels {
         send_ws_push(
             channel_id,
@@ -279,8 +1573,8 @@ fn push_update_via_ws(ws_channels: &HashSet<u32>, update: &str) {
             LazyLoadBlob {
                 mime: Some("application/json".to_string()),
                 bytes: serde_json::json!({
-                    "type": "update",
-                    "data": update
+                    "type": event_type,
+                    "data": data
                 })
                 .to_string()
                 .into_bytes(),
@@ -0,0 +1,334 @@
+// EBU R128 loudness as defined in ITU-R BS.1770-4: K-weighted, gated integrated loudness plus a
+// 4x oversampled true peak. https://tech.ebu.ch/docs/tech/tech3341.pdf
+//
+// Gating blocks are kept as a histogram rather than a list so that the loudness of a whole
+// tag can later be computed from its tracks' histograms without decoding them again. The
+// histogram sidecar is "LHST", a version byte, the entry count as u32 LE, then sparse
+// (bin u16 LE, count u32 LE) pairs.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::PI;
+use std::ops::ControlFlow;
+
+use crate::decode;
+use crate::metadata::AudioFormat;
+
+/// ReplayGain 2.0 normalizes to -18 LUFS
+pub const REFERENCE_LUFS: f64 = -18.0;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = 10.0;
+const BIN_LU: f64 = 0.1;
+/// -70 to +10 LUFS, anything louder goes in the top bin
+const HISTOGRAM_BINS: usize = 800;
+
+const MAGIC: &[u8; 4] = b"LHST";
+const VERSION: u8 = 1;
+
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+const TRUE_PEAK_TAPS: usize = 12;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    pub integrated_lufs: f64,
+    /// linear, 1.0 is full scale
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    pub fn gain_db(&self) -> f64 {
+        REFERENCE_LUFS - self.integrated_lufs
+    }
+}
+
+/// Count of 400 ms gating blocks per 0.1 LU, starting at the absolute gate
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockHistogram {
+    pub bins: Vec<u32>,
+}
+
+impl Default for BlockHistogram {
+    fn default() -> Self {
+        BlockHistogram { bins: vec![0; HISTOGRAM_BINS] }
+    }
+}
+
+impl BlockHistogram {
+    fn add(&mut self, lufs: f64) {
+        if lufs <= ABSOLUTE_GATE_LUFS {
+            return;
+        }
+        let bin = (((lufs - ABSOLUTE_GATE_LUFS) / BIN_LU) as usize).min(HISTOGRAM_BINS - 1);
+        self.bins[bin] += 1;
+    }
+
+    /// Gated integrated loudness, None when nothing is above the absolute gate
+    pub fn integrated_lufs(&self) -> Option<f64> {
+        let mean_energy = |from_bin: usize| {
+            let (energy, blocks) = self.bins.iter().enumerate().skip(from_bin)
+                .fold((0.0, 0u64), |(energy, blocks), (bin, &count)| {
+                    (energy + count as f64 * to_energy(bin_lufs(bin)), blocks + count as u64)
+                });
+            if blocks == 0 { None } else { Some(energy / blocks as f64) }
+        };
+
+        let ungated = to_lufs(mean_energy(0)?);
+        let relative_gate = ungated - RELATIVE_GATE_LU;
+        let from_bin = ((relative_gate - ABSOLUTE_GATE_LUFS) / BIN_LU).ceil().max(0.0) as usize;
+        Some(to_lufs(mean_energy(from_bin)?))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let entries: Vec<(u16, u32)> = self.bins.iter().enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(bin, &count)| (bin as u16, count))
+            .collect();
+        let mut bytes = Vec::with_capacity(9 + entries.len() * 6);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        for (bin, count) in entries {
+            bytes.extend_from_slice(&bin.to_le_bytes());
+            bytes.extend_from_slice(&count.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<BlockHistogram> {
+        if bytes.get(0..4)? != MAGIC || *bytes.get(4)? != VERSION {
+            return None;
+        }
+        let count = u32::from_le_bytes(bytes.get(5..9)?.try_into().ok()?) as usize;
+        let body = bytes.get(9..9 + count.checked_mul(6)?)?;
+        let mut histogram = BlockHistogram::default();
+        for entry in body.chunks_exact(6) {
+            let bin = u16::from_le_bytes([entry[0], entry[1]]) as usize;
+            *histogram.bins.get_mut(bin)? = u32::from_le_bytes([entry[2], entry[3], entry[4], entry[5]]);
+        }
+        Some(histogram)
+    }
+}
+
+/// A tag's tracks' histograms merged, kept up to date one track at a time so that album
+/// loudness doesn't mean re-reading every histogram in the tag whenever one track changes
+#[derive(Debug, Default)]
+pub struct AlbumHistogram {
+    /// tracks merged in as sparse (bin, count), None where the histogram couldn't be read
+    tracks: HashMap<String, Option<Vec<(usize, u32)>>>,
+    merged: BlockHistogram,
+}
+
+impl AlbumHistogram {
+    pub fn song_ids(&self) -> impl Iterator<Item = &String> {
+        self.tracks.keys()
+    }
+
+    pub fn contains(&self, song_id: &str) -> bool {
+        self.tracks.contains_key(song_id)
+    }
+
+    pub fn has_histogram(&self, song_id: &str) -> bool {
+        matches!(self.tracks.get(song_id), Some(Some(_)))
+    }
+
+    pub fn insert(&mut self, song_id: &str, histogram: Option<&BlockHistogram>) {
+        self.remove(song_id);
+        let sparse: Option<Vec<(usize, u32)>> = histogram.map(|histogram| {
+            histogram.bins.iter().enumerate()
+                .filter(|(_, &count)| count > 0)
+                .map(|(bin, &count)| (bin, count))
+                .collect()
+        });
+        for &(bin, count) in sparse.iter().flatten() {
+            self.merged.bins[bin] += count;
+        }
+        self.tracks.insert(song_id.to_string(), sparse);
+    }
+
+    pub fn remove(&mut self, song_id: &str) {
+        if let Some(Some(sparse)) = self.tracks.remove(song_id) {
+            for (bin, count) in sparse {
+                self.merged.bins[bin] -= count;
+            }
+        }
+    }
+
+    pub fn integrated_lufs(&self) -> Option<f64> {
+        self.merged.integrated_lufs()
+    }
+}
+
+/// Decodes the song and measures it
+pub fn analyze(format: AudioFormat, data: Vec<u8>) -> anyhow::Result<(Loudness, BlockHistogram)> {
+    let mut meter: Option<Meter> = None;
+    decode::for_each_block(format, data, |samples, channels, sample_rate| {
+        let meter = meter.get_or_insert_with(|| Meter::new(channels, sample_rate));
+        // a mid-stream format change is rare enough that we just measure the first format
+        if meter.channels == channels && meter.sample_rate == sample_rate {
+            meter.process(samples);
+        }
+        ControlFlow::Continue(())
+    })?;
+    let meter = meter.ok_or_else(|| anyhow::anyhow!("No audio could be decoded"))?;
+    let integrated_lufs = meter.histogram.integrated_lufs()
+        .ok_or_else(|| anyhow::anyhow!("Song is silent"))?;
+    Ok((Loudness { integrated_lufs, true_peak: meter.true_peak }, meter.histogram))
+}
+
+struct Meter {
+    channels: usize,
+    sample_rate: u32,
+    /// K-weighting: high shelf then high pass, per channel
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    /// 100 ms sub-blocks; a gating block is the last four, giving the 75% overlap
+    sub_block_len: usize,
+    sub_block_pos: usize,
+    sub_block_energy: f64,
+    recent: VecDeque<f64>,
+    histogram: BlockHistogram,
+    true_peak: f64,
+    interpolator: Vec<[f64; TRUE_PEAK_TAPS]>,
+    history: Vec<VecDeque<f64>>,
+}
+
+impl Meter {
+    fn new(channels: usize, sample_rate: u32) -> Self {
+        let rate = sample_rate as f64;
+        Meter {
+            channels,
+            sample_rate,
+            filters: (0..channels).map(|_| [Biquad::high_shelf(rate), Biquad::high_pass(rate)]).collect(),
+            weights: channel_weights(channels),
+            sub_block_len: (sample_rate as usize / 10).max(1),
+            sub_block_pos: 0,
+            sub_block_energy: 0.0,
+            recent: VecDeque::with_capacity(4),
+            histogram: BlockHistogram::default(),
+            true_peak: 0.0,
+            interpolator: interpolator(),
+            history: (0..channels).map(|_| VecDeque::from(vec![0.0; TRUE_PEAK_TAPS])).collect(),
+        }
+    }
+
+    fn process(&mut self, samples: &[f32]) {
+        // at 4x 44.1/48 kHz rates and up the samples themselves are close enough
+        let oversample = self.sample_rate < 176_400;
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let sample = sample as f64;
+                let [shelf, pass] = &mut self.filters[channel];
+                let weighted = pass.process(shelf.process(sample));
+                self.sub_block_energy += self.weights[channel] * weighted * weighted;
+
+                self.true_peak = self.true_peak.max(sample.abs());
+                if oversample {
+                    let history = &mut self.history[channel];
+                    history.pop_back();
+                    history.push_front(sample);
+                    for phase in &self.interpolator[1..] {
+                        let value: f64 = phase.iter().zip(history.iter()).map(|(c, x)| c * x).sum();
+                        self.true_peak = self.true_peak.max(value.abs());
+                    }
+                }
+            }
+
+            self.sub_block_pos += 1;
+            if self.sub_block_pos == self.sub_block_len {
+                if self.recent.len() == 4 {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(self.sub_block_energy);
+                if self.recent.len() == 4 {
+                    let mean = self.recent.iter().sum::<f64>() / (4 * self.sub_block_len) as f64;
+                    self.histogram.add(to_lufs(mean));
+                }
+                self.sub_block_pos = 0;
+                self.sub_block_energy = 0.0;
+            }
+        }
+    }
+}
+
+/// Left, right and centre count fully, surrounds get +1.5 dB and the LFE isn't counted
+fn channel_weights(channels: usize) -> Vec<f64> {
+    match channels {
+        5 => vec![1.0, 1.0, 1.0, 1.41, 1.41],
+        6 => vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41],
+        _ => vec![1.0; channels],
+    }
+}
+
+/// Hann-windowed sinc coefficients for each oversampling phase; phase 0 is the sample itself
+fn interpolator() -> Vec<[f64; TRUE_PEAK_TAPS]> {
+    let half = (TRUE_PEAK_TAPS / 2) as f64;
+    (0..TRUE_PEAK_OVERSAMPLING).map(|phase| {
+        let mut taps = [0.0; TRUE_PEAK_TAPS];
+        for (tap, coefficient) in taps.iter_mut().enumerate() {
+            let t = tap as f64 - half + phase as f64 / TRUE_PEAK_OVERSAMPLING as f64;
+            let sinc = if t == 0.0 { 1.0 } else { (PI * t).sin() / (PI * t) };
+            let window = 0.5 * (1.0 + (PI * t / half).cos());
+            *coefficient = sinc * window;
+        }
+        taps
+    }).collect()
+}
+
+fn to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn to_energy(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+fn bin_lufs(bin: usize) -> f64 {
+    ABSOLUTE_GATE_LUFS + (bin as f64 + 0.5) * BIN_LU
+}
+
+/// Transposed direct form II biquad
+#[derive(Debug, Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    // BS.1770 gives the coefficients at 48 kHz only; these are the analog prototypes
+    // re-derived for any rate, as libebur128 does.
+    fn high_shelf(rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn high_pass(rate: f64) -> Self {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
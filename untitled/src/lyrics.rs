@@ -0,0 +1,130 @@
+// Song lyrics, either plain text or synced to the audio. Synced lyrics come from ID3 SYLT frames
+// or LRC text: "[mm:ss.xx]line", with any number of timestamps per line and an optional
+// "[offset:ms]" header. https://en.wikipedia.org/wiki/LRC_(file_format)
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Lyrics {
+    /// every line has a time when true, none do otherwise
+    pub synced: bool,
+    pub lines: Vec<LyricLine>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LyricLine {
+    /// from the start of the song
+    pub time_ms: Option<u32>,
+    pub text: String,
+}
+
+impl Lyrics {
+    /// LRC if any line has a timestamp, plain text otherwise. None if there's no text at all.
+    pub fn parse(text: &str) -> Option<Lyrics> {
+        let mut offset_ms = 0i64;
+        let mut synced_lines = Vec::new();
+        for line in text.lines() {
+            let (tags, text) = split_tags(line);
+            let mut times = Vec::new();
+            for tag in tags {
+                if let Some(time) = parse_timestamp(tag) {
+                    times.push(time);
+                } else if let Some(offset) = tag.strip_prefix("offset:") {
+                    offset_ms = offset.trim().parse().unwrap_or(0);
+                }
+            }
+            let text = strip_word_times(text);
+            synced_lines.extend(times.into_iter().map(|time| (time, text.clone())));
+        }
+
+        if !synced_lines.is_empty() {
+            // a positive offset makes the lyrics show up sooner
+            let mut lines: Vec<LyricLine> = synced_lines.into_iter()
+                .map(|(time, text)| LyricLine {
+                    time_ms: Some((time as i64).saturating_sub(offset_ms).clamp(0, u32::MAX as i64) as u32),
+                    text,
+                })
+                .collect();
+            lines.sort_by_key(|line| line.time_ms);
+            return Some(Lyrics { synced: true, lines });
+        }
+        Lyrics::unsynced(text)
+    }
+
+    /// Plain text, blank lines between verses are kept
+    pub fn unsynced(text: &str) -> Option<Lyrics> {
+        let text = text.trim();
+        if text.is_empty() {
+            return None;
+        }
+        let lines = text.lines()
+            .map(|line| LyricLine { time_ms: None, text: line.trim_end().to_string() })
+            .collect();
+        Some(Lyrics { synced: false, lines })
+    }
+
+    pub fn to_lrc(&self) -> String {
+        self.lines.iter()
+            .map(|line| match line.time_ms {
+                Some(ms) => format!("[{:02}:{:02}.{:02}]{}\n", ms / 60_000, ms / 1000 % 60, ms % 1000 / 10, line.text),
+                None => format!("{}\n", line.text),
+            })
+            .collect()
+    }
+
+    /// The line containing `needle` (lowercase), for showing why a search matched
+    pub fn find_line(&self, needle: &str) -> Option<&LyricLine> {
+        self.lines.iter().find(|line| line.text.to_lowercase().contains(needle))
+    }
+}
+
+/// Leading "[...]" tags and the text after them
+fn split_tags(line: &str) -> (Vec<&str>, &str) {
+    let mut tags = Vec::new();
+    let mut rest = line.trim_start();
+    while let Some(tag) = rest.strip_prefix('[') {
+        let Some(end) = tag.find(']') else {
+            break;
+        };
+        tags.push(&tag[..end]);
+        rest = tag[end + 1..].trim_start();
+    }
+    (tags, rest)
+}
+
+/// "mm:ss", "mm:ss.xx" or "mm:ss.xxx"; some writers use ':' before the fraction
+fn parse_timestamp(tag: &str) -> Option<u32> {
+    let (minutes, rest) = tag.trim().split_once(':')?;
+    let (seconds, fraction) = rest.split_once(['.', ':']).unwrap_or((rest, ""));
+    let minutes: u32 = minutes.parse().ok()?;
+    let seconds: u32 = seconds.parse().ok()?;
+    if seconds >= 60 || !fraction.chars().all(|c| c.is_ascii_digit()) || fraction.len() > 3 {
+        return None;
+    }
+    let fraction_ms = match fraction.len() {
+        0 => 0,
+        len => fraction.parse::<u32>().ok()? * 10u32.pow(3 - len as u32),
+    };
+    // the text is whatever the uploader wrote, so a minute count can be absurd
+    minutes.checked_mul(60_000)?.checked_add(seconds * 1000 + fraction_ms)
+}
+
+/// Enhanced LRC times individual words with "<mm:ss.xx>"; only line times are kept
+fn strip_word_times(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        match rest[start + 1..].find('>') {
+            Some(end) if parse_timestamp(&rest[start + 1..start + 1 + end]).is_some() => {
+                stripped.push_str(&rest[..start]);
+                rest = &rest[start + end + 2..];
+            }
+            _ => {
+                stripped.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    stripped.push_str(rest);
+    stripped.trim().to_string()
+}
@@ -0,0 +1,357 @@
+use serde::{Serialize, Deserialize};
+
+use crate::chapters::{self, ChapterMark};
+use crate::key::Key;
+use crate::lyrics::Lyrics;
+use crate::vorbis_comment::VorbisComments;
+use crate::{flac, id3, mp3, mp4, ogg, wav};
+
+/// Container/codec of an audio file, detected from its magic bytes
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum AudioFormat {
+    #[default]
+    Mp3,
+    Flac,
+    OggVorbis,
+    Opus,
+    Wav,
+    M4a,
+}
+
+impl AudioFormat {
+    pub fn detect(data: &[u8]) -> Option<AudioFormat> {
+        if data.get(4..8) == Some(b"ftyp") {
+            return Some(AudioFormat::M4a);
+        }
+        if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WAVE" {
+            return Some(AudioFormat::Wav);
+        }
+        if data.starts_with(b"OggS") {
+            return match ogg::codec(data)? {
+                ogg::Codec::Vorbis => Some(AudioFormat::OggVorbis),
+                ogg::Codec::Opus => Some(AudioFormat::Opus),
+            };
+        }
+        // FLAC and MP3 may both sit behind an ID3v2 tag
+        let audio = &data[id3::v2_tag_len(data)..];
+        if audio.starts_with(b"fLaC") {
+            return Some(AudioFormat::Flac);
+        }
+        if mp3::frames(data).next().is_some() {
+            return Some(AudioFormat::Mp3);
+        }
+        None
+    }
+
+    pub fn from_extension(extension: &str) -> Option<AudioFormat> {
+        match extension.to_ascii_lowercase().as_str() {
+            "mp3" => Some(AudioFormat::Mp3),
+            "flac" => Some(AudioFormat::Flac),
+            "ogg" | "oga" => Some(AudioFormat::OggVorbis),
+            "opus" => Some(AudioFormat::Opus),
+            "wav" => Some(AudioFormat::Wav),
+            "m4a" | "mp4" => Some(AudioFormat::M4a),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Flac => "flac",
+            AudioFormat::OggVorbis => "ogg",
+            AudioFormat::Opus => "opus",
+            AudioFormat::Wav => "wav",
+            AudioFormat::M4a => "m4a",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "audio/mpeg",
+            AudioFormat::Flac => "audio/flac",
+            AudioFormat::OggVorbis => "audio/ogg",
+            AudioFormat::Opus => "audio/ogg; codecs=opus",
+            AudioFormat::Wav => "audio/wav",
+            AudioFormat::M4a => "audio/mp4",
+        }
+    }
+}
+
+/// Descriptive tags read from the audio file itself at ingest
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct SongMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub track: Option<u32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub replay_gain: ReplayGain,
+    pub gapless: Option<Gapless>,
+    pub bpm: Option<f32>,
+    pub key: Option<Key>,
+}
+
+impl SongMetadata {
+    /// TBPM and friends are free text; some taggers write "128.00" or "0"
+    pub fn parse_bpm(text: &str) -> Option<f32> {
+        text.trim().replace(',', ".").parse::<f32>().ok().filter(|bpm| *bpm > 0.0 && bpm.is_finite())
+    }
+
+    /// Takes values from `other` for every field that is still empty
+    pub fn fill_missing(&mut self, other: SongMetadata) {
+        self.title = self.title.take().or(other.title);
+        self.artist = self.artist.take().or(other.artist);
+        self.album_artist = self.album_artist.take().or(other.album_artist);
+        self.album = self.album.take().or(other.album);
+        self.track = self.track.or(other.track);
+        self.year = self.year.or(other.year);
+        self.genre = self.genre.take().or(other.genre);
+        self.replay_gain.fill_missing(other.replay_gain);
+        self.gapless = self.gapless.take().or(other.gapless);
+        self.bpm = self.bpm.or(other.bpm);
+        self.key = self.key.or(other.key);
+    }
+}
+
+/// Silence the encoder added around the audio, from a LAME tag or an iTunSMPB comment
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Gapless {
+    pub encoder_delay: u32,
+    pub padding: u32,
+    /// samples of actual audio, when the source says
+    pub total_samples: Option<u64>,
+}
+
+impl Gapless {
+    /// iTunSMPB: space separated hex, a zero field then delay, padding and the original sample count
+    pub fn from_itunsmpb(value: &str) -> Option<Gapless> {
+        let fields: Vec<u64> = value.split_whitespace()
+            .map(|field| u64::from_str_radix(field, 16).ok())
+            .collect::<Option<_>>()?;
+        Some(Gapless {
+            encoder_delay: *fields.get(1)? as u32,
+            padding: *fields.get(2)? as u32,
+            total_samples: fields.get(3).copied().filter(|&samples| samples > 0),
+        })
+    }
+}
+
+/// Track and album gain in dB relative to the ReplayGain 2.0 reference of -18 LUFS, peaks linear
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ReplayGain {
+    pub track_gain_db: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain_db: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+    /// From REPLAYGAIN_* fields as found in ID3 TXXX frames, Vorbis comments and MP4 freeform atoms
+    pub fn from_fields<'a>(fields: impl Iterator<Item = (&'a str, &'a str)>) -> ReplayGain {
+        let mut replay_gain = ReplayGain::default();
+        for (key, value) in fields {
+            let number = || value.trim().trim_end_matches(|c: char| c.is_ascii_alphabetic()).trim().parse::<f32>().ok();
+            let slot = match key.to_ascii_uppercase().as_str() {
+                "REPLAYGAIN_TRACK_GAIN" => &mut replay_gain.track_gain_db,
+                "REPLAYGAIN_TRACK_PEAK" => &mut replay_gain.track_peak,
+                "REPLAYGAIN_ALBUM_GAIN" => &mut replay_gain.album_gain_db,
+                "REPLAYGAIN_ALBUM_PEAK" => &mut replay_gain.album_peak,
+                _ => continue,
+            };
+            if slot.is_none() {
+                *slot = number().filter(|number| number.is_finite());
+            }
+        }
+        replay_gain
+    }
+
+    pub fn fill_missing(&mut self, other: ReplayGain) {
+        self.track_gain_db = self.track_gain_db.or(other.track_gain_db);
+        self.track_peak = self.track_peak.or(other.track_peak);
+        self.album_gain_db = self.album_gain_db.or(other.album_gain_db);
+        self.album_peak = self.album_peak.or(other.album_peak);
+    }
+}
+
+/// Technical details of the audio stream, computed at ingest
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AudioProperties {
+    pub duration_ms: u64,
+    /// average over the whole stream
+    pub bitrate_kbps: u32,
+    pub bitrate_mode: BitrateMode,
+    pub sample_rate: u32,
+    pub channels: u8,
+    /// only known for MPEG audio
+    pub channel_mode: Option<ChannelMode>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum BitrateMode {
+    Cbr,
+    Vbr,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ChannelMode {
+    Stereo,
+    JointStereo,
+    DualChannel,
+    Mono,
+}
+
+/// Embedded cover art
+#[derive(Debug, Clone)]
+pub struct Picture {
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
+impl Picture {
+    /// The image's own magic bytes win over the MIME type (or ID3v2.2 format) the tag claims
+    pub fn new(claimed_mime: &str, data: &[u8]) -> Option<Picture> {
+        if data.is_empty() {
+            return None;
+        }
+        let mime = match image_mime_type(data) {
+            Some(mime) => mime.to_string(),
+            None => match claimed_mime.to_ascii_lowercase().as_str() {
+                "image/jpg" | "jpg" => "image/jpeg".to_string(),
+                "png" => "image/png".to_string(),
+                mime if mime.starts_with("image/") => mime.to_string(),
+                _ => return None,
+            },
+        };
+        Some(Picture { mime, data: data.to_vec() })
+    }
+
+    pub fn extension(&self) -> &str {
+        match self.mime.as_str() {
+            "image/jpeg" => "jpg",
+            "image/png" => "png",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            "image/bmp" => "bmp",
+            _ => "img",
+        }
+    }
+}
+
+pub fn image_mime_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if data.starts_with(b"BM") {
+        Some("image/bmp")
+    } else {
+        None
+    }
+}
+
+pub fn image_mime_from_extension(extension: &str) -> &'static str {
+    match extension {
+        "jpg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Picture type 3 is "Cover (front)" in both ID3 and FLAC; otherwise take the first one
+pub fn pick_cover(pictures: impl Iterator<Item = (u32, Picture)>) -> Option<Picture> {
+    let mut first = None;
+    for (picture_type, picture) in pictures {
+        if picture_type == 3 {
+            return Some(picture);
+        }
+        first.get_or_insert(picture);
+    }
+    first
+}
+
+/// Structural check that `data` really is a playable stream of `format`
+pub fn validate(format: AudioFormat, data: &[u8]) -> Result<(), String> {
+    match format {
+        AudioFormat::Mp3 => mp3::validate(data, 4, 64 * 1024),
+        AudioFormat::Flac => flac::validate(data),
+        // for the rest, being able to read the stream layout means the headers hang together
+        _ => match read_properties(format, data) {
+            Some(properties) if properties.duration_ms > 0 && properties.channels > 0 => Ok(()),
+            Some(_) => Err("stream is empty".to_string()),
+            None => Err("stream headers are missing or broken".to_string()),
+        },
+    }
+}
+
+pub fn read_metadata(format: AudioFormat, data: &[u8]) -> SongMetadata {
+    match format {
+        AudioFormat::Mp3 => {
+            // the encoder's own header is exact, iTunSMPB from the ID3 tag fills in otherwise
+            let mut metadata = id3::read_metadata(data);
+            metadata.gapless = mp3::read_gapless(data).or(metadata.gapless);
+            metadata
+        }
+        AudioFormat::Flac => flac::read_metadata(data),
+        AudioFormat::OggVorbis | AudioFormat::Opus => ogg::read_metadata(data),
+        AudioFormat::Wav => wav::read_metadata(data),
+        AudioFormat::M4a => mp4::read_metadata(data),
+    }
+}
+
+pub fn read_picture(format: AudioFormat, data: &[u8]) -> Option<Picture> {
+    match format {
+        AudioFormat::Mp3 => id3::read_picture(data),
+        AudioFormat::Flac => flac::read_picture(data).or_else(|| id3::read_picture(data)),
+        AudioFormat::OggVorbis | AudioFormat::Opus => ogg::read_picture(data),
+        AudioFormat::Wav => wav::read_picture(data),
+        AudioFormat::M4a => mp4::read_picture(data),
+    }
+}
+
+/// Lyrics embedded in the file's tags
+pub fn read_lyrics(format: AudioFormat, data: &[u8]) -> Option<Lyrics> {
+    match format {
+        AudioFormat::Mp3 => id3::read_lyrics(data),
+        AudioFormat::Flac => flac::read_comments(data)
+            .and_then(|comments| comments.lyrics())
+            .or_else(|| id3::read_lyrics(data)),
+        AudioFormat::OggVorbis | AudioFormat::Opus => ogg::read_comments(data).and_then(|comments| comments.lyrics()),
+        AudioFormat::Wav => wav::read_lyrics(data),
+        AudioFormat::M4a => mp4::read_lyrics(data),
+    }
+}
+
+/// Chapters from ID3 CHAP frames or a CUESHEET comment, not yet closed off
+pub fn read_chapters(format: AudioFormat, data: &[u8]) -> Vec<ChapterMark> {
+    let cue_sheet = |comments: Option<VorbisComments>| {
+        comments.and_then(|comments| comments.get("CUESHEET").map(chapters::parse_cue)).unwrap_or_default()
+    };
+    match format {
+        AudioFormat::Mp3 => id3::read_chapters(data),
+        AudioFormat::Wav => wav::read_chapters(data),
+        AudioFormat::Flac => cue_sheet(flac::read_comments(data)),
+        AudioFormat::OggVorbis | AudioFormat::Opus => cue_sheet(ogg::read_comments(data)),
+        AudioFormat::M4a => Vec::new(),
+    }
+}
+
+pub fn read_properties(format: AudioFormat, data: &[u8]) -> Option<AudioProperties> {
+    match format {
+        AudioFormat::Mp3 => mp3::read_properties(data),
+        AudioFormat::Flac => flac::read_properties(data),
+        AudioFormat::OggVorbis | AudioFormat::Opus => ogg::read_properties(data),
+        AudioFormat::Wav => wav::read_properties(data),
+        AudioFormat::M4a => mp4::read_properties(data),
+    }
+}
@@ -0,0 +1,326 @@
+// MPEG audio frame header walking, plus the Xing/Info, LAME and VBRI headers encoders
+// put in the first frame. http://www.mp3-tech.org/programmer/frame_header.html
+
+use crate::id3;
+use crate::metadata::{AudioProperties, BitrateMode, ChannelMode, Gapless};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MpegVersion {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameHeader {
+    pub version: MpegVersion,
+    pub layer: u8,
+    pub bitrate_kbps: u32,
+    pub sample_rate: u32,
+    pub channel_mode: ChannelMode,
+    /// whole frame including the 4 header bytes
+    pub frame_len: usize,
+    pub samples: u32,
+}
+
+impl FrameHeader {
+    /// Bytes between the header and the Xing/Info tag in a layer III frame
+    fn side_info_len(&self) -> usize {
+        match (self.version, self.channel_mode) {
+            (MpegVersion::Mpeg1, ChannelMode::Mono) => 17,
+            (MpegVersion::Mpeg1, _) => 32,
+            (_, ChannelMode::Mono) => 9,
+            _ => 17,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub offset: usize,
+    pub header: FrameHeader,
+}
+
+/// Xing/Info (or VBRI) header from the first frame; that frame carries no audio
+#[derive(Debug, Clone, Default)]
+pub struct VbrHeader {
+    pub frames: Option<u32>,
+    pub bytes: Option<u32>,
+    /// "Xing" and VBRI mean VBR, "Info" is what LAME writes for CBR files
+    pub vbr: bool,
+    /// where the frame and byte counts sit in the frame, for rewriting them
+    pub frames_at: Option<usize>,
+    pub bytes_at: Option<usize>,
+    pub lame: Option<LameTag>,
+}
+
+/// The LAME extension after the Xing/Info fields, also written by ffmpeg.
+/// http://gabriel.mp3-tech.org/mp3infotag.html
+#[derive(Debug, Clone, Copy)]
+pub struct LameTag {
+    /// start of the tag within the frame
+    pub offset: usize,
+    /// samples the encoder added before the audio, not counting the decoder's own 529
+    pub encoder_delay: u32,
+    /// samples of silence appended to fill the last frame
+    pub padding: u32,
+}
+
+impl LameTag {
+    pub const DELAY_AT: usize = 21;
+    pub const MUSIC_LENGTH_AT: usize = 28;
+    /// CRC-16 of the frame up to here
+    pub const TAG_CRC_AT: usize = 34;
+    const LEN: usize = 36;
+}
+
+const BITRATES: [[u32; 15]; 5] = [
+    [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448], // MPEG1 layer I
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],    // MPEG1 layer II
+    [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],     // MPEG1 layer III
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],    // MPEG2/2.5 layer I
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],         // MPEG2/2.5 layer II & III
+];
+
+pub fn parse_header(bytes: &[u8]) -> Option<FrameHeader> {
+    if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    let version = match (bytes[1] >> 3) & 0x03 {
+        0 => MpegVersion::Mpeg25,
+        2 => MpegVersion::Mpeg2,
+        3 => MpegVersion::Mpeg1,
+        _ => return None,
+    };
+    let layer = match (bytes[1] >> 1) & 0x03 {
+        1 => 3,
+        2 => 2,
+        3 => 1,
+        _ => return None,
+    };
+    let bitrate_index = (bytes[2] >> 4) as usize;
+    let sample_rate_index = ((bytes[2] >> 2) & 0x03) as usize;
+    // 0 is free format, which we can't size without decoding
+    if bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+        return None;
+    }
+    let padding = ((bytes[2] >> 1) & 0x01) as usize;
+    let channel_mode = match bytes[3] >> 6 {
+        0 => ChannelMode::Stereo,
+        1 => ChannelMode::JointStereo,
+        2 => ChannelMode::DualChannel,
+        _ => ChannelMode::Mono,
+    };
+
+    let table = match (version, layer) {
+        (MpegVersion::Mpeg1, layer) => layer as usize - 1,
+        (_, 1) => 3,
+        _ => 4,
+    };
+    let bitrate_kbps = BITRATES[table][bitrate_index];
+    let sample_rate = match version {
+        MpegVersion::Mpeg1 => [44100, 48000, 32000][sample_rate_index],
+        MpegVersion::Mpeg2 => [22050, 24000, 16000][sample_rate_index],
+        MpegVersion::Mpeg25 => [11025, 12000, 8000][sample_rate_index],
+    };
+    let samples = match (version, layer) {
+        (_, 1) => 384,
+        (MpegVersion::Mpeg1, _) | (_, 2) => 1152,
+        _ => 576,
+    };
+    let frame_len = if layer == 1 {
+        (12 * bitrate_kbps as usize * 1000 / sample_rate as usize + padding) * 4
+    } else {
+        samples as usize / 8 * bitrate_kbps as usize * 1000 / sample_rate as usize + padding
+    };
+
+    Some(FrameHeader { version, layer, bitrate_kbps, sample_rate, channel_mode, frame_len, samples })
+}
+
+/// Iterates over the audio frames of an MPEG stream, skipping a leading ID3v2 tag
+/// and resynchronising over junk between frames.
+pub fn frames(data: &[u8]) -> impl Iterator<Item = Frame> + '_ {
+    let mut pos = id3::v2_tag_len(data);
+    std::iter::from_fn(move || {
+        let offset = find_frame(data, pos)?;
+        let header = parse_header(&data[offset..])?;
+        pos = offset + header.frame_len;
+        Some(Frame { offset, header })
+    })
+}
+
+/// Finds the next frame at or after `from`. Away from the expected position a
+/// candidate only counts if another frame header directly follows it.
+fn find_frame(data: &[u8], from: usize) -> Option<usize> {
+    if let Some(header) = data.get(from..).and_then(parse_header) {
+        if from + header.frame_len <= data.len() {
+            return Some(from);
+        }
+    }
+    (from..data.len().saturating_sub(4)).find(|&offset| {
+        match parse_header(&data[offset..]) {
+            Some(header) => {
+                let next = offset + header.frame_len;
+                next == data.len() || data.get(next..).and_then(parse_header).is_some()
+            }
+            None => false,
+        }
+    })
+}
+
+/// Checks that the stream starts with a run of `count` back-to-back frames that agree on
+/// version, layer and sample rate. Junk before the first frame is tolerated up to `max_skip` bytes.
+pub fn validate(data: &[u8], count: usize, max_skip: usize) -> Result<(), String> {
+    let start = id3::v2_tag_len(data);
+    let first = find_frame(data, start).ok_or("no MPEG audio frames found")?;
+    if first - start > max_skip {
+        return Err(format!("first MPEG frame is {} bytes into the audio data", first - start));
+    }
+
+    let first_header = parse_header(&data[first..]).ok_or("invalid MPEG frame header")?;
+    let mut pos = first;
+    for n in 0..count {
+        let header = data.get(pos..)
+            .and_then(parse_header)
+            .ok_or_else(|| format!("MPEG frame {} is missing or has a broken header", n + 1))?;
+        if header.version != first_header.version
+            || header.layer != first_header.layer
+            || header.sample_rate != first_header.sample_rate
+        {
+            return Err(format!("MPEG frame {} doesn't match the stream's format", n + 1));
+        }
+        pos += header.frame_len;
+        if pos >= data.len() {
+            break; // very short file, every frame it has checked out
+        }
+    }
+    Ok(())
+}
+
+pub fn read_vbr_header(data: &[u8], frame: &Frame) -> Option<VbrHeader> {
+    let frame_data = data.get(frame.offset..frame.offset + frame.header.frame_len)?;
+    if frame.header.layer != 3 {
+        return None;
+    }
+
+    let xing_at = 4 + frame.header.side_info_len();
+    if let Some(tag) = frame_data.get(xing_at..xing_at + 8) {
+        if &tag[0..4] == b"Xing" || &tag[0..4] == b"Info" {
+            let flags = be_u32(&tag[4..8]);
+            let mut pos = xing_at + 8;
+            let mut header = VbrHeader { vbr: &tag[0..4] == b"Xing", ..Default::default() };
+            if flags & 0x1 != 0 {
+                header.frames = frame_data.get(pos..pos + 4).map(be_u32);
+                header.frames_at = Some(pos);
+                pos += 4;
+            }
+            if flags & 0x2 != 0 {
+                header.bytes = frame_data.get(pos..pos + 4).map(be_u32);
+                header.bytes_at = Some(pos);
+                pos += 4;
+            }
+            // seek table and quality indicator
+            if flags & 0x4 != 0 {
+                pos += 100;
+            }
+            if flags & 0x8 != 0 {
+                pos += 4;
+            }
+            header.lame = read_lame_tag(frame_data, pos);
+            return Some(header);
+        }
+    }
+
+    // VBRI always sits 32 bytes after the header
+    let tag = frame_data.get(36..36 + 18)?;
+    if &tag[0..4] == b"VBRI" {
+        return Some(VbrHeader {
+            bytes: Some(be_u32(&tag[10..14])),
+            frames: Some(be_u32(&tag[14..18])),
+            vbr: true,
+            ..Default::default()
+        });
+    }
+    None
+}
+
+fn read_lame_tag(frame_data: &[u8], offset: usize) -> Option<LameTag> {
+    let tag = frame_data.get(offset..offset + LameTag::LEN)?;
+    if !matches!(&tag[0..4], b"LAME" | b"Lavf" | b"Lavc") {
+        return None;
+    }
+    let delay = &tag[LameTag::DELAY_AT..LameTag::DELAY_AT + 3];
+    Some(LameTag {
+        offset,
+        encoder_delay: ((delay[0] as u32) << 4) | (delay[1] as u32 >> 4),
+        padding: ((delay[1] as u32 & 0x0F) << 8) | delay[2] as u32,
+    })
+}
+
+/// Encoder delay and padding from the LAME tag, with the exact sample count they imply
+pub fn read_gapless(data: &[u8]) -> Option<Gapless> {
+    let first = frames(data).next()?;
+    let header = read_vbr_header(data, &first)?;
+    let lame = header.lame?;
+    let total_samples = header.frames
+        .map(|frames| (frames as u64 * first.header.samples as u64).saturating_sub((lame.encoder_delay + lame.padding) as u64));
+    Some(Gapless { encoder_delay: lame.encoder_delay, padding: lame.padding, total_samples })
+}
+
+/// Duration, bitrate and stream layout from the frame headers. Uses the
+/// Xing/VBRI frame count when present, otherwise counts every frame.
+pub fn read_properties(data: &[u8]) -> Option<AudioProperties> {
+    let mut frames = frames(data).peekable();
+    let first = *frames.peek()?;
+    let vbr_header = read_vbr_header(data, &first);
+    if vbr_header.is_some() {
+        frames.next();
+    }
+
+    let mut frame_count: u64 = 0;
+    let mut total_samples: u64 = 0;
+    let mut audio_bytes: u64 = 0;
+    let mut bitrates_differ = false;
+    let mut first_bitrate = None;
+    for frame in frames {
+        frame_count += 1;
+        total_samples += frame.header.samples as u64;
+        audio_bytes += frame.header.frame_len as u64;
+        match first_bitrate {
+            None => first_bitrate = Some(frame.header.bitrate_kbps),
+            Some(bitrate) if bitrate != frame.header.bitrate_kbps => bitrates_differ = true,
+            _ => {}
+        }
+    }
+    if frame_count == 0 {
+        return None;
+    }
+
+    // trust the encoder's counts over a walk that may have stopped early on a damaged frame
+    if let Some(header) = &vbr_header {
+        if let Some(frames) = header.frames.filter(|&frames| frames > 0) {
+            total_samples = frames as u64 * first.header.samples as u64;
+        }
+        if let Some(bytes) = header.bytes.filter(|&bytes| bytes > 0) {
+            audio_bytes = bytes as u64;
+        }
+    }
+
+    let sample_rate = first.header.sample_rate;
+    let duration_ms = total_samples * 1000 / sample_rate as u64;
+    let bitrate_kbps = if duration_ms > 0 { (audio_bytes * 8 / duration_ms) as u32 } else { first.header.bitrate_kbps };
+    let vbr = vbr_header.map(|header| header.vbr).unwrap_or(false) || bitrates_differ;
+
+    Some(AudioProperties {
+        duration_ms,
+        bitrate_kbps,
+        bitrate_mode: if vbr { BitrateMode::Vbr } else { BitrateMode::Cbr },
+        sample_rate,
+        channels: if first.header.channel_mode == ChannelMode::Mono { 1 } else { 2 },
+        channel_mode: Some(first.header.channel_mode),
+    })
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
@@ -0,0 +1,180 @@
+// MP4/M4A atoms: iTunes-style tags under moov/udta/meta/ilst and the
+// audio track's sample description. https://developer.apple.com/documentation/quicktime-file-format
+
+use crate::key::Key;
+use crate::lyrics::Lyrics;
+use crate::metadata::{AudioProperties, BitrateMode, Gapless, Picture, ReplayGain, SongMetadata};
+
+/// (atom type, atom body) for every atom directly inside `data`
+pub fn atoms(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut atoms = Vec::new();
+    let mut pos = 0;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let (header_len, size) = match size {
+            0 => (8, (data.len() - pos) as u64),
+            1 => match data.get(pos + 8..pos + 16) {
+                Some(large) => (16, u64::from_be_bytes(large.try_into().unwrap())),
+                None => break,
+            },
+            size => (8, size),
+        };
+        // a 64-bit size can run past the end of the file or of usize on wasm32
+        let Some(end) = (pos as u64).checked_add(size).and_then(|end| usize::try_from(end).ok()) else {
+            break;
+        };
+        if size < header_len as u64 || end > data.len() {
+            break;
+        }
+        atoms.push((kind, &data[pos + header_len..end]));
+        pos = end;
+    }
+    atoms
+}
+
+pub fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    atoms(data).into_iter().find(|(k, _)| k == kind).map(|(_, body)| body)
+}
+
+pub fn path<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter().try_fold(data, |atom, kind| {
+        let body = child(atom, kind)?;
+        // meta is a full box: version and flags come before its children
+        if *kind == b"meta" { body.get(4..) } else { Some(body) }
+    })
+}
+
+/// The ilst tag items as (item type, payload of their `data` atom)
+pub fn tag_items(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let Some(ilst) = path(data, &[b"moov", b"udta", b"meta", b"ilst"]) else {
+        return Vec::new();
+    };
+    atoms(ilst)
+        .into_iter()
+        .filter_map(|(kind, item)| {
+            // skip the 4 byte type indicator and 4 byte locale
+            let payload = child(item, b"data")?.get(8..)?;
+            Some((kind, payload))
+        })
+        .collect()
+}
+
+/// "----" items as (name, text value); iTunes keeps ReplayGain and other tags without an atom of their own here
+pub fn freeform_items(data: &[u8]) -> Vec<(String, String)> {
+    let Some(ilst) = path(data, &[b"moov", b"udta", b"meta", b"ilst"]) else {
+        return Vec::new();
+    };
+    atoms(ilst)
+        .into_iter()
+        .filter(|(kind, _)| kind == b"----")
+        .filter_map(|(_, item)| {
+            // name is a full box, data has a type indicator and locale
+            let name = child(item, b"name")?.get(4..)?;
+            let value = child(item, b"data")?.get(8..)?;
+            Some((String::from_utf8_lossy(name).into_owned(), String::from_utf8_lossy(value).into_owned()))
+        })
+        .collect()
+}
+
+pub fn read_metadata(data: &[u8]) -> SongMetadata {
+    let items = tag_items(data);
+    let freeform = freeform_items(data);
+    let text = |kind: &[u8; 4]| {
+        items.iter()
+            .find(|(k, _)| k == kind)
+            .map(|(_, payload)| String::from_utf8_lossy(payload).trim().to_string())
+            .filter(|text| !text.is_empty())
+    };
+    let item = |kind: &[u8; 4]| items.iter().find(|(k, _)| k == kind).map(|(_, payload)| *payload);
+
+    SongMetadata {
+        title: text(b"\xa9nam"),
+        artist: text(b"\xa9ART"),
+        album_artist: text(b"aART"),
+        album: text(b"\xa9alb"),
+        // trkn: 2 reserved bytes, track, total
+        track: item(b"trkn")
+            .and_then(|payload| payload.get(2..4))
+            .map(|track| u16::from_be_bytes([track[0], track[1]]) as u32)
+            .filter(|&track| track > 0),
+        year: text(b"\xa9day").and_then(|date| date.get(..4)?.parse().ok()),
+        genre: text(b"\xa9gen"),
+        replay_gain: ReplayGain::from_fields(freeform.iter().map(|(name, value)| (name.as_str(), value.as_str()))),
+        gapless: freeform.iter()
+            .find(|(name, _)| name == "iTunSMPB")
+            .and_then(|(_, value)| Gapless::from_itunsmpb(value)),
+        bpm: item(b"tmpo")
+            .and_then(|payload| payload.get(..2))
+            .map(|bpm| u16::from_be_bytes([bpm[0], bpm[1]]) as f32)
+            .filter(|&bpm| bpm > 0.0),
+        key: freeform.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("initialkey"))
+            .and_then(|(_, value)| Key::parse(value)),
+    }
+}
+
+/// covr carries no picture type, the first image is the cover
+pub fn read_picture(data: &[u8]) -> Option<Picture> {
+    tag_items(data)
+        .into_iter()
+        .filter(|(kind, _)| kind == b"covr")
+        .find_map(|(_, image)| Picture::new("", image))
+}
+
+pub fn read_lyrics(data: &[u8]) -> Option<Lyrics> {
+    tag_items(data)
+        .into_iter()
+        .find(|(kind, _)| kind == b"\xa9lyr")
+        .and_then(|(_, text)| Lyrics::parse(&String::from_utf8_lossy(text)))
+}
+
+/// The trak whose handler is "soun"
+fn audio_track(data: &[u8]) -> Option<&[u8]> {
+    let moov = child(data, b"moov")?;
+    atoms(moov)
+        .into_iter()
+        .filter(|(kind, _)| kind == b"trak")
+        .map(|(_, trak)| trak)
+        .find(|trak| {
+            path(trak, &[b"mdia", b"hdlr"])
+                .and_then(|hdlr| hdlr.get(8..12))
+                .map(|handler| handler == b"soun")
+                .unwrap_or(false)
+        })
+}
+
+pub fn read_properties(data: &[u8]) -> Option<AudioProperties> {
+    let trak = audio_track(data)?;
+
+    let mdhd = path(trak, &[b"mdia", b"mdhd"])?;
+    let (timescale, duration) = match mdhd.first()? {
+        1 => (be_u32(mdhd.get(20..24)?), u64::from_be_bytes(mdhd.get(24..32)?.try_into().ok()?)),
+        _ => (be_u32(mdhd.get(12..16)?), be_u32(mdhd.get(16..20)?) as u64),
+    };
+    if timescale == 0 {
+        return None;
+    }
+
+    // stsd: version/flags, entry count, then sample entries (mp4a, alac, ...)
+    let stsd = path(trak, &[b"mdia", b"minf", b"stbl", b"stsd"])?;
+    let (_, entry) = atoms(stsd.get(8..)?).into_iter().next()?;
+    let channels = u16::from_be_bytes([*entry.get(16)?, *entry.get(17)?]);
+    // 16.16 fixed point
+    let sample_rate = u16::from_be_bytes([*entry.get(24)?, *entry.get(25)?]) as u32;
+
+    let duration_ms = duration * 1000 / timescale as u64;
+    let audio_bytes = child(data, b"mdat").map(|mdat| mdat.len()).unwrap_or(data.len()) as u64;
+    Some(AudioProperties {
+        duration_ms,
+        bitrate_kbps: if duration_ms > 0 { (audio_bytes * 8 / duration_ms) as u32 } else { 0 },
+        bitrate_mode: BitrateMode::Vbr,
+        sample_rate: if sample_rate > 0 { sample_rate } else { timescale },
+        channels: channels as u8,
+        channel_mode: None,
+    })
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
@@ -0,0 +1,147 @@
+// Ogg pages and the Vorbis / Opus headers inside them.
+// https://xiph.org/ogg/doc/framing.html, https://www.rfc-editor.org/rfc/rfc7845
+
+use crate::flac;
+use crate::metadata::{self, AudioProperties, BitrateMode, Picture, SongMetadata};
+use crate::vorbis_comment::VorbisComments;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    Vorbis,
+    Opus,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Page<'a> {
+    pub granule: i64,
+    pub serial: u32,
+    pub lacing: &'a [u8],
+    pub body: &'a [u8],
+}
+
+pub fn pages(data: &[u8]) -> impl Iterator<Item = Page<'_>> + '_ {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos + 27)?;
+        if &header[0..4] != b"OggS" {
+            return None;
+        }
+        let granule = i64::from_le_bytes(header[6..14].try_into().ok()?);
+        let serial = u32::from_le_bytes(header[14..18].try_into().ok()?);
+        let segments = header[26] as usize;
+        let lacing = data.get(pos + 27..pos + 27 + segments)?;
+        let body_len: usize = lacing.iter().map(|&len| len as usize).sum();
+        let body_start = pos + 27 + segments;
+        let body = data.get(body_start..body_start + body_len)?;
+
+        let page = Page { granule, serial, lacing, body };
+        pos = body_start + body_len;
+        Some(page)
+    })
+}
+
+/// Reassembles the first `count` packets of the first logical stream
+pub fn packets(data: &[u8], count: usize) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    let mut current = Vec::new();
+    let mut serial = None;
+
+    for page in pages(data) {
+        if *serial.get_or_insert(page.serial) != page.serial {
+            continue;
+        }
+        let mut body_pos = 0;
+        for &len in page.lacing {
+            current.extend_from_slice(&page.body[body_pos..body_pos + len as usize]);
+            body_pos += len as usize;
+            // a lacing value under 255 ends the packet
+            if len < 255 {
+                packets.push(std::mem::take(&mut current));
+                if packets.len() == count {
+                    return packets;
+                }
+            }
+        }
+    }
+    packets
+}
+
+pub fn codec(data: &[u8]) -> Option<Codec> {
+    let first = pages(data).next()?;
+    if first.body.starts_with(b"\x01vorbis") {
+        Some(Codec::Vorbis)
+    } else if first.body.starts_with(b"OpusHead") {
+        Some(Codec::Opus)
+    } else {
+        None
+    }
+}
+
+pub fn read_comments(data: &[u8]) -> Option<VorbisComments> {
+    let headers = packets(data, 2);
+    let comment_packet = headers.get(1)?;
+    let comments = match codec(data)? {
+        Codec::Vorbis => comment_packet.strip_prefix(b"\x03vorbis")?,
+        Codec::Opus => comment_packet.strip_prefix(b"OpusTags")?,
+    };
+    VorbisComments::parse(comments)
+}
+
+/// METADATA_BLOCK_PICTURE holds a base64 FLAC picture block, the older COVERART a bare base64 image
+pub fn read_picture(data: &[u8]) -> Option<Picture> {
+    let comments = read_comments(data)?;
+    let blocks = comments.comments.iter()
+        .filter(|(key, _)| key == "METADATA_BLOCK_PICTURE")
+        .filter_map(|(_, value)| flac::parse_picture_block(&base64::decode(value.trim()).ok()?));
+    let legacy = comments.comments.iter()
+        .filter(|(key, _)| key == "COVERART")
+        .filter_map(|(_, value)| Some((3, Picture::new("", &base64::decode(value.trim()).ok()?)?)));
+    metadata::pick_cover(blocks.chain(legacy))
+}
+
+pub fn read_metadata(data: &[u8]) -> SongMetadata {
+    read_comments(data).map(|comments| comments.to_metadata()).unwrap_or_default()
+}
+
+pub fn read_properties(data: &[u8]) -> Option<AudioProperties> {
+    let headers = packets(data, 1);
+    let id_header = headers.first()?;
+    let first_serial = pages(data).next()?.serial;
+    let last_granule = pages(data)
+        .filter(|page| page.serial == first_serial && page.granule >= 0)
+        .last()?
+        .granule as u64;
+
+    let (channels, sample_rate, total_samples, bitrate_mode) = match codec(data)? {
+        Codec::Vorbis => {
+            if id_header.len() < 28 {
+                return None;
+            }
+            let le_u32 = |pos: usize| u32::from_le_bytes([id_header[pos], id_header[pos + 1], id_header[pos + 2], id_header[pos + 3]]);
+            let (max, nominal, min) = (le_u32(16), le_u32(20), le_u32(24));
+            let cbr = nominal > 0 && max == nominal && min == nominal;
+            (id_header[11], le_u32(12), last_granule, if cbr { BitrateMode::Cbr } else { BitrateMode::Vbr })
+        }
+        Codec::Opus => {
+            if id_header.len() < 19 {
+                return None;
+            }
+            // granule positions always count 48 kHz samples, including the pre-skip
+            let pre_skip = u16::from_le_bytes([id_header[10], id_header[11]]) as u64;
+            (id_header[9], 48_000, last_granule.saturating_sub(pre_skip), BitrateMode::Vbr)
+        }
+    };
+    if sample_rate == 0 {
+        return None;
+    }
+
+    let duration_ms = total_samples * 1000 / sample_rate as u64;
+    Some(AudioProperties {
+        duration_ms,
+        bitrate_kbps: if duration_ms > 0 { (data.len() as u64 * 8 / duration_ms) as u32 } else { 0 },
+        bitrate_mode,
+        sample_rate,
+        channels,
+        channel_mode: None,
+    })
+}
@@ -0,0 +1,356 @@
+// Playlists, and moving them to and from desktop players as M3U8, PLS or XSPF. Exported entries
+// point at our /stream_audio; imported entries are matched against the library by content
+// hash, then file name, then title and artist.
+// https://en.wikipedia.org/wiki/M3U https://en.wikipedia.org/wiki/PLS_(file_format) https://xspf.org/spec
+
+use serde::{Deserialize, Serialize};
+
+use crate::links::{decode_component, encode_component, xml_escape, xml_unescape};
+use crate::structs::{Song, SongDb};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Playlist {
+    pub id: String,
+    pub name: String,
+    /// in play order; songs deleted since are skipped when the playlist is used
+    pub song_ids: Vec<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+/// Fields left out stay as they are
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct PlaylistEdit {
+    pub name: Option<String>,
+    pub song_ids: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistFormat {
+    M3u8,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub fn from_name(name: &str) -> Option<PlaylistFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "m3u8" | "m3u" => Some(PlaylistFormat::M3u8),
+            "pls" => Some(PlaylistFormat::Pls),
+            "xspf" => Some(PlaylistFormat::Xspf),
+            _ => None,
+        }
+    }
+
+    /// From the file's first bytes; anything that isn't PLS or XSPF is read as M3U
+    pub fn detect(text: &str) -> PlaylistFormat {
+        let start = text.trim_start_matches('\u{feff}').trim_start();
+        if start.get(..10).is_some_and(|start| start.eq_ignore_ascii_case("[playlist]")) {
+            PlaylistFormat::Pls
+        } else if start.starts_with("<?xml") || start.starts_with("<playlist") {
+            PlaylistFormat::Xspf
+        } else {
+            PlaylistFormat::M3u8
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Pls => "pls",
+            PlaylistFormat::Xspf => "xspf",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u8 => "audio/x-mpegurl",
+            PlaylistFormat::Pls => "audio/x-scpls",
+            PlaylistFormat::Xspf => "application/xspf+xml",
+        }
+    }
+}
+
+/// One entry of an imported playlist, with whatever the format had to say about it
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Entry {
+    pub location: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub content_hash: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlaylistImportSummary {
+    pub playlist: Playlist,
+    /// entries no song in the library matched
+    pub unmatched: Vec<Entry>,
+}
+
+pub fn stream_url(base_url: &str, song_id: &str) -> String {
+    format!("{}/stream_audio?id={}", base_url, encode_component(song_id))
+}
+
+fn title(song: &Song) -> &str {
+    song.metadata.title.as_deref().unwrap_or(&song.name)
+}
+
+fn artist(song: &Song) -> Option<&str> {
+    song.metadata.artist.as_deref().or(song.metadata.album_artist.as_deref())
+}
+
+/// "Artist - Title", the display convention of M3U and PLS, which are line based
+fn display_title(song: &Song) -> String {
+    let display = match artist(song) {
+        Some(artist) => format!("{} - {}", artist, title(song)),
+        None => title(song).to_string(),
+    };
+    one_line(&display)
+}
+
+fn one_line(text: &str) -> String {
+    text.split(['\r', '\n']).filter(|part| !part.is_empty()).collect::<Vec<_>>().join(" ")
+}
+
+fn duration_secs(song: &Song) -> Option<u64> {
+    song.properties.as_ref().map(|properties| properties.duration_ms / 1000)
+}
+
+pub fn export(format: PlaylistFormat, name: &str, songs: &[&Song], base_url: &str) -> String {
+    let mut out = String::new();
+    match format {
+        PlaylistFormat::M3u8 => {
+            out.push_str("#EXTM3U\n");
+            out.push_str(&format!("#PLAYLIST:{}\n", one_line(name)));
+            for song in songs {
+                // -1 is M3U for an unknown length
+                let length = duration_secs(song).map_or(-1, |secs| secs as i64);
+                out.push_str(&format!("#EXTINF:{},{}\n", length, display_title(song)));
+                out.push_str(&stream_url(base_url, &song.id));
+                out.push('\n');
+            }
+        }
+        PlaylistFormat::Pls => {
+            out.push_str("[playlist]\n");
+            for (i, song) in songs.iter().enumerate() {
+                let n = i + 1;
+                out.push_str(&format!("File{}={}\n", n, stream_url(base_url, &song.id)));
+                out.push_str(&format!("Title{}={}\n", n, display_title(song)));
+                out.push_str(&format!("Length{}={}\n", n, duration_secs(song).map_or(-1, |secs| secs as i64)));
+            }
+            out.push_str(&format!("NumberOfEntries={}\nVersion=2\n", songs.len()));
+        }
+        PlaylistFormat::Xspf => {
+            out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+            out.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+            out.push_str(&format!("  <title>{}</title>\n", xml_escape(name)));
+            out.push_str("  <trackList>\n");
+            for song in songs {
+                out.push_str("    <track>\n");
+                let mut field = |name: &str, value: &str| {
+                    out.push_str(&format!("      <{}>{}</{}>\n", name, xml_escape(value), name));
+                };
+                field("location", &stream_url(base_url, &song.id));
+                if !song.content_hash.is_empty() {
+                    field("identifier", &format!("urn:sha256:{}", song.content_hash));
+                }
+                field("title", title(song));
+                if let Some(artist) = artist(song) {
+                    field("creator", artist);
+                }
+                if let Some(album) = &song.metadata.album {
+                    field("album", album);
+                }
+                if let Some(track) = song.metadata.track {
+                    field("trackNum", &track.to_string());
+                }
+                if let Some(properties) = &song.properties {
+                    field("duration", &properties.duration_ms.to_string());
+                }
+                if let Some(artwork) = &song.artwork {
+                    field("image", &format!("{}/artwork?id={}", base_url, encode_component(artwork)));
+                }
+                out.push_str("    </track>\n");
+            }
+            out.push_str("  </trackList>\n</playlist>\n");
+        }
+    }
+    out
+}
+
+pub fn parse(format: PlaylistFormat, text: &str) -> Vec<Entry> {
+    let text = text.trim_start_matches('\u{feff}');
+    match format {
+        PlaylistFormat::M3u8 => parse_m3u(text),
+        PlaylistFormat::Pls => parse_pls(text),
+        PlaylistFormat::Xspf => parse_xspf(text),
+    }
+}
+
+/// The playlist's own name, when the format has one
+pub fn parse_name(format: PlaylistFormat, text: &str) -> Option<String> {
+    let name = match format {
+        PlaylistFormat::M3u8 => text.lines().find_map(|line| line.trim().strip_prefix("#PLAYLIST:")).map(str::to_string),
+        PlaylistFormat::Pls => None,
+        // the playlist's title comes before the track list and its tracks' titles
+        PlaylistFormat::Xspf => {
+            let head = text.split("<trackList").next().unwrap_or_default();
+            elements(head, "title").first().map(|title| xml_unescape(title))
+        }
+    };
+    name.map(|name| name.trim().to_string()).filter(|name| !name.is_empty())
+}
+
+/// "Artist - Title" back into its parts
+fn split_display_title(display: &str) -> (Option<String>, Option<String>) {
+    let display = display.trim();
+    match display.split_once(" - ") {
+        Some((artist, title)) => (Some(title.trim().to_string()), Some(artist.trim().to_string())),
+        None => ((!display.is_empty()).then(|| display.to_string()), None),
+    }
+}
+
+fn length_ms(secs: &str) -> Option<u64> {
+    secs.trim().parse::<i64>().ok().filter(|&secs| secs > 0).map(|secs| secs as u64 * 1000)
+}
+
+fn parse_m3u(text: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut pending = Entry::default();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // attributes may sit between the length and the comma, the title follows the first comma
+            let (length, display) = info.split_once(',').unwrap_or((info, ""));
+            let length = length.split_whitespace().next().unwrap_or_default();
+            let (title, artist) = split_display_title(display);
+            pending = Entry { duration_ms: length_ms(length), title, artist, ..Default::default() };
+        } else if !line.starts_with('#') {
+            pending.location = Some(line.to_string());
+            entries.push(std::mem::take(&mut pending));
+        }
+    }
+    entries
+}
+
+fn parse_pls(text: &str) -> Vec<Entry> {
+    let mut entries: std::collections::BTreeMap<u32, Entry> = std::collections::BTreeMap::new();
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let field_len = key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len());
+        let Ok(n) = key[field_len..].parse::<u32>() else {
+            continue;
+        };
+        let entry = entries.entry(n).or_default();
+        match &key[..field_len] {
+            "file" => entry.location = Some(value.trim().to_string()),
+            "title" => (entry.title, entry.artist) = split_display_title(value),
+            "length" => entry.duration_ms = length_ms(value),
+            _ => {}
+        }
+    }
+    entries.into_values().filter(|entry| entry.location.is_some()).collect()
+}
+
+fn parse_xspf(text: &str) -> Vec<Entry> {
+    elements(text, "track")
+        .into_iter()
+        .map(|track| {
+            let field = |name: &str| {
+                elements(track, name).first()
+                    .map(|value| xml_unescape(value.trim()))
+                    .filter(|value| !value.is_empty())
+            };
+            let content_hash = elements(track, "identifier").into_iter()
+                .map(|identifier| identifier.trim().to_ascii_lowercase())
+                .find_map(|identifier| {
+                    let hash = identifier.strip_prefix("urn:sha256:").or_else(|| identifier.strip_prefix("sha256:"))?;
+                    (hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())).then(|| hash.to_string())
+                });
+            Entry {
+                location: field("location"),
+                title: field("title"),
+                artist: field("creator"),
+                duration_ms: field("duration").and_then(|ms| ms.parse().ok()),
+                content_hash,
+            }
+        })
+        .collect()
+}
+
+/// Contents of every `<name>` element, not nested in one another; good enough for XSPF, which
+/// has no attributes worth reading and no recursion
+fn elements<'a>(text: &'a str, name: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}", name), format!("</{}>", name));
+    let mut found = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(&open) {
+        let after_name = &rest[start + open.len()..];
+        // <track> or <track attr="...">, but not <trackList>
+        if !after_name.starts_with(['>', ' ', '\t', '\n', '\r', '/']) {
+            rest = after_name;
+            continue;
+        }
+        let Some(tag_end) = after_name.find('>') else {
+            break;
+        };
+        if after_name[..tag_end].ends_with('/') {
+            found.push("");
+            rest = &after_name[tag_end + 1..];
+            continue;
+        }
+        let body = &after_name[tag_end + 1..];
+        let Some(end) = body.find(&close) else {
+            break;
+        };
+        found.push(&body[..end]);
+        rest = &body[end + close.len()..];
+    }
+    found
+}
+
+/// The library song an entry refers to: by content hash, then by the file name in its
+/// location (our own stream URLs carry the song id), then by title and artist
+pub fn find_song<'a>(song_db: &'a SongDb, entry: &Entry) -> Option<&'a Song> {
+    let songs = || song_db.songs.values().flatten();
+    if let Some(hash) = &entry.content_hash {
+        if let Some(song) = songs().find(|song| song.content_hash == *hash) {
+            return Some(song);
+        }
+    }
+
+    if let Some(location) = &entry.location {
+        let stream_id = location.split_once('?')
+            .and_then(|(_, query)| query.split('&').find_map(|pair| pair.strip_prefix("id=")))
+            .map(decode_component);
+        if let Some(song) = stream_id.and_then(|id| song_db.get_song(&id)) {
+            return Some(song);
+        }
+        let path = location.split(['?', '#']).next().unwrap_or_default();
+        let file_name = decode_component(path.rsplit(['/', '\\']).next().unwrap_or_default());
+        if !file_name.is_empty() {
+            let same_file = |name: &str| name.rsplit(['/', '\\']).next().is_some_and(|name| name.eq_ignore_ascii_case(&file_name));
+            let found = songs().find(|song| {
+                same_file(&song.id) || song.source_path.as_deref().is_some_and(same_file)
+            });
+            if found.is_some() {
+                return found;
+            }
+        }
+    }
+
+    let wanted_title = entry.title.as_deref()?.trim();
+    let same = |a: Option<&str>, b: &str| a.is_some_and(|a| a.trim().eq_ignore_ascii_case(b));
+    songs()
+        .filter(|song| title(song).trim().eq_ignore_ascii_case(wanted_title))
+        .filter(|song| entry.artist.as_deref().is_none_or(|wanted| same(artist(song), wanted)))
+        .min_by_key(|song| match (entry.duration_ms, &song.properties) {
+            (Some(wanted), Some(properties)) => wanted.abs_diff(properties.duration_ms),
+            _ => u64::MAX,
+        })
+}
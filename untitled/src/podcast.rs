@@ -0,0 +1,138 @@
+// Tags published as podcasts: an RSS 2.0 feed with the iTunes tags podcast apps expect, served
+// without authentication at /feed/<tag>.xml. Episodes, and their artwork, are streamed from
+// public paths that only serve songs under published tags.
+// https://help.apple.com/itc/podcasts_connect/#/itcb54353390
+
+use serde::{Deserialize, Serialize};
+
+use crate::links::{decode_component, encode_component, xml_escape};
+use crate::stream;
+use crate::structs::{Song, SongDb};
+
+pub const FEED_PREFIX: &str = "/feed/";
+pub const FEED_SUFFIX: &str = ".xml";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Podcast {
+    /// defaults to the tag's name
+    pub title: Option<String>,
+    pub description: String,
+    pub author: Option<String>,
+    /// e.g. "en-us"
+    pub language: Option<String>,
+    /// one of Apple's podcast categories, e.g. "Music"
+    pub category: Option<String>,
+    pub explicit: bool,
+    /// artwork id for the show, otherwise the newest episode's artwork is used
+    pub artwork: Option<String>,
+}
+
+/// Tag key from a feed path, "/feed/team%20show.xml" -> "team show"
+pub fn tag_from_path(path: &str) -> Option<String> {
+    let file = path.strip_prefix(FEED_PREFIX)?.strip_suffix(FEED_SUFFIX)?;
+    Some(decode_component(file)).filter(|tag| !tag.is_empty())
+}
+
+pub fn feed_url(base_url: &str, tag_key: &str) -> String {
+    format!("{}{}{}{}", base_url, FEED_PREFIX, encode_component(tag_key), FEED_SUFFIX)
+}
+
+pub fn stream_url(base_url: &str, song_id: &str) -> String {
+    format!("{}/public_stream?id={}", base_url, encode_component(song_id))
+}
+
+pub fn artwork_url(base_url: &str, artwork_id: &str) -> String {
+    format!("{}/public_artwork?id={}", base_url, encode_component(artwork_id))
+}
+
+/// Whether a song may be streamed without authentication
+pub fn is_public_song(song_db: &SongDb, song_id: &str) -> bool {
+    song_db.get_song(song_id).is_some_and(|song| song_db.podcasts.contains_key(&song.tag.key))
+}
+
+/// Whether artwork may be fetched without authentication: a show's own, or an episode's
+pub fn is_public_artwork(song_db: &SongDb, artwork_id: &str) -> bool {
+    song_db.podcasts.values().any(|podcast| podcast.artwork.as_deref() == Some(artwork_id))
+        || song_db.podcasts.keys()
+            .filter_map(|tag_key| song_db.songs.get(tag_key))
+            .flatten()
+            .any(|song| song.artwork.as_deref() == Some(artwork_id))
+}
+
+/// The RSS document for a published tag, newest episode first
+pub fn feed(song_db: &SongDb, tag_key: &str, podcast: &Podcast, base_url: &str) -> String {
+    let mut episodes: Vec<&Song> = song_db.songs.get(tag_key).into_iter().flatten().collect();
+    episodes.sort_by_key(|song| std::cmp::Reverse(song.uploaded_at));
+
+    let tag_name = episodes.first().and_then(|song| song.tag.name.clone()).unwrap_or_else(|| tag_key.to_string());
+    let title = podcast.title.clone().unwrap_or(tag_name);
+    let feed_url = feed_url(base_url, tag_key);
+    let artwork = podcast.artwork.as_ref().or_else(|| episodes.iter().find_map(|song| song.artwork.as_ref()));
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str("<channel>\n");
+    element(&mut xml, 1, "title", &title);
+    element(&mut xml, 1, "link", &feed_url);
+    xml.push_str(&format!("  <atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n", xml_escape(&feed_url)));
+    element(&mut xml, 1, "description", &podcast.description);
+    element(&mut xml, 1, "itunes:summary", &podcast.description);
+    if let Some(language) = &podcast.language {
+        element(&mut xml, 1, "language", language);
+    }
+    if let Some(author) = &podcast.author {
+        element(&mut xml, 1, "itunes:author", author);
+    }
+    if let Some(category) = &podcast.category {
+        xml.push_str(&format!("  <itunes:category text=\"{}\"/>\n", xml_escape(category)));
+    }
+    element(&mut xml, 1, "itunes:explicit", if podcast.explicit { "true" } else { "false" });
+    if let Some(artwork) = artwork {
+        xml.push_str(&format!("  <itunes:image href=\"{}\"/>\n", xml_escape(&artwork_url(base_url, artwork))));
+    }
+    if let Some(newest) = episodes.first() {
+        element(&mut xml, 1, "lastBuildDate", &stream::http_date(newest.uploaded_at));
+    }
+
+    for song in episodes {
+        xml.push_str("  <item>\n");
+        element(&mut xml, 2, "title", song.metadata.title.as_deref().unwrap_or(&song.name));
+        xml.push_str(&format!("    <guid isPermaLink=\"false\">{}</guid>\n", xml_escape(&episode_guid(song))));
+        element(&mut xml, 2, "pubDate", &stream::http_date(song.uploaded_at));
+        xml.push_str(&format!(
+            "    <enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n",
+            xml_escape(&stream_url(base_url, &song.id)), song.size, song.format.mime_type(),
+        ));
+        if let Some(properties) = &song.properties {
+            element(&mut xml, 2, "itunes:duration", &(properties.duration_ms / 1000).to_string());
+        }
+        if let Some(artist) = song.metadata.artist.as_ref().or(song.metadata.album_artist.as_ref()) {
+            element(&mut xml, 2, "itunes:author", artist);
+        }
+        if let Some(album) = &song.metadata.album {
+            element(&mut xml, 2, "description", album);
+        }
+        if let Some(track) = song.metadata.track {
+            element(&mut xml, 2, "itunes:episode", &track.to_string());
+        }
+        if let Some(artwork) = &song.artwork {
+            xml.push_str(&format!("    <itunes:image href=\"{}\"/>\n", xml_escape(&artwork_url(base_url, artwork))));
+        }
+        xml.push_str("  </item>\n");
+    }
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+fn element(xml: &mut String, depth: usize, name: &str, text: &str) {
+    xml.push_str(&format!("{}<{}>{}</{}>\n", "  ".repeat(depth), name, xml_escape(text), name));
+}
+
+/// Stays the same for the life of an upload, even when the file is retagged or its hash is
+/// recomputed, so podcast apps don't list the episode twice; identical files in two tags
+/// still get their own guid
+fn episode_guid(song: &Song) -> String {
+    format!("{}/{}/{}", song.tag.key, song.id, song.uploaded_at)
+}
@@ -0,0 +1,38 @@
+// Short preview clips cut straight out of an MP3 on frame boundaries, no re-encoding. The clip
+// drops the ID3 tag and the Xing/Info frame, whose counts describe the whole song.
+
+use crate::mp3;
+
+/// `secs` long clip starting `offset_percent` into the song, moved earlier if the song ends
+/// first. The whole song when it's shorter than the clip. None if there are no MPEG frames.
+pub fn cut_mp3(data: &[u8], offset_percent: u8, secs: u32) -> Option<Vec<u8>> {
+    let mut frames: Vec<mp3::Frame> = mp3::frames(data).collect();
+    let first = *frames.first()?;
+    if mp3::read_vbr_header(data, &first).is_some() {
+        frames.remove(0);
+    }
+    let sample_rate = first.header.sample_rate as u64;
+    let total_samples: u64 = frames.iter().map(|frame| frame.header.samples as u64).sum();
+
+    let clip_samples = secs as u64 * sample_rate;
+    let offset = (total_samples * offset_percent.min(100) as u64 / 100)
+        .min(total_samples.saturating_sub(clip_samples));
+
+    let mut position = 0;
+    let mut start = None;
+    let mut end = None;
+    for frame in &frames {
+        if start.is_none() && position >= offset {
+            start = Some(frame.offset);
+        }
+        position += frame.header.samples as u64;
+        if start.is_some() && position >= offset + clip_samples {
+            end = Some(frame.offset + frame.header.frame_len);
+            break;
+        }
+    }
+    let last = frames.last()?;
+    let start = start?;
+    let end = end.unwrap_or(last.offset + last.header.frame_len).min(data.len());
+    Some(data[start..end].to_vec())
+}
@@ -0,0 +1,138 @@
+// Catalog search over names, tags, the file's own tags, chapter titles and lyrics. Every word of
+// the query has to appear somewhere in the song; case is ignored. Results, like tag listings, can
+// be narrowed down by tempo and key for DJ use.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::key::Key;
+use crate::lyrics::LyricLine;
+use crate::structs::{Song, SongDb};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchField {
+    Name,
+    Tag,
+    Title,
+    Artist,
+    Album,
+    Chapters,
+    Lyrics,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SearchHit {
+    pub song: Song,
+    /// fields at least one query word was found in
+    pub matched: Vec<SearchField>,
+    /// first lyric line with a query word in it, when the lyrics matched
+    pub lyrics_line: Option<LyricLine>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct SongFilter {
+    pub min_bpm: Option<f32>,
+    pub max_bpm: Option<f32>,
+    /// only songs in keys that mix harmonically with this one
+    pub key: Option<Key>,
+}
+
+impl SongFilter {
+    /// From `bpm_min`, `bpm_max` and `key` query parameters; the key can be in Camelot, Open Key
+    /// or note name notation
+    pub fn from_query(params: &HashMap<String, String>) -> Result<SongFilter, String> {
+        let bpm = |name: &str| {
+            params.get(name)
+                .map(|value| value.trim().parse::<f32>().map_err(|_| format!("{} is not a number: {}", name, value)))
+                .transpose()
+        };
+        Ok(SongFilter {
+            min_bpm: bpm("bpm_min")?,
+            max_bpm: bpm("bpm_max")?,
+            key: params.get("key")
+                .map(|key| Key::parse(key).ok_or_else(|| format!("not a key: {}", key)))
+                .transpose()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == SongFilter::default()
+    }
+
+    /// Songs whose tempo or key isn't known yet only pass when that part isn't filtered on
+    pub fn matches(&self, song: &Song) -> bool {
+        let bpm_ok = match (self.min_bpm, self.max_bpm) {
+            (None, None) => true,
+            (min, max) => song.bpm.is_some_and(|bpm| {
+                min.is_none_or(|min| bpm >= min) && max.is_none_or(|max| bpm <= max)
+            }),
+        };
+        let key_ok = self.key.is_none_or(|key| song.key.is_some_and(|song_key| key.mixes_with(&song_key)));
+        bpm_ok && key_ok
+    }
+}
+
+/// Songs matched on their names or tags come before those only matched in the lyrics. An empty
+/// query with a filter lists every song that passes it.
+pub fn search(song_db: &SongDb, query: &str, filter: &SongFilter) -> Vec<SearchHit> {
+    let query = query.to_lowercase();
+    let words: Vec<&str> = query.split_whitespace().collect();
+    if words.is_empty() && filter.is_empty() {
+        return Vec::new();
+    }
+
+    let mut hits: Vec<SearchHit> = song_db.songs.values().flatten()
+        .filter(|song| filter.matches(song))
+        .filter_map(|song| match_song(song, &words))
+        .collect();
+    hits.sort_by(|a, b| {
+        let lyrics_only = |hit: &SearchHit| hit.matched == [SearchField::Lyrics];
+        lyrics_only(a).cmp(&lyrics_only(b)).then_with(|| a.song.name.cmp(&b.song.name))
+    });
+    hits
+}
+
+fn match_song(song: &Song, words: &[&str]) -> Option<SearchHit> {
+    let metadata = &song.metadata;
+    let fields = [
+        (SearchField::Name, Some(song.name.to_lowercase())),
+        (SearchField::Tag, Some(song.tag.name.as_deref().unwrap_or(&song.tag.key).to_lowercase())),
+        (SearchField::Title, metadata.title.as_deref().map(str::to_lowercase)),
+        (SearchField::Artist, metadata.artist.as_deref().or(metadata.album_artist.as_deref()).map(str::to_lowercase)),
+        (SearchField::Album, metadata.album.as_deref().map(str::to_lowercase)),
+        (SearchField::Chapters, (!song.chapters.is_empty()).then(|| {
+            song.chapters.iter()
+                .flat_map(|chapter| [chapter.title.as_deref(), chapter.performer.as_deref()])
+                .flatten()
+                .collect::<Vec<_>>()
+                .join("\n")
+                .to_lowercase()
+        })),
+    ];
+    let lyrics = song.lyrics.as_ref();
+    let lyrics_text = lyrics.map(|lyrics| {
+        lyrics.lines.iter().map(|line| line.text.to_lowercase()).collect::<Vec<_>>().join("\n")
+    });
+
+    let in_lyrics = |word: &str| lyrics_text.as_ref().is_some_and(|text| text.contains(word));
+    let in_field = |word: &str, text: &Option<String>| text.as_ref().is_some_and(|text| text.contains(word));
+    let all_found = words.iter().all(|word| in_lyrics(word) || fields.iter().any(|(_, text)| in_field(word, text)));
+    if !all_found {
+        return None;
+    }
+
+    let mut matched: Vec<SearchField> = fields.iter()
+        .filter(|(_, text)| words.iter().any(|word| in_field(word, text)))
+        .map(|(field, _)| *field)
+        .collect();
+    let lyrics_word = words.iter().find(|word| in_lyrics(word));
+    if lyrics_word.is_some() {
+        matched.push(SearchField::Lyrics);
+    }
+    Some(SearchHit {
+        song: song.clone(),
+        matched,
+        lyrics_line: lyrics.zip(lyrics_word).and_then(|(lyrics, word)| lyrics.find_line(word)).cloned(),
+    })
+}
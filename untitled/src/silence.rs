@@ -0,0 +1,113 @@
+// Silence detection: the leading and trailing silence a player can skip, and the gaps inside a
+// long file that are likely boundaries between songs recorded back to back. Levels are taken
+// over 50 ms blocks as the song decodes, so even an hour-long mix costs little to keep around.
+
+use serde::{Deserialize, Serialize};
+use std::ops::ControlFlow;
+
+use crate::decode;
+use crate::metadata::AudioFormat;
+
+const BLOCK_MS: u64 = 50;
+/// Blocks quieter than this count as silent
+const SILENCE_DBFS: f64 = -50.0;
+/// Quieter stretches shorter than this are pauses within a song
+const MIN_GAP_MS: u64 = 1_500;
+/// Only files at least this long get split proposals
+const MIN_SPLIT_FILE_MS: u64 = 10 * 60_000;
+/// No proposed track is shorter than this
+const MIN_TRACK_MS: u64 = 60_000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Gap {
+    pub start_ms: u64,
+    pub end_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Silence {
+    /// where the audio starts and ends once leading and trailing silence are trimmed
+    pub trim_start_ms: u64,
+    pub trim_end_ms: u64,
+    /// silent stretches between the trim points
+    pub gaps: Vec<Gap>,
+    /// where a long file could be split into tracks, the middle of a gap each
+    pub proposed_splits: Vec<u64>,
+}
+
+/// None when the whole song is silent
+pub fn analyze(format: AudioFormat, data: Vec<u8>) -> anyhow::Result<Option<Silence>> {
+    let mut levels = Vec::new();
+    let (mut energy, mut samples_in_block, mut block_len) = (0.0f64, 0usize, 0usize);
+    decode::for_each_block(format, data, |samples, channels, sample_rate| {
+        if block_len == 0 {
+            block_len = (sample_rate as u64 * BLOCK_MS / 1000) as usize * channels.max(1);
+        }
+        for &sample in samples {
+            energy += sample as f64 * sample as f64;
+            samples_in_block += 1;
+            if samples_in_block == block_len {
+                levels.push(to_dbfs(energy / block_len as f64));
+                energy = 0.0;
+                samples_in_block = 0;
+            }
+        }
+        ControlFlow::Continue(())
+    })?;
+    if samples_in_block > 0 {
+        levels.push(to_dbfs(energy / samples_in_block as f64));
+    }
+    Ok(from_levels(&levels))
+}
+
+/// From the level of each 50 ms block in dBFS
+pub fn from_levels(levels: &[f64]) -> Option<Silence> {
+    let first = levels.iter().position(|&level| level > SILENCE_DBFS)?;
+    let last = levels.iter().rposition(|&level| level > SILENCE_DBFS)?;
+    let trim_start_ms = first as u64 * BLOCK_MS;
+    let trim_end_ms = (last as u64 + 1) * BLOCK_MS;
+
+    let mut gaps = Vec::new();
+    let mut gap_start: Option<usize> = None;
+    for (block, &level) in levels.iter().enumerate().take(last + 1).skip(first) {
+        match (level > SILENCE_DBFS, gap_start) {
+            (false, None) => gap_start = Some(block),
+            (true, Some(start)) => {
+                let gap = Gap { start_ms: start as u64 * BLOCK_MS, end_ms: block as u64 * BLOCK_MS };
+                if gap.end_ms - gap.start_ms >= MIN_GAP_MS {
+                    gaps.push(gap);
+                }
+                gap_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let proposed_splits = if trim_end_ms - trim_start_ms >= MIN_SPLIT_FILE_MS {
+        propose_splits(&gaps, trim_start_ms, trim_end_ms)
+    } else {
+        Vec::new()
+    };
+    Some(Silence { trim_start_ms, trim_end_ms, gaps, proposed_splits })
+}
+
+/// Longest gaps first, skipping any that would leave a track shorter than MIN_TRACK_MS
+fn propose_splits(gaps: &[Gap], start_ms: u64, end_ms: u64) -> Vec<u64> {
+    let mut by_length: Vec<&Gap> = gaps.iter().collect();
+    by_length.sort_by_key(|gap| std::cmp::Reverse(gap.end_ms - gap.start_ms));
+
+    let mut splits: Vec<u64> = Vec::new();
+    for gap in by_length {
+        let split = (gap.start_ms + gap.end_ms) / 2;
+        let far_enough = [start_ms, end_ms].iter().chain(&splits).all(|&other| split.abs_diff(other) >= MIN_TRACK_MS);
+        if far_enough {
+            splits.push(split);
+        }
+    }
+    splits.sort_unstable();
+    splits
+}
+
+fn to_dbfs(mean_square: f64) -> f64 {
+    10.0 * mean_square.max(1e-12).log10()
+}
@@ -0,0 +1,190 @@
+// Serving song files and listings over HTTP without sending more than needed: byte ranges so
+// players can seek, and validators so repeat requests get a 304.
+// https://www.rfc-editor.org/rfc/rfc9110#name-range-requests
+// https://www.rfc-editor.org/rfc/rfc9110#name-conditional-requests
+
+use kinode_process_lib::vfs::{self, SeekFrom};
+use std::collections::HashMap;
+
+use crate::structs::civil_from_days;
+
+/// Listings must be revalidated every time, but revalidating is cheap
+pub const CATALOG_CACHE_CONTROL: &str = "private, no-cache";
+/// A song id can be reused after a delete, so audio is revalidated too
+pub const AUDIO_CACHE_CONTROL: &str = "private, no-cache";
+
+/// What a response's freshness is judged by
+#[derive(Debug, Clone)]
+pub struct Validators {
+    /// strong ETag, quotes included
+    pub etag: String,
+    /// unix seconds
+    pub last_modified: u64,
+}
+
+impl Validators {
+    pub fn new(tag: &str, last_modified: u64) -> Self {
+        Validators { etag: format!("\"{}\"", tag), last_modified }
+    }
+
+    pub fn headers(&self, cache_control: &str) -> HashMap<String, String> {
+        HashMap::from([
+            ("ETag".to_string(), self.etag.clone()),
+            ("Last-Modified".to_string(), http_date(self.last_modified)),
+            ("Cache-Control".to_string(), cache_control.to_string()),
+        ])
+    }
+
+    /// If-None-Match wins over If-Modified-Since when both are sent
+    pub fn not_modified(&self, if_none_match: Option<&str>, if_modified_since: Option<&str>) -> bool {
+        if let Some(if_none_match) = if_none_match {
+            return if_none_match.split(',').map(str::trim).any(|tag| {
+                tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == self.etag
+            });
+        }
+        if_modified_since
+            .and_then(parse_http_date)
+            .is_some_and(|since| self.last_modified <= since)
+    }
+
+    /// If-Range only lets the range through when the client's copy is still current
+    pub fn range_applies(&self, if_range: Option<&str>) -> bool {
+        match if_range.map(str::trim) {
+            None => true,
+            Some(tag) if tag.starts_with('"') => tag == self.etag,
+            Some(date) => parse_http_date(date).is_some_and(|date| self.last_modified <= date),
+        }
+    }
+}
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// IMF-fixdate, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn http_date(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    let time = secs % 86_400;
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize], day, MONTHS[month as usize - 1], year,
+        time / 3600, time / 60 % 60, time % 60,
+    )
+}
+
+/// Only IMF-fixdate; the obsolete formats are rare enough to just treat as absent
+pub fn parse_http_date(date: &str) -> Option<u64> {
+    let mut parts = date.split_whitespace().skip(1);
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month_name = parts.next()?;
+    let month = MONTHS.iter().position(|&month| month == month_name)? as i64 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+
+    // days_from_civil, the inverse of civil_from_days
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    u64::try_from(days).ok().map(|days| days * 86_400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Inclusive byte window of a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+/// None of the requested bytes exist, answered with 416
+#[derive(Debug)]
+pub struct RangeNotSatisfiable;
+
+/// Parses a `Range` header against a file of `len` bytes. Ok(None) means serve the whole
+/// file: no usable header, or several ranges, which we're allowed to answer in full.
+pub fn parse_range(header: &str, len: u64) -> Result<Option<ByteRange>, RangeNotSatisfiable> {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = if start.is_empty() {
+        // suffix range: the last n bytes
+        let Ok(suffix) = end.parse::<u64>() else {
+            return Ok(None);
+        };
+        if suffix == 0 || len == 0 {
+            return Err(RangeNotSatisfiable);
+        }
+        ByteRange { start: len.saturating_sub(suffix), end: len - 1 }
+    } else {
+        let Ok(start) = start.parse::<u64>() else {
+            return Ok(None);
+        };
+        let end = match end {
+            "" => len.saturating_sub(1),
+            end => match end.parse::<u64>() {
+                Ok(end) if end >= start => end.min(len.saturating_sub(1)),
+                _ => return Ok(None),
+            },
+        };
+        if start >= len {
+            return Err(RangeNotSatisfiable);
+        }
+        ByteRange { start, end }
+    };
+    Ok(Some(range))
+}
+
+/// Where a response body comes from: the song file itself, or a variant built in memory
+pub enum Body {
+    File { path: String, size: u64 },
+    Memory(Vec<u8>),
+}
+
+impl Body {
+    pub fn size(&self) -> u64 {
+        match self {
+            Body::File { size, .. } => *size,
+            Body::Memory(data) => data.len() as u64,
+        }
+    }
+
+    pub fn read(self, range: Option<ByteRange>) -> anyhow::Result<Vec<u8>> {
+        match (self, range) {
+            (Body::File { path, .. }, Some(range)) => read_range(&path, range),
+            (Body::File { path, .. }, None) => Ok(vfs::open_file(&path, false, None)?.read()?),
+            (Body::Memory(data), Some(range)) => Ok(data[range.start as usize..=range.end as usize].to_vec()),
+            (Body::Memory(data), None) => Ok(data),
+        }
+    }
+}
+
+/// Reads just `range` of the file instead of loading all of it
+pub fn read_range(path: &str, range: ByteRange) -> anyhow::Result<Vec<u8>> {
+    let mut file = vfs::open_file(path, false, None)?;
+    file.seek(SeekFrom::Start(range.start))?;
+    let mut buffer = vec![0; range.length() as usize];
+    let read = file.read_at(&mut buffer)?;
+    buffer.truncate(read);
+    Ok(buffer)
+}
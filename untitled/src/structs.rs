@@ -1,9 +1,27 @@
-use std::collections::HashMap;
-use kinode_process_lib::{set_state, get_state, vfs};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+use kinode_process_lib::{set_state, get_state, vfs, Address};
 use kinode_process_lib::http::{HttpServerRequest, IncomingHttpRequest, WsMessageType };
 use kinode_process_lib::vfs::Directory;
 use serde::{Serialize, Deserialize};
 
+use crate::chapters::{self, Chapter};
+use crate::duplicates::{DuplicateGroup, MergeSummary};
+use crate::fingerprint;
+use crate::ingest::{self, IngestError};
+use crate::key::Key;
+use crate::jobs::{Job, JobKind};
+use crate::waveform;
+use crate::loudness::{AlbumHistogram, BlockHistogram, Loudness};
+use crate::lyrics::Lyrics;
+use crate::playlist::{self, Playlist, PlaylistEdit, PlaylistFormat, PlaylistImportSummary};
+use crate::podcast::Podcast;
+use crate::preview;
+use crate::search::SearchHit;
+use crate::silence::Silence;
+use crate::metadata::{self, AudioFormat, AudioProperties, Picture, ReplayGain, SongMetadata};
+use sha2::{Digest, Sha256};
+
 #[derive(Debug)]
 pub enum IncomingMessage {
     Http(IncomingHttpRequest),
@@ -21,6 +39,73 @@ pub enum SongDbRequest{
     GetAllTags,
     //AddSong(Song),
     UploadSong(UploadSongRequest),
+    ExportLibrary(ExportLibraryRequest),
+    ImportLibrary(ImportLibraryRequest),
+    DeleteSong(String),
+    RestoreSong(String),
+    ListTrash,
+    GetStats,
+    ImportDirectory(String),
+    SeedDemoTracks,
+    RemoveDemoTracks,
+    GetSettings,
+    UpdateSettings(Settings),
+    GetArtwork(String),
+    GetWaveform(String),
+    GetPreview(String),
+    GetLyrics(String),
+    /// LRC or plain text; empty removes the lyrics
+    SetLyrics { song_id: String, lyrics: String },
+    Search(String),
+    /// empty goes back to the chapters in the file's own tags
+    SetCueSheet { song_id: String, cue_sheet: String },
+    GetFingerprint(String),
+    /// songs here that are the same recording as a fingerprint from another node
+    FindRecording(Vec<u32>),
+    FindDuplicates,
+    /// keep defaults to the best quality song of the lot
+    MergeSongs { song_ids: Vec<String>, keep: Option<String> },
+    GetPodcasts,
+    /// None unpublishes the tag
+    PublishPodcast { tag_key: String, podcast: Option<Podcast> },
+    GetSilence(String),
+    /// turns split points into chapters; None takes the proposed ones
+    SplitSong { song_id: String, split_points: Option<Vec<u64>> },
+    GetPlaylists,
+    /// None creates a playlist
+    SavePlaylist { id: Option<String>, edit: PlaylistEdit },
+    DeletePlaylist(String),
+    /// playlist file in blob; the format is detected when None
+    ImportPlaylist { name: Option<String>, format: Option<PlaylistFormat> },
+    /// None turns the Subsonic API off
+    SetSubsonicPassword(Option<String>),
+}
+
+impl SongDbRequest {
+    /// Requests that only our own node may make; peers get an error back
+    pub fn is_owner_only(&self) -> bool {
+        matches!(
+            self,
+            SongDbRequest::ExportLibrary(_)
+                | SongDbRequest::ImportLibrary(_)
+                | SongDbRequest::DeleteSong(_)
+                | SongDbRequest::RestoreSong(_)
+                | SongDbRequest::ImportDirectory(_)
+                | SongDbRequest::SeedDemoTracks
+                | SongDbRequest::RemoveDemoTracks
+                | SongDbRequest::UpdateSettings(_)
+                | SongDbRequest::SetLyrics { .. }
+                | SongDbRequest::SetCueSheet { .. }
+                | SongDbRequest::FindDuplicates
+                | SongDbRequest::MergeSongs { .. }
+                | SongDbRequest::SplitSong { .. }
+                | SongDbRequest::PublishPodcast { .. }
+                | SongDbRequest::SavePlaylist { .. }
+                | SongDbRequest::DeletePlaylist(_)
+                | SongDbRequest::ImportPlaylist { .. }
+                | SongDbRequest::SetSubsonicPassword(_)
+        )
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -30,6 +115,60 @@ pub struct UploadSongRequest {
     //data in blob
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportLibraryRequest {
+    /// vfs path to write the archive to; if None the archive is returned in the response blob
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportLibraryRequest {
+    /// vfs path to read the archive from; if None the archive is expected in the request blob
+    pub path: Option<String>,
+    pub on_conflict: ConflictPolicy,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DirectoryImportSummary {
+    pub imported: Vec<String>,
+    /// files that were already imported from the same path
+    pub skipped: Vec<String>,
+    /// (vfs path, error)
+    pub failed: Vec<(String, String)>,
+}
+
+/// What to do when an imported song id already exists in the SongDb
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+    Skip,
+    Overwrite,
+    KeepBoth,
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ConflictPolicy::Skip),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "keep_both" => Ok(ConflictPolicy::KeepBoth),
+            _ => Err(anyhow::anyhow!("unknown conflict policy: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ImportSummary {
+    pub imported: Vec<String>,
+    pub overwritten: Vec<String>,
+    /// (id in archive, id it was stored under)
+    pub renamed: Vec<(String, String)>,
+    pub skipped: Vec<String>,
+    /// catalog entries whose audio file was missing from the archive
+    pub missing: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum SongDbResponse {
     Songs(Vec<Song>),
@@ -37,57 +176,959 @@ pub enum SongDbResponse {
     Tags(Vec<String>),
     SongAdded,
     //SongRemoved(bool),
+    UploadRejected(IngestError),
+    LibraryExported { path: Option<String>, songs: usize },
+    LibraryImported(ImportSummary),
+    SongDeleted,
+    SongRestored,
+    Trash(Vec<TrashedSong>),
+    Stats(LibraryStats),
+    DirectoryImported(DirectoryImportSummary),
+    DemoTracksSeeded(usize),
+    DemoTracksRemoved(usize),
+    Settings(Settings),
+    /// image bytes are in the blob
+    Artwork { mime: String },
+    /// (min, max) pairs in -1.0..=1.0
+    Waveform(Vec<(f32, f32)>),
+    /// the song is still queued for peak generation
+    WaveformPending,
+    /// MP3 clip bytes are in the blob
+    Preview,
+    Lyrics(Option<Lyrics>),
+    SearchResults(Vec<SearchHit>),
+    Chapters(Vec<Chapter>),
+    Podcasts(HashMap<String, Podcast>),
+    /// None when the song is entirely silent
+    Silence(Option<Silence>),
+    /// the song is still queued for silence detection
+    SilencePending,
+    Fingerprint(Vec<u32>),
+    /// the song is still queued for fingerprinting
+    FingerprintPending,
+    /// (song id, similarity), most similar first
+    Recordings(Vec<(String, f32)>),
+    Duplicates(Vec<DuplicateGroup>),
+    SongsMerged(MergeSummary),
+    Playlists(Vec<Playlist>),
+    Playlist(Playlist),
+    PlaylistDeleted,
+    PlaylistImported(PlaylistImportSummary),
+    SubsonicEnabled(bool),
     Error(String),
 } 
 
+pub const TRASH_DIR: &str = "trash";
+/// Cover art, one file per distinct image named by its sha256
+pub const ARTWORK_DIR: &str = "artwork";
+/// Waveform peak sidecars, "<song id>.peaks"
+pub const WAVEFORM_DIR: &str = "waveforms";
+/// Loudness gating block histograms, "<song id>.lhist"
+pub const LOUDNESS_DIR: &str = "loudness";
+/// Preview clips, named like the song itself
+pub const PREVIEW_DIR: &str = "previews";
+/// Acoustic fingerprints, "<song id>.fp"
+pub const FINGERPRINT_DIR: &str = "fingerprints";
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct LibraryStats {
+    pub total_songs: usize,
+    pub library_bytes: u64,
+    pub trash_bytes: u64,
+    /// artwork, waveforms, loudness histograms, previews and fingerprints
+    pub derived_bytes: u64,
+    /// everything we hold in music_db: library + trash + derived files
+    pub total_bytes: u64,
+    pub bytes_per_tag: HashMap<String, u64>,
+    pub largest_files: Vec<SongSize>,
+    /// "YYYY-MM-DD" (UTC): uploads that day
+    pub uploads_by_day: BTreeMap<String, DayUploads>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+pub struct SongSize {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DayUploads {
+    pub songs: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// how long a deleted song stays restorable before it is purged from vfs
+    pub trash_retention_secs: u64,
+    /// import the demo tracks on the next start, unless they were imported once already
+    pub seed_demo_tracks: bool,
+    /// uploads bigger than this are rejected
+    pub max_upload_bytes: u64,
+    /// where previews start, as a percentage of the song's length
+    pub preview_offset_percent: u8,
+    pub preview_secs: u32,
+    /// peers may fetch previews of any song
+    pub share_previews: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            trash_retention_secs: 30 * 24 * 60 * 60,
+            seed_demo_tracks: true,
+            max_upload_bytes: 512 * 1024 * 1024,
+            preview_offset_percent: 30,
+            preview_secs: 25,
+            share_previews: true,
+        }
+    }
+}
+
+impl Settings {
+    /// What a fresh install starts with: the package's pkg/settings.json where it has one, so
+    /// that e.g. demo seeding can be turned off before the first start
+    pub fn initial(our: &Address) -> Settings {
+        let path = format!("/{}/pkg/settings.json", our.package_id());
+        match vfs::open_file(&path, false, None).and_then(|file| file.read()) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                println!("Ignoring {}: {}", path, e);
+                Settings::default()
+            }),
+            Err(_) => Settings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrashedSong {
+    pub trash_id: String, // the same song can be in the trash more than once, so it's keyed by this
+    pub song: Song,
+    pub deleted_at: u64, // unix seconds
+}
+
+/// Bumped when old state needs more than serde defaults for new fields to be read, with the
+/// conversion added to SongDb::from_state
+const STATE_VERSION: u32 = 1;
+
+/// What set_state holds, as JSON so that fields can be added without losing saved state
+#[derive(Serialize, Deserialize)]
+struct SavedState<T> {
+    version: u32,
+    db: T,
+}
+
+/// The catalog as the first release saved it
+#[derive(Deserialize)]
+struct LegacySongDb {
+    _vfs_dir_path: String,
+    songs: HashMap<String, Vec<LegacySong>>,
+}
+
+#[derive(Deserialize)]
+struct LegacySong {
+    id: String,
+    name: String,
+    _data: Vec<u8>,
+    tag: Tag,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct SongDb {
     pub vfs_dir_path: String,
     pub songs: HashMap<String, Vec<Song>>,
+    pub trash: HashMap<String, TrashedSong>, // trash id: trashed song
+    pub settings: Settings,
+    pub jobs: VecDeque<Job>,
+    pub catalog_version: u64,
+    /// published tags, by tag key
+    pub podcasts: HashMap<String, Podcast>,
+    pub playlists: HashMap<String, Playlist>,
+    /// what Subsonic clients log in with; None turns the Subsonic API off
+    pub subsonic_password: Option<String>,
+    pub catalog_modified_at: u64, // unix seconds
+    /// set once the demo tracks were imported, so removing them doesn't bring them back
+    #[serde(default = "seeded_before_tracking")]
+    pub demo_tracks_seeded: bool,
+    #[serde(skip)]
+    pub job_timer_armed: bool,
+    /// decoded fingerprint files, None where there is none; see load_fingerprints
+    #[serde(skip)]
+    pub fingerprints: HashMap<String, Option<Vec<u32>>>,
+    /// merged loudness histograms by tag key; see update_replay_gain
+    #[serde(skip)]
+    pub album_histograms: HashMap<String, AlbumHistogram>,
+}
+/// State saved before demo_tracks_seeded existed was seeded on its first start
+fn seeded_before_tracking() -> bool {
+    true
 }
+
 impl SongDb {
     pub fn new(vfs_dir: &Directory) -> Self {
         Self {
             vfs_dir_path: vfs_dir.path.to_string(),
             songs: HashMap::new(),
+            trash: HashMap::new(),
+            settings: Settings::default(),
+            jobs: VecDeque::new(),
+            catalog_version: 0,
+            podcasts: HashMap::new(),
+            playlists: HashMap::new(),
+            subsonic_password: None,
+            catalog_modified_at: now_secs(),
+            demo_tracks_seeded: false,
+            job_timer_armed: false,
+            fingerprints: HashMap::new(),
+            album_histograms: HashMap::new(),
         }
     }
 
     pub fn load(vfs_dir: &Directory) -> Self {
-        match get_state() {
-            Some(state_bytes) => {
-                let mut db: SongDb = bincode::deserialize(&state_bytes)
-                    .unwrap_or_else(|_| Self::new(vfs_dir));
-                db.vfs_dir_path = vfs_dir.path.to_string();
-                db
+        let Some(state_bytes) = get_state() else {
+            return Self::new(vfs_dir);
+        };
+        match Self::from_state(&state_bytes, vfs_dir) {
+            Ok(db) => db,
+            // starting empty would overwrite the catalog on the next save and orphan every song in vfs
+            Err(e) => panic!("Saved state can't be read, refusing to start over it: {:?}", e),
+        }
+    }
+
+    fn from_state(state_bytes: &[u8], vfs_dir: &Directory) -> anyhow::Result<Self> {
+        let state: SavedState<SongDb> = match serde_json::from_slice(state_bytes) {
+            Ok(state) => state,
+            // the first release saved bincode, which has no way of telling old fields from new ones
+            Err(json_error) => match bincode::deserialize::<LegacySongDb>(state_bytes) {
+                Ok(legacy) => return Ok(Self::migrate_legacy(legacy, vfs_dir)),
+                Err(_) => return Err(json_error.into()),
             },
-            None => Self::new(vfs_dir)
+        };
+        if state.version > STATE_VERSION {
+            anyhow::bail!("state was saved by a newer version ({}, we read up to {})", state.version, STATE_VERSION);
         }
+        let mut db = state.db;
+        db.vfs_dir_path = vfs_dir.path.to_string();
+        Ok(db)
+    }
+
+    /// Re-reads each song's file so it gets everything ingest finds in it today
+    fn migrate_legacy(legacy: LegacySongDb, vfs_dir: &Directory) -> Self {
+        let mut db = Self::new(vfs_dir);
+        // a library that's been in use has no need for demo tracks
+        db.demo_tracks_seeded = true;
+        for old in legacy.songs.into_values().flatten() {
+            let data = vfs::open_file(&db.song_path(&old.id), false, None).and_then(|file| file.read()).unwrap_or_default();
+            let song = match ingest::song_from_upload(&old.name, old.tag.clone(), data.clone(), u64::MAX) {
+                Ok(song) => Song { id: old.id, ..song },
+                Err(e) => {
+                    println!("Migrating {} without its tags: {}", old.id, e);
+                    let format = old.id.rsplit_once('.').and_then(|(_, ext)| AudioFormat::from_extension(ext)).unwrap_or_default();
+                    Song { id: old.id, name: old.name, tag: old.tag, format, data, ..Default::default() }
+                }
+            };
+            db.catalog_song(song);
+        }
+        db.save();
+        db
+    }
+
+    /// Persists a change to the catalog; bumping the version invalidates cached listings
+    pub fn save(&mut self) {
+        self.catalog_version += 1;
+        self.catalog_modified_at = now_secs();
+        self.save_queue();
     }
 
-    pub fn save(&self) {
-        let state_bytes = bincode::serialize(self).expect("Failed to serialize state");
+    /// Persists job queue bookkeeping, which listings don't show
+    pub fn save_queue(&self) {
+        let state = SavedState { version: STATE_VERSION, db: self };
+        let state_bytes = serde_json::to_vec(&state).expect("Failed to serialize state");
         set_state(&state_bytes);
     }
 
-    pub fn add_song(&mut self, mut song: Song) -> anyhow::Result<()> {
-        let file_path = format!("{}/{}", self.vfs_dir_path, song.id);
+    pub fn add_song(&mut self, song: Song) -> anyhow::Result<()> {
+        let file_path = self.song_path(&song.id);
         let mut file = vfs::create_file(&file_path, None)?;
         file.write_all(&song.data)?;
 
         println!("Saved file to: {}", file_path);
 
+        self.catalog_song(song);
+        self.save();
+        Ok(())
+    }
+
+    /// Everything add_song does once the file is in place: what's derived from the data, and
+    /// the analysis jobs
+    fn catalog_song(&mut self, mut song: Song) {
+        song.size = song.data.len() as u64;
+        song.content_hash = format!("{:x}", Sha256::digest(&song.data));
+        song.artwork = metadata::read_picture(song.format, &song.data).and_then(|picture| {
+            self.store_artwork(&picture)
+                .map_err(|e| println!("Failed to store artwork for {}: {:?}", song.id, e))
+                .ok()
+        });
+        if song.uploaded_at == 0 {
+            song.uploaded_at = now_secs();
+        }
+
         // Clear the data after writing to file to save memory
         song.data.clear();
+        self.queue_job(Job::new(JobKind::Waveform, &song.id));
+        self.queue_job(Job::new(JobKind::Loudness, &song.id));
+        self.queue_job(Job::new(JobKind::Fingerprint, &song.id));
+        if song.bpm.is_none() {
+            self.queue_job(Job::new(JobKind::Tempo, &song.id));
+        }
+        if song.key.is_none() {
+            self.queue_job(Job::new(JobKind::Key, &song.id));
+        }
+        self.queue_job(Job::new(JobKind::Silence, &song.id));
+        if song.format == AudioFormat::Mp3 {
+            self.queue_job(Job::new(JobKind::Preview, &song.id));
+        }
 
-        self.songs.entry(song.tag.key.clone())
+        let tag_key = song.tag.key.clone();
+        self.songs.entry(tag_key.clone())
             .or_insert_with(Vec::new)
             .push(song);
+        self.update_replay_gain(&tag_key);
+    }
+
+    pub fn song_path(&self, song_id: &str) -> String {
+        format!("{}/{}", self.vfs_dir_path, song_id)
+    }
+
+    pub fn get_song(&self, song_id: &str) -> Option<&Song> {
+        self.songs.values().flatten().find(|song| song.id == song_id)
+    }
+
+    /// A chapter by its track id, see chapters::track_id
+    pub fn get_track(&self, track_id: &str) -> Option<(&Song, &Chapter)> {
+        let (song_id, index) = chapters::parse_track_id(track_id)?;
+        let song = self.get_song(song_id)?;
+        Some((song, song.chapters.get(index - 1)?))
+    }
+
+    pub fn contains_song(&self, song_id: &str) -> bool {
+        self.get_song(song_id).is_some()
+    }
+
+    /// "song.mp3" -> "song (1).mp3", "song (2).mp3", ... whichever is free first
+    pub fn free_song_id(&self, song_id: &str) -> String {
+        if !self.contains_song(song_id) {
+            return song_id.to_string();
+        }
+        let (stem, ext) = match song_id.rsplit_once('.') {
+            Some((stem, ext)) => (stem, format!(".{}", ext)),
+            None => (song_id, String::new()),
+        };
+        (1..)
+            .map(|n| format!("{} ({}){}", stem, n, ext))
+            .find(|candidate| !self.contains_song(candidate))
+            .unwrap()
+    }
+
+    /// Removes the catalog entry only; the file in vfs is left alone
+    pub fn remove_song(&mut self, song_id: &str) -> Option<Song> {
+        let mut removed = None;
+        for songs in self.songs.values_mut() {
+            if let Some(idx) = songs.iter().position(|song| song.id == song_id) {
+                removed = Some(songs.remove(idx));
+                break;
+            }
+        }
+        self.songs.retain(|_, songs| !songs.is_empty());
+        if let Some(song) = &removed {
+            self.update_replay_gain(&song.tag.key);
+            self.save();
+        }
+        removed
+    }
+
+    pub fn artwork_path(&self, artwork_id: &str) -> String {
+        format!("{}/{}/{}", self.vfs_dir_path, ARTWORK_DIR, artwork_id)
+    }
+
+    /// Songs from the same album usually embed the same image, so it's only written once
+    fn store_artwork(&self, picture: &Picture) -> anyhow::Result<String> {
+        let artwork_id = format!("{:x}.{}", Sha256::digest(&picture.data), picture.extension());
+        let path = self.artwork_path(&artwork_id);
+        if vfs::open_file(&path, false, None).is_err() {
+            let mut file = vfs::create_file(&path, None)?;
+            file.write_all(&picture.data)?;
+        }
+        Ok(artwork_id)
+    }
+
+    /// Artwork ids are only ever "<sha256 hex>.<ext>", anything else can't name a file in ARTWORK_DIR
+    pub fn get_artwork(&self, artwork_id: &str) -> anyhow::Result<(&'static str, Vec<u8>)> {
+        let (hash, extension) = artwork_id.split_once('.')
+            .filter(|(hash, extension)| {
+                hash.len() == 64
+                    && hash.chars().all(|c| c.is_ascii_hexdigit())
+                    && extension.chars().all(|c| c.is_ascii_alphanumeric())
+            })
+            .ok_or_else(|| anyhow::anyhow!("Invalid artwork id: {}", artwork_id))?;
+        let data = vfs::open_file(&self.artwork_path(&format!("{}.{}", hash, extension)), false, None)?.read()?;
+        Ok((metadata::image_mime_from_extension(extension), data))
+    }
+
+    pub fn waveform_path(&self, song_id: &str) -> String {
+        format!("{}/{}/{}.peaks", self.vfs_dir_path, WAVEFORM_DIR, song_id)
+    }
+
+    /// Ok(None) while the song is still waiting in the job queue
+    pub fn get_waveform(&self, song_id: &str) -> anyhow::Result<Option<Vec<(f32, f32)>>> {
+        if !self.contains_song(song_id) {
+            anyhow::bail!("No song with id {}", song_id);
+        }
+        if self.jobs.iter().any(|job| job.kind == JobKind::Waveform && job.song_id == song_id) {
+            return Ok(None);
+        }
+        let bytes = vfs::open_file(&self.waveform_path(song_id), false, None)?.read()?;
+        waveform::decode(&bytes).map(Some).ok_or_else(|| anyhow::anyhow!("Corrupt waveform file for {}", song_id))
+    }
+
+    pub fn loudness_path(&self, song_id: &str) -> String {
+        format!("{}/{}/{}.lhist", self.vfs_dir_path, LOUDNESS_DIR, song_id)
+    }
+
+    pub fn fingerprint_path(&self, song_id: &str) -> String {
+        format!("{}/{}/{}.fp", self.vfs_dir_path, FINGERPRINT_DIR, song_id)
+    }
+
+    /// Ok(None) while the song is still waiting in the job queue
+    pub fn get_fingerprint(&self, song_id: &str) -> anyhow::Result<Option<Vec<u32>>> {
+        if !self.contains_song(song_id) {
+            anyhow::bail!("No song with id {}", song_id);
+        }
+        if self.jobs.iter().any(|job| job.kind == JobKind::Fingerprint && job.song_id == song_id) {
+            return Ok(None);
+        }
+        if let Some(Some(fingerprint)) = self.fingerprints.get(song_id) {
+            return Ok(Some(fingerprint.clone()));
+        }
+        let bytes = vfs::open_file(&self.fingerprint_path(song_id), false, None)?.read()?;
+        fingerprint::decode(&bytes).map(Some).ok_or_else(|| anyhow::anyhow!("Corrupt fingerprint file for {}", song_id))
+    }
+
+    pub fn set_fingerprint(&mut self, song_id: &str, fingerprint: Vec<u32>) -> anyhow::Result<()> {
+        let mut file = vfs::create_file(&self.fingerprint_path(song_id), None)?;
+        file.write_all(&fingerprint::encode(&fingerprint))?;
+        self.fingerprints.insert(song_id.to_string(), Some(fingerprint));
+        Ok(())
+    }
+
+    /// Reads the fingerprint files of library songs that aren't cached yet, so that comparing
+    /// every song against the others doesn't go to vfs each time
+    pub fn load_fingerprints(&mut self) {
+        let uncached: Vec<String> = self.songs.values().flatten()
+            .filter(|song| !self.fingerprints.contains_key(&song.id) && !self.has_queued_job(JobKind::Fingerprint, &song.id))
+            .map(|song| song.id.clone())
+            .collect();
+        for song_id in uncached {
+            let fingerprint = vfs::open_file(&self.fingerprint_path(&song_id), false, None)
+                .and_then(|file| file.read())
+                .ok()
+                .and_then(|bytes| fingerprint::decode(&bytes));
+            self.fingerprints.insert(song_id, fingerprint);
+        }
+    }
+
+    /// The cached fingerprint of a song that isn't waiting to be fingerprinted again
+    pub fn cached_fingerprint(&self, song_id: &str) -> Option<&[u32]> {
+        if self.has_queued_job(JobKind::Fingerprint, song_id) {
+            return None;
+        }
+        self.fingerprints.get(song_id)?.as_deref()
+    }
+
+    pub fn preview_path(&self, song_id: &str) -> String {
+        format!("{}/{}/{}", self.vfs_dir_path, PREVIEW_DIR, song_id)
+    }
+
+    /// Cuts and stores a song's preview clip
+    pub fn make_preview(&self, song_id: &str) -> anyhow::Result<Vec<u8>> {
+        let song = self.get_song(song_id).ok_or_else(|| anyhow::anyhow!("No song with id {}", song_id))?;
+        if song.format != AudioFormat::Mp3 {
+            anyhow::bail!("Previews are only available for MP3 songs");
+        }
+        let data = vfs::open_file(&self.song_path(song_id), false, None)?.read()?;
+        let clip = preview::cut_mp3(&data, self.settings.preview_offset_percent, self.settings.preview_secs)
+            .ok_or_else(|| anyhow::anyhow!("No MPEG frames in {}", song_id))?;
+        let mut file = vfs::create_file(&self.preview_path(song_id), None)?;
+        file.write_all(&clip)?;
+        Ok(clip)
+    }
+
+    /// The stored clip, cut on the spot if its job hasn't got to it yet
+    pub fn get_preview(&self, song_id: &str) -> anyhow::Result<Vec<u8>> {
+        if !self.contains_song(song_id) {
+            anyhow::bail!("No song with id {}", song_id);
+        }
+        let queued = self.jobs.iter().any(|job| job.kind == JobKind::Preview && job.song_id == song_id);
+        if !queued {
+            if let Ok(clip) = vfs::open_file(&self.preview_path(song_id), false, None).and_then(|file| file.read()) {
+                return Ok(clip);
+            }
+        }
+        self.make_preview(song_id)
+    }
+
+    /// Stored previews are re-cut when the settings they were cut with change
+    pub fn update_settings(&mut self, settings: Settings) {
+        let recut_previews = settings.preview_offset_percent != self.settings.preview_offset_percent
+            || settings.preview_secs != self.settings.preview_secs;
+        self.settings = settings;
+        if recut_previews {
+            let mp3_ids: Vec<String> = self.songs.values().flatten()
+                .filter(|song| song.format == AudioFormat::Mp3)
+                .map(|song| song.id.clone())
+                .collect();
+            for song_id in mp3_ids {
+                self.queue_job(Job::new(JobKind::Preview, &song_id));
+            }
+        }
+        self.save();
+    }
 
+    pub fn set_loudness(&mut self, song_id: &str, loudness: Loudness) {
+        let Some(song) = self.songs.values_mut().flatten().find(|song| song.id == song_id) else {
+            return;
+        };
+        song.loudness = Some(loudness);
+        let tag_key = song.tag.key.clone();
+        // a re-analysed song has a new histogram on disk
+        if let Some(album) = self.album_histograms.get_mut(&tag_key) {
+            album.remove(song_id);
+        }
+        self.update_replay_gain(&tag_key);
+        self.save();
+    }
+
+    /// Analysis results only fill in what the file's tags didn't say
+    pub fn set_tempo(&mut self, song_id: &str, bpm: Option<f32>) {
+        if let Some(song) = self.songs.values_mut().flatten().find(|song| song.id == song_id) {
+            song.bpm = song.bpm.or(bpm);
+            self.save();
+        }
+    }
+
+    pub fn set_key(&mut self, song_id: &str, key: Option<Key>) {
+        if let Some(song) = self.songs.values_mut().flatten().find(|song| song.id == song_id) {
+            song.key = song.key.or(key);
+            self.save();
+        }
+    }
+
+    /// Recomputes the gain players should apply to each song in a tag. Values from the file's own
+    /// ReplayGain tags win, the rest come from our analysis; album gain treats the tag as the album.
+    /// The tag's merged histogram is cached, so only songs analysed or removed since the last
+    /// call are read from or taken out of it.
+    pub fn update_replay_gain(&mut self, tag_key: &str) {
+        let mut album = self.album_histograms.remove(tag_key).unwrap_or_default();
+        let Some(songs) = self.songs.get(tag_key) else {
+            return;
+        };
+        let analysed: HashSet<&str> = songs.iter()
+            .filter(|song| song.loudness.is_some())
+            .map(|song| song.id.as_str())
+            .collect();
+        let gone: Vec<String> = album.song_ids()
+            .filter(|song_id| !analysed.contains(song_id.as_str()))
+            .cloned()
+            .collect();
+        for song_id in gone {
+            album.remove(&song_id);
+        }
+        for song_id in analysed {
+            if album.contains(song_id) {
+                continue;
+            }
+            let histogram = vfs::open_file(&self.loudness_path(song_id), false, None)
+                .and_then(|file| file.read())
+                .ok()
+                .and_then(|bytes| BlockHistogram::decode(&bytes));
+            album.insert(song_id, histogram.as_ref());
+        }
+        let album_peak = songs.iter()
+            .filter(|song| album.has_histogram(&song.id))
+            .filter_map(|song| song.loudness.map(|loudness| loudness.true_peak))
+            .reduce(f64::max);
+        let album_loudness = album.integrated_lufs()
+            .zip(album_peak)
+            .map(|(integrated_lufs, true_peak)| Loudness { integrated_lufs, true_peak });
+
+        for song in self.songs.get_mut(tag_key).into_iter().flatten() {
+            let mut replay_gain = song.metadata.replay_gain.clone();
+            replay_gain.fill_missing(ReplayGain {
+                track_gain_db: song.loudness.map(|loudness| loudness.gain_db() as f32),
+                track_peak: song.loudness.map(|loudness| loudness.true_peak as f32),
+                album_gain_db: album_loudness.map(|loudness| loudness.gain_db() as f32),
+                album_peak: album_loudness.map(|loudness| loudness.true_peak as f32),
+            });
+            song.replay_gain = replay_gain;
+        }
+        self.album_histograms.insert(tag_key.to_string(), album);
+    }
+
+    /// Replaces a song's lyrics with LRC or plain text, an empty text removes them
+    pub fn set_lyrics(&mut self, song_id: &str, text: &str) -> anyhow::Result<Option<Lyrics>> {
+        let song = self.songs.values_mut().flatten()
+            .find(|song| song.id == song_id)
+            .ok_or_else(|| anyhow::anyhow!("no song with id {}", song_id))?;
+        song.lyrics = Lyrics::parse(text);
+        let lyrics = song.lyrics.clone();
+        self.save();
+        Ok(lyrics)
+    }
+
+    /// Replaces a song's chapters with those of a cue sheet. An empty sheet reads them from the
+    /// file's own tags again.
+    pub fn set_cue_sheet(&mut self, song_id: &str, cue_sheet: &str) -> anyhow::Result<Vec<Chapter>> {
+        let song = self.get_song(song_id).ok_or_else(|| anyhow::anyhow!("no song with id {}", song_id))?;
+        let duration_ms = song.properties.as_ref()
+            .map(|properties| properties.duration_ms)
+            .ok_or_else(|| anyhow::anyhow!("the length of {} isn't known", song_id))?;
+        let marks = if cue_sheet.trim().is_empty() {
+            let data = vfs::open_file(&self.song_path(song_id), false, None)?.read()?;
+            metadata::read_chapters(song.format, &data)
+        } else {
+            chapters::parse_cue(cue_sheet)
+        };
+        let chapters = chapters::close(marks, duration_ms);
+
+        if let Some(song) = self.songs.values_mut().flatten().find(|song| song.id == song_id) {
+            song.chapters = chapters.clone();
+        }
+        self.save();
+        Ok(chapters)
+    }
+
+    /// Chapters running from the start of the audio to the first split, between splits, and
+    /// from the last one to the end of the audio, with leading and trailing silence left out
+    pub fn split_song(&mut self, song_id: &str, split_points: Option<Vec<u64>>) -> anyhow::Result<Vec<Chapter>> {
+        let song = self.get_song(song_id).ok_or_else(|| anyhow::anyhow!("no song with id {}", song_id))?;
+        let silence = song.silence.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("{} hasn't been checked for silence yet", song_id))?;
+        let split_points = split_points.unwrap_or_else(|| silence.proposed_splits.clone());
+        if split_points.is_empty() {
+            anyhow::bail!("no split points for {}", song_id);
+        }
+        let marks = std::iter::once(silence.trim_start_ms)
+            .chain(split_points.into_iter().filter(|&point| point > silence.trim_start_ms))
+            .map(|start_ms| chapters::ChapterMark { start_ms, ..Default::default() })
+            .collect();
+        let chapters = chapters::close(marks, silence.trim_end_ms);
+        if chapters.is_empty() {
+            anyhow::bail!("the split points don't fall within the audio of {}", song_id);
+        }
+
+        if let Some(song) = self.songs.values_mut().flatten().find(|song| song.id == song_id) {
+            song.chapters = chapters.clone();
+        }
+        self.save();
+        Ok(chapters)
+    }
+
+    pub fn set_silence(&mut self, song_id: &str, silence: Option<Silence>) {
+        if let Some(song) = self.songs.values_mut().flatten().find(|song| song.id == song_id) {
+            song.silence = silence;
+            self.save();
+        }
+    }
+
+    /// Publishes a tag as a podcast, or with None stops publishing it
+    pub fn set_podcast(&mut self, tag_key: &str, podcast: Option<Podcast>) -> anyhow::Result<()> {
+        match podcast {
+            Some(podcast) => {
+                if !self.songs.contains_key(tag_key) {
+                    anyhow::bail!("no tag {}", tag_key);
+                }
+                if let Some(artwork) = &podcast.artwork {
+                    self.get_artwork(artwork)?;
+                }
+                self.podcasts.insert(tag_key.to_string(), podcast);
+            }
+            None => {
+                self.podcasts.remove(tag_key);
+            }
+        }
+        self.save();
+        Ok(())
+    }
+
+    /// Playlists sorted by name
+    pub fn list_playlists(&self) -> Vec<Playlist> {
+        let mut playlists: Vec<Playlist> = self.playlists.values().cloned().collect();
+        playlists.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()).then(a.created_at.cmp(&b.created_at)));
+        playlists
+    }
+
+    /// Creates a playlist when id is None, otherwise changes the fields the edit has
+    pub fn save_playlist(&mut self, id: Option<&str>, edit: PlaylistEdit) -> anyhow::Result<Playlist> {
+        if let Some(name) = &edit.name {
+            if name.trim().is_empty() {
+                anyhow::bail!("playlist name is empty");
+            }
+        }
+        if let Some(missing) = edit.song_ids.iter().flatten().find(|song_id| !self.contains_song(song_id)) {
+            anyhow::bail!("no song {}", missing);
+        }
+        let now = now_secs();
+        let playlist = match id {
+            Some(id) => {
+                let playlist = self.playlists.get_mut(id).ok_or_else(|| anyhow::anyhow!("no playlist {}", id))?;
+                if let Some(name) = edit.name {
+                    playlist.name = name.trim().to_string();
+                }
+                if let Some(song_ids) = edit.song_ids {
+                    playlist.song_ids = song_ids;
+                }
+                playlist.updated_at = now;
+                playlist.clone()
+            }
+            None => {
+                let name = edit.name.ok_or_else(|| anyhow::anyhow!("a new playlist needs a name"))?;
+                let playlist = Playlist {
+                    id: uuid::Uuid::new_v4().to_string(),
+                    name: name.trim().to_string(),
+                    song_ids: edit.song_ids.unwrap_or_default(),
+                    created_at: now,
+                    updated_at: now,
+                };
+                self.playlists.insert(playlist.id.clone(), playlist.clone());
+                playlist
+            }
+        };
+        self.save();
+        Ok(playlist)
+    }
+
+    pub fn delete_playlist(&mut self, id: &str) -> anyhow::Result<()> {
+        self.playlists.remove(id).ok_or_else(|| anyhow::anyhow!("no playlist {}", id))?;
         self.save();
         Ok(())
     }
 
+    /// Songs of a playlist in order, skipping any deleted since
+    pub fn playlist_songs(&self, playlist: &Playlist) -> Vec<&Song> {
+        playlist.song_ids.iter().filter_map(|song_id| self.get_song(song_id)).collect()
+    }
+
+    /// Creates a playlist from an M3U8, PLS or XSPF file, keeping the entries that match a song
+    pub fn import_playlist(&mut self, name: Option<String>, format: Option<PlaylistFormat>, text: &str) -> anyhow::Result<PlaylistImportSummary> {
+        let format = format.unwrap_or_else(|| PlaylistFormat::detect(text));
+        let entries = playlist::parse(format, text);
+        if entries.is_empty() {
+            anyhow::bail!("no entries found in {} playlist", format.extension());
+        }
+        let name = name
+            .filter(|name| !name.trim().is_empty())
+            .or_else(|| playlist::parse_name(format, text))
+            .unwrap_or_else(|| "Imported playlist".to_string());
+
+        let mut song_ids = Vec::new();
+        let mut unmatched = Vec::new();
+        for entry in entries {
+            match playlist::find_song(self, &entry) {
+                Some(song) => song_ids.push(song.id.clone()),
+                None => unmatched.push(entry),
+            }
+        }
+        let playlist = self.save_playlist(None, PlaylistEdit { name: Some(name), song_ids: Some(song_ids) })?;
+        Ok(PlaylistImportSummary { playlist, unmatched })
+    }
+
+    /// Counts a play a player reported, e.g. a Subsonic scrobble
+    pub fn record_play(&mut self, song_id: &str, played_at: u64) {
+        if let Some(song) = self.songs.values_mut().flatten().find(|song| song.id == song_id) {
+            song.play_count += 1;
+            song.last_played = song.last_played.max(Some(played_at));
+            self.save();
+        }
+    }
+
+    pub fn set_subsonic_password(&mut self, password: Option<String>) {
+        self.subsonic_password = password.filter(|password| !password.is_empty());
+        self.save();
+    }
+
+    pub fn has_queued_job(&self, kind: JobKind, song_id: &str) -> bool {
+        self.jobs.iter().any(|job| job.kind == kind && job.song_id == song_id)
+    }
+
+    /// A song that's queued again (e.g. overwritten by an import) keeps its place in line
+    pub fn queue_job(&mut self, job: Job) {
+        if job.kind == JobKind::Fingerprint {
+            self.fingerprints.remove(&job.song_id);
+        }
+        if !self.jobs.iter().any(|queued| queued.kind == job.kind && queued.song_id == job.song_id) {
+            self.jobs.push_back(job);
+        }
+    }
+
+    /// Cleans up what was generated from a song that's gone for good
+    pub fn remove_derived_files(&self, song: &Song) {
+        if let Some(artwork_id) = &song.artwork {
+            self.release_artwork(artwork_id);
+        }
+        // not every song gets analysed, e.g. codecs we can't decode
+        let _ = vfs::remove_file(&self.waveform_path(&song.id), None);
+        let _ = vfs::remove_file(&self.loudness_path(&song.id), None);
+        let _ = vfs::remove_file(&self.preview_path(&song.id), None);
+        let _ = vfs::remove_file(&self.fingerprint_path(&song.id), None);
+    }
+
+    /// Removes the artwork file once no song, in the library or the trash, points at it
+    fn release_artwork(&self, artwork_id: &str) {
+        let in_use = self.songs.values().flatten()
+            .chain(self.trash.values().map(|trashed| &trashed.song))
+            .any(|song| song.artwork.as_deref() == Some(artwork_id));
+        if !in_use {
+            if let Err(e) = vfs::remove_file(&self.artwork_path(artwork_id), None) {
+                println!("Failed to remove artwork {}: {:?}", artwork_id, e);
+            }
+        }
+    }
+
+    pub fn trash_path(&self, trash_id: &str) -> String {
+        format!("{}/{}/{}", self.vfs_dir_path, TRASH_DIR, trash_id)
+    }
+
+    /// Moves a song into the trash, keeping its metadata so it can be restored
+    pub fn delete_song(&mut self, song_id: &str) -> anyhow::Result<()> {
+        if !self.contains_song(song_id) {
+            return Err(anyhow::anyhow!("song {} not found", song_id));
+        }
+        // the file goes first, so a failed move leaves the song where it was
+        let trash_id = uuid::Uuid::new_v4().to_string();
+        move_file(&self.song_path(song_id), &self.trash_path(&trash_id))?;
+        let song = self.remove_song(song_id)
+            .ok_or_else(|| anyhow::anyhow!("song {} not found", song_id))?;
+
+        self.trash.insert(trash_id.clone(), TrashedSong { trash_id, song, deleted_at: now_secs() });
+        self.save();
+        Ok(())
+    }
+
+    /// Restores by trash id, or by song id, taking the most recently deleted copy of it
+    pub fn restore_song(&mut self, id: &str) -> anyhow::Result<()> {
+        let trash_id = match self.trash.contains_key(id) {
+            true => id.to_string(),
+            false => self.trash.values()
+                .filter(|trashed| trashed.song.id == id)
+                .max_by_key(|trashed| trashed.deleted_at)
+                .map(|trashed| trashed.trash_id.clone())
+                .ok_or_else(|| anyhow::anyhow!("{} is not in the trash", id))?,
+        };
+        let song_id = self.trash[&trash_id].song.id.clone();
+        if self.contains_song(&song_id) {
+            return Err(anyhow::anyhow!("a song with id {} already exists", song_id));
+        }
+        move_file(&self.trash_path(&trash_id), &self.song_path(&song_id))?;
+        let trashed = self.trash.remove(&trash_id)
+            .ok_or_else(|| anyhow::anyhow!("{} is not in the trash", trash_id))?;
+
+        let tag_key = trashed.song.tag.key.clone();
+        self.songs.entry(tag_key.clone())
+            .or_insert_with(Vec::new)
+            .push(trashed.song);
+        self.update_replay_gain(&tag_key);
+
+        self.save();
+        Ok(())
+    }
+
+    /// Permanently removes trashed songs older than the retention period, returns their trash ids
+    pub fn purge_expired_trash(&mut self) -> Vec<String> {
+        let now = now_secs();
+        let retention = self.settings.trash_retention_secs;
+        let expired: Vec<String> = self.trash.iter()
+            .filter(|(_, trashed)| trashed.deleted_at.saturating_add(retention) <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for trash_id in &expired {
+            if let Err(e) = vfs::remove_file(&self.trash_path(trash_id), None) {
+                println!("Failed to purge {} from trash: {:?}", trash_id, e);
+            }
+            let Some(trashed) = self.trash.remove(trash_id) else {
+                continue;
+            };
+            // sidecars are named by song id, and may now belong to a re-uploaded song or another trashed copy
+            let still_used = self.contains_song(&trashed.song.id)
+                || self.trash.values().any(|other| other.song.id == trashed.song.id);
+            if still_used {
+                if let Some(artwork_id) = &trashed.song.artwork {
+                    self.release_artwork(artwork_id);
+                }
+            } else {
+                self.remove_derived_files(&trashed.song);
+            }
+        }
+
+        if !expired.is_empty() {
+            self.save();
+        }
+        expired
+    }
+
+    /// Storage and upload statistics, built from the sizes recorded at ingest and the sizes of
+    /// the files generated from each song
+    pub fn stats(&self, largest_files: usize) -> LibraryStats {
+        let mut stats = LibraryStats::default();
+        let mut sizes = Vec::new();
+
+        for (tag_key, songs) in &self.songs {
+            let tag_bytes: u64 = songs.iter().map(|song| song.size).sum();
+            stats.bytes_per_tag.insert(tag_key.clone(), tag_bytes);
+            stats.library_bytes += tag_bytes;
+            stats.total_songs += songs.len();
+
+            for song in songs {
+                let day = stats.uploads_by_day.entry(unix_day(song.uploaded_at)).or_default();
+                day.songs += 1;
+                day.bytes += song.size;
+                sizes.push(SongSize { id: song.id.clone(), name: song.name.clone(), size: song.size });
+            }
+        }
+
+        stats.trash_bytes = self.trash.values().map(|trashed| trashed.song.size).sum();
+        stats.derived_bytes = self.derived_bytes();
+        stats.total_bytes = stats.library_bytes + stats.trash_bytes + stats.derived_bytes;
+
+        sizes.sort_by(|a, b| b.size.cmp(&a.size));
+        sizes.truncate(largest_files);
+        stats.largest_files = sizes;
+        stats
+    }
+
+    /// Sizes of the sidecar files on disk; songs share artwork, and not every song has every sidecar
+    fn derived_bytes(&self) -> u64 {
+        let songs: Vec<&Song> = self.songs.values().flatten()
+            .chain(self.trash.values().map(|trashed| &trashed.song))
+            .collect();
+        let artwork: HashSet<&str> = songs.iter().filter_map(|song| song.artwork.as_deref()).collect();
+        let song_ids: HashSet<&str> = songs.iter().map(|song| song.id.as_str()).collect();
+
+        artwork.iter().map(|artwork_id| self.artwork_path(artwork_id))
+            .chain(song_ids.iter().flat_map(|song_id| [
+                self.waveform_path(song_id),
+                self.loudness_path(song_id),
+                self.preview_path(song_id),
+                self.fingerprint_path(song_id),
+            ]))
+            .filter_map(|path| vfs::metadata(&path, None).ok())
+            .map(|metadata| metadata.len)
+            .sum()
+    }
+
     pub fn get_songs_by_tag(&self, tag: &str) -> Vec<Song> {
         self.songs.get(tag).cloned().unwrap_or_else(Vec::new)
 
@@ -112,18 +1153,68 @@ impl SongDb {
 
 }
 
-#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// unix seconds -> "YYYY-MM-DD" (UTC)
+pub fn unix_day(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// (year, month, day) of a day count since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn move_file(from: &str, to: &str) -> anyhow::Result<()> {
+    let data = vfs::open_file(from, false, None)?.read()?;
+    let mut file = vfs::create_file(to, None)?;
+    file.write_all(&data)?;
+    vfs::remove_file(from, None)?;
+    Ok(())
+}
+
+#[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Tag {
     pub key: String, 
     pub name: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)] // fields added since a state or archive was written start out empty
 pub struct Song {
     pub id: String, 
     pub name: String,
     pub data: Vec<u8>,
     pub tag: Tag, 
+    pub size: u64, // bytes on disk, recorded at ingest
+    pub uploaded_at: u64, // unix seconds
+    pub source_path: Option<String>, // vfs path the song was bulk imported from
+    pub format: AudioFormat,
+    pub metadata: SongMetadata,
+    pub properties: Option<AudioProperties>,
+    pub artwork: Option<String>, // file name under ARTWORK_DIR
+    pub content_hash: String, // sha256 of the file, hex
+    pub loudness: Option<Loudness>, // filled in by a background job
+    pub replay_gain: ReplayGain, // what players should apply, see SongDb::update_replay_gain
+    pub lyrics: Option<Lyrics>, // from the file's tags, an uploaded .lrc, or edited by the owner
+    pub chapters: Vec<Chapter>, // virtual tracks, from a cue sheet or the file's chapter frames
+    pub bpm: Option<f32>, // from the file's tags, otherwise filled in by a background job
+    pub key: Option<Key>, // likewise
+    pub silence: Option<Silence>, // trim points and proposed split points, from a background job
+    pub play_count: u64, // plays reported by players, see SongDb::record_play
+    pub last_played: Option<u64>, // unix seconds
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
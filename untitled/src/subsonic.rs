@@ -0,0 +1,527 @@
+// Subsonic API under /rest/, so the many Subsonic and OpenSubsonic clients can browse and play
+// the library. Artists and albums are grouped from the songs' tags; a song without an album tag
+// is filed under the tag it was uploaded with. The user name is our node's name and the password
+// is one the owner sets, which clients send as is or as a salted MD5 token.
+// http://www.subsonic.org/pages/api.jsp https://opensubsonic.netlify.app/docs/
+
+use serde_json::{json, Map, Value};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::links::xml_escape;
+use crate::playlist::Playlist;
+use crate::search::{self, SongFilter};
+use crate::structs::{civil_from_days, now_secs, Song, SongDb};
+
+pub const PREFIX: &str = "/rest/";
+const API_VERSION: &str = "1.16.1";
+const SERVER_NAME: &str = "untitled";
+const UNKNOWN_ARTIST: &str = "Unknown Artist";
+/// Leading words skipped when sorting and indexing artists, "The Beatles" goes under B
+const IGNORED_ARTICLES: [&str; 3] = ["The", "A", "An"];
+/// We have a single music folder, the library
+const MUSIC_FOLDER_ID: u32 = 1;
+
+/// Endpoint name from the path, "/rest/getAlbum.view" -> "getAlbum"
+pub fn method(path: &str) -> Option<&str> {
+    let method = path.strip_prefix(PREFIX)?;
+    Some(method.strip_suffix(".view").unwrap_or(method))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+    Xml,
+    Json,
+    /// JSON wrapped in a call to the named function
+    Jsonp(String),
+}
+
+impl Format {
+    pub fn from_query(params: &HashMap<String, String>) -> Format {
+        match params.get("f").map(String::as_str) {
+            Some("json") => Format::Json,
+            Some("jsonp") => {
+                // it ends up in a script, so only a plain function name will do
+                let callback: String = params.get("callback").map_or("callback", String::as_str)
+                    .chars()
+                    .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '.'))
+                    .collect();
+                Format::Jsonp(callback)
+            }
+            _ => Format::Xml,
+        }
+    }
+}
+
+/// A failed response, with one of Subsonic's error codes
+#[derive(Debug)]
+pub struct Error {
+    pub code: u32,
+    pub message: String,
+}
+
+impl Error {
+    fn new(code: u32, message: impl Into<String>) -> Error {
+        Error { code, message: message.into() }
+    }
+
+    pub fn missing(param: &str) -> Error {
+        Error::new(10, format!("Required parameter is missing: {}", param))
+    }
+
+    pub fn not_found(what: &str, id: &str) -> Error {
+        Error::new(70, format!("{} not found: {}", what, id))
+    }
+
+    pub fn generic(message: impl Into<String>) -> Error {
+        Error::new(0, message)
+    }
+}
+
+pub type Reply = Result<Option<(&'static str, Value)>, Error>;
+
+/// Checks the u parameter against our node's name, and the password given as p (plain or
+/// "enc:" hex) or as t, md5(password + s)
+pub fn authenticate(params: &HashMap<String, String>, username: &str, password: Option<&str>) -> Result<(), Error> {
+    let user = params.get("u").ok_or_else(|| Error::missing("u"))?;
+    let Some(password) = password else {
+        return Err(Error::new(40, "Subsonic access is turned off; set a password to turn it on"));
+    };
+    let valid = match (params.get("t"), params.get("s"), params.get("p")) {
+        (Some(token), Some(salt), _) => from_hex(token)
+            .is_some_and(|token| constant_time_eq(&token, &Md5::digest(format!("{}{}", password, salt).as_bytes()))),
+        (_, _, Some(given)) => match given.strip_prefix("enc:") {
+            Some(hex) => from_hex(hex).is_some_and(|given| constant_time_eq(&given, password.as_bytes())),
+            None => constant_time_eq(given.as_bytes(), password.as_bytes()),
+        },
+        _ => return Err(Error::missing("t")),
+    };
+    if user != username || !valid {
+        return Err(Error::new(40, "Wrong username or password"));
+    }
+    Ok(())
+}
+
+/// Answers every endpoint but stream and getCoverArt, which send the file itself
+pub fn handle(method: &str, params: &HashMap<String, String>, username: &str, song_db: &mut SongDb) -> Reply {
+    let id = || params.get("id").map(String::as_str).ok_or_else(|| Error::missing("id"));
+    match method {
+        "ping" => Ok(None),
+        "getLicense" => Ok(Some(("license", json!({ "valid": true })))),
+        "getOpenSubsonicExtensions" => Ok(Some(("openSubsonicExtensions", json!([])))),
+        "getMusicFolders" => Ok(Some(("musicFolders", json!({
+            "musicFolder": [{ "id": MUSIC_FOLDER_ID, "name": "Music" }],
+        })))),
+        "getIndexes" => Ok(Some(("indexes", json!({
+            "lastModified": song_db.catalog_modified_at * 1000,
+            "ignoredArticles": IGNORED_ARTICLES.join(" "),
+            "index": index(&artists(song_db), |artist| json!({ "id": artist.id, "name": artist.name })),
+        })))),
+        "getArtists" => Ok(Some(("artists", json!({
+            "ignoredArticles": IGNORED_ARTICLES.join(" "),
+            "index": index(&artists(song_db), |artist| artist_json(artist, false)),
+        })))),
+        "getArtist" => {
+            let id = id()?;
+            let artists = artists(song_db);
+            let artist = artists.iter().find(|artist| artist.id == id).ok_or_else(|| Error::not_found("Artist", id))?;
+            Ok(Some(("artist", artist_json(artist, true))))
+        }
+        "getMusicDirectory" => {
+            let id = id()?;
+            let artists = artists(song_db);
+            if let Some(artist) = artists.iter().find(|artist| artist.id == id) {
+                let children: Vec<Value> = artist.albums.iter().map(album_directory_json).collect();
+                return Ok(Some(("directory", json!({ "id": artist.id, "name": artist.name, "child": children }))));
+            }
+            let album = artists.iter().flat_map(|artist| &artist.albums).find(|album| album.id == id)
+                .ok_or_else(|| Error::not_found("Directory", id))?;
+            let children: Vec<Value> = album.songs.iter().map(|song| song_json(song)).collect();
+            Ok(Some(("directory", json!({ "id": album.id, "parent": album.artist_id, "name": album.name, "child": children }))))
+        }
+        "getAlbum" => {
+            let id = id()?;
+            let album = albums(song_db).into_iter().find(|album| album.id == id).ok_or_else(|| Error::not_found("Album", id))?;
+            Ok(Some(("album", album_json(&album, true))))
+        }
+        "getSong" => {
+            let id = id()?;
+            let song = song_db.get_song(id).ok_or_else(|| Error::not_found("Song", id))?;
+            Ok(Some(("song", song_json(song))))
+        }
+        "search3" => Ok(Some(("searchResult3", search3(params, song_db)))),
+        "getPlaylists" => {
+            let playlists: Vec<Value> = song_db.list_playlists().iter()
+                .map(|playlist| playlist_json(song_db, playlist, username, false))
+                .collect();
+            Ok(Some(("playlists", json!({ "playlist": playlists }))))
+        }
+        "getPlaylist" => {
+            let id = id()?;
+            let playlist = song_db.playlists.get(id).ok_or_else(|| Error::not_found("Playlist", id))?;
+            Ok(Some(("playlist", playlist_json(song_db, playlist, username, true))))
+        }
+        "scrobble" => {
+            let id = id()?;
+            if song_db.get_song(id).is_none() {
+                return Err(Error::not_found("Song", id));
+            }
+            // submission=false only says what's playing now, which we don't keep
+            if params.get("submission").is_none_or(|submission| submission != "false") {
+                let played_at = params.get("time").and_then(|ms| ms.parse::<u64>().ok()).map_or_else(now_secs, |ms| ms / 1000);
+                song_db.record_play(id, played_at);
+            }
+            Ok(None)
+        }
+        _ => Err(Error::new(0, format!("Not implemented: {}", method))),
+    }
+}
+
+/// Artwork to send for getCoverArt: ours are artwork ids already, but clients also ask by song,
+/// album or artist id
+pub fn cover_art_id(song_db: &SongDb, id: &str) -> Option<String> {
+    if let Some(song) = song_db.get_song(id) {
+        return song.artwork.clone();
+    }
+    if id.starts_with("al-") || id.starts_with("ar-") {
+        return albums(song_db).into_iter()
+            .filter(|album| album.id == id || album.artist_id == id)
+            .find_map(|album| album.cover_art().map(str::to_string));
+    }
+    Some(id.to_string())
+}
+
+/// The response document, content type first
+pub fn render(format: &Format, reply: Reply) -> (&'static str, Vec<u8>) {
+    let mut response = Map::new();
+    response.insert("status".to_string(), json!(if reply.is_ok() { "ok" } else { "failed" }));
+    response.insert("version".to_string(), json!(API_VERSION));
+    response.insert("type".to_string(), json!(SERVER_NAME));
+    response.insert("serverVersion".to_string(), json!(env!("CARGO_PKG_VERSION")));
+    response.insert("openSubsonic".to_string(), json!(true));
+    match reply {
+        Ok(Some((name, payload))) => {
+            response.insert(name.to_string(), without_nulls(payload));
+        }
+        Ok(None) => {}
+        Err(e) => {
+            response.insert("error".to_string(), json!({ "code": e.code, "message": e.message }));
+        }
+    }
+
+    match format {
+        Format::Xml => {
+            response.insert("xmlns".to_string(), json!("http://subsonic.org/restapi"));
+            let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+            write_element(&mut xml, "subsonic-response", &Value::Object(response));
+            ("text/xml; charset=utf-8", xml.into_bytes())
+        }
+        Format::Json => ("application/json", json!({ "subsonic-response": response }).to_string().into_bytes()),
+        Format::Jsonp(callback) => (
+            "text/javascript",
+            format!("{}({});", callback, json!({ "subsonic-response": response })).into_bytes(),
+        ),
+    }
+}
+
+/// Subsonic's XML and JSON carry the same tree: scalars become attributes, objects child
+/// elements and arrays repeated child elements
+fn write_element(xml: &mut String, name: &str, value: &Value) {
+    xml.push('<');
+    xml.push_str(name);
+    let mut children = Vec::new();
+    if let Value::Object(fields) = value {
+        for (key, field) in fields {
+            match field {
+                Value::Object(_) => children.push((key, field)),
+                Value::Array(items) => children.extend(items.iter().map(|item| (key, item))),
+                Value::Null => {}
+                Value::String(text) => xml.push_str(&format!(" {}=\"{}\"", key, xml_escape(text))),
+                scalar => xml.push_str(&format!(" {}=\"{}\"", key, scalar)),
+            }
+        }
+    }
+    if children.is_empty() {
+        xml.push_str("/>");
+        return;
+    }
+    xml.push('>');
+    for (key, child) in children {
+        write_element(xml, key, child);
+    }
+    xml.push_str(&format!("</{}>", name));
+}
+
+/// Fields we have no value for are left out rather than sent as null, which some clients reject
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields.into_iter().filter(|(_, field)| !field.is_null()).map(|(key, field)| (key, without_nulls(field))).collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(without_nulls).collect()),
+        value => value,
+    }
+}
+
+struct Album<'a> {
+    id: String,
+    name: String,
+    artist: String,
+    artist_id: String,
+    /// in track order
+    songs: Vec<&'a Song>,
+}
+
+impl Album<'_> {
+    fn cover_art(&self) -> Option<&str> {
+        self.songs.iter().find_map(|song| song.artwork.as_deref())
+    }
+}
+
+struct Artist<'a> {
+    id: String,
+    name: String,
+    albums: Vec<Album<'a>>,
+}
+
+fn album_artist(song: &Song) -> &str {
+    song.metadata.album_artist.as_deref().or(song.metadata.artist.as_deref()).unwrap_or(UNKNOWN_ARTIST)
+}
+
+fn album_name(song: &Song) -> &str {
+    song.metadata.album.as_deref().or(song.tag.name.as_deref()).unwrap_or(&song.tag.key)
+}
+
+fn artist_id(artist: &str) -> String {
+    format!("ar-{}", short_hash(&artist.to_lowercase()))
+}
+
+fn album_id(artist: &str, album: &str) -> String {
+    format!("al-{}", short_hash(&format!("{}\0{}", artist.to_lowercase(), album.to_lowercase())))
+}
+
+/// Ids stay the same for as long as the names do
+fn short_hash(text: &str) -> String {
+    to_hex(&Sha256::digest(text.as_bytes())[..8])
+}
+
+/// "The Beatles" -> "beatles"
+fn sort_name(name: &str) -> String {
+    let name = name.trim();
+    let without_article = IGNORED_ARTICLES.iter()
+        .find_map(|article| {
+            let rest = name.get(article.len()..)?;
+            (name[..article.len()].eq_ignore_ascii_case(article) && rest.starts_with(' ')).then(|| rest.trim_start())
+        })
+        .unwrap_or(name);
+    without_article.to_lowercase()
+}
+
+/// Every album, sorted by artist and then name
+fn albums(song_db: &SongDb) -> Vec<Album<'_>> {
+    let mut by_id: HashMap<String, Album> = HashMap::new();
+    for song in song_db.songs.values().flatten() {
+        let (artist, name) = (album_artist(song), album_name(song));
+        by_id.entry(album_id(artist, name))
+            .or_insert_with_key(|id| Album {
+                id: id.clone(),
+                name: name.to_string(),
+                artist: artist.to_string(),
+                artist_id: artist_id(artist),
+                songs: Vec::new(),
+            })
+            .songs.push(song);
+    }
+    let mut albums: Vec<Album> = by_id.into_values().collect();
+    for album in &mut albums {
+        album.songs.sort_by(|a, b| a.metadata.track.unwrap_or(u32::MAX).cmp(&b.metadata.track.unwrap_or(u32::MAX)).then(a.name.cmp(&b.name)));
+    }
+    albums.sort_by_cached_key(|album| (sort_name(&album.artist), album.name.to_lowercase()));
+    albums
+}
+
+/// Every album artist, sorted by name
+fn artists(song_db: &SongDb) -> Vec<Artist<'_>> {
+    let mut artists: Vec<Artist> = Vec::new();
+    // albums come sorted by artist, so each artist's albums are next to each other
+    for album in albums(song_db) {
+        match artists.last_mut() {
+            Some(artist) if artist.id == album.artist_id => artist.albums.push(album),
+            _ => artists.push(Artist { id: album.artist_id.clone(), name: album.artist.clone(), albums: vec![album] }),
+        }
+    }
+    artists
+}
+
+/// Artists under the letter they sort by, "#" for anything that doesn't start with one
+fn index(artists: &[Artist], entry: impl Fn(&Artist) -> Value) -> Vec<Value> {
+    let mut letters: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    for artist in artists {
+        let letter = sort_name(&artist.name).chars().next()
+            .filter(|c| c.is_alphabetic())
+            .map_or("#".to_string(), |c| c.to_uppercase().to_string());
+        letters.entry(letter).or_default().push(entry(artist));
+    }
+    letters.into_iter().map(|(name, artists)| json!({ "name": name, "artist": artists })).collect()
+}
+
+fn song_json(song: &Song) -> Value {
+    let (album_artist, album) = (album_artist(song), album_name(song));
+    json!({
+        "id": song.id,
+        "parent": album_id(album_artist, album),
+        "isDir": false,
+        "title": song.metadata.title.as_deref().unwrap_or(&song.name),
+        "album": album,
+        "artist": song.metadata.artist.as_deref().unwrap_or(album_artist),
+        "track": song.metadata.track,
+        "year": song.metadata.year,
+        "genre": song.metadata.genre,
+        "coverArt": song.artwork,
+        "size": song.size,
+        "contentType": song.format.mime_type(),
+        "suffix": song.format.extension(),
+        "duration": song.properties.as_ref().map(|properties| properties.duration_ms / 1000),
+        "bitRate": song.properties.as_ref().map(|properties| properties.bitrate_kbps),
+        "path": format!("{}/{}/{}", album_artist, album, song.id),
+        "created": iso_date(song.uploaded_at),
+        "albumId": album_id(album_artist, album),
+        "artistId": artist_id(album_artist),
+        "type": "music",
+        "playCount": song.play_count,
+        "played": song.last_played.map(iso_date),
+        "bpm": song.bpm.map(|bpm| bpm.round() as u32),
+    })
+}
+
+fn album_json(album: &Album, with_songs: bool) -> Value {
+    let mut json = json!({
+        "id": album.id,
+        "name": album.name,
+        "artist": album.artist,
+        "artistId": album.artist_id,
+        "coverArt": album.cover_art(),
+        "songCount": album.songs.len(),
+        "duration": album.songs.iter().filter_map(|song| song.properties.as_ref()).map(|properties| properties.duration_ms / 1000).sum::<u64>(),
+        "playCount": album.songs.iter().map(|song| song.play_count).sum::<u64>(),
+        "created": album.songs.iter().map(|song| song.uploaded_at).min().map(iso_date),
+        "year": album.songs.iter().find_map(|song| song.metadata.year),
+        "genre": album.songs.iter().find_map(|song| song.metadata.genre.as_ref()),
+    });
+    if with_songs {
+        json["song"] = album.songs.iter().map(|song| song_json(song)).collect();
+    }
+    json
+}
+
+/// An album as a folder under its artist, for the older folder-based endpoints
+fn album_directory_json(album: &Album) -> Value {
+    json!({
+        "id": album.id,
+        "parent": album.artist_id,
+        "isDir": true,
+        "title": album.name,
+        "album": album.name,
+        "artist": album.artist,
+        "coverArt": album.cover_art(),
+        "year": album.songs.iter().find_map(|song| song.metadata.year),
+    })
+}
+
+fn artist_json(artist: &Artist, with_albums: bool) -> Value {
+    let mut json = json!({
+        "id": artist.id,
+        "name": artist.name,
+        "albumCount": artist.albums.len(),
+        "coverArt": artist.albums.iter().find_map(|album| album.cover_art()),
+    });
+    if with_albums {
+        json["album"] = artist.albums.iter().map(|album| album_json(album, false)).collect();
+    }
+    json
+}
+
+fn playlist_json(song_db: &SongDb, playlist: &Playlist, owner: &str, with_entries: bool) -> Value {
+    let songs = song_db.playlist_songs(playlist);
+    let mut json = json!({
+        "id": playlist.id,
+        "name": playlist.name,
+        "owner": owner,
+        "public": false,
+        "songCount": songs.len(),
+        "duration": songs.iter().filter_map(|song| song.properties.as_ref()).map(|properties| properties.duration_ms / 1000).sum::<u64>(),
+        "created": iso_date(playlist.created_at),
+        "changed": iso_date(playlist.updated_at),
+        "coverArt": songs.iter().find_map(|song| song.artwork.as_ref()),
+    });
+    if with_entries {
+        json["entry"] = songs.iter().map(|song| song_json(song)).collect();
+    }
+    json
+}
+
+/// Artists, albums and songs whose names have every word of the query; an empty query, which
+/// clients use to page through the whole library, matches everything
+fn search3(params: &HashMap<String, String>, song_db: &SongDb) -> Value {
+    let query = params.get("query").map_or("", |query| query.trim().trim_matches('"')).to_lowercase();
+    let page = |kind: &str, default: usize| {
+        let number = |name: String| params.get(&name).and_then(|value| value.parse::<usize>().ok());
+        (number(format!("{}Offset", kind)).unwrap_or(0), number(format!("{}Count", kind)).unwrap_or(default))
+    };
+    let matches = |text: &str| {
+        let text = text.to_lowercase();
+        query.split_whitespace().all(|word| text.contains(word))
+    };
+
+    let artists = artists(song_db);
+    let (offset, count) = page("artist", 20);
+    let artist_hits: Vec<Value> = artists.iter()
+        .filter(|artist| matches(&artist.name))
+        .skip(offset).take(count)
+        .map(|artist| artist_json(artist, false))
+        .collect();
+
+    let (offset, count) = page("album", 20);
+    let album_hits: Vec<Value> = artists.iter().flat_map(|artist| &artist.albums)
+        .filter(|album| matches(&format!("{} {}", album.name, album.artist)))
+        .skip(offset).take(count)
+        .map(|album| album_json(album, false))
+        .collect();
+
+    let (offset, count) = page("song", 20);
+    let songs: Vec<&Song> = if query.is_empty() {
+        artists.iter().flat_map(|artist| &artist.albums).flat_map(|album| album.songs.iter().copied()).collect()
+    } else {
+        search::search(song_db, &query, &SongFilter::default()).iter()
+            .filter_map(|hit| song_db.get_song(&hit.song.id))
+            .collect()
+    };
+    let song_hits: Vec<Value> = songs.into_iter().skip(offset).take(count).map(song_json).collect();
+
+    json!({ "artist": artist_hits, "album": album_hits, "song": song_hits })
+}
+
+/// "2024-05-01T12:00:00Z"
+fn iso_date(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let time = secs % 86_400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Compares secrets without bailing out at the first differing byte, so response times don't
+/// give away how much of a guess was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,97 @@
+// Tempo estimation: an onset strength envelope (how much the spectrum grows from one short
+// frame to the next) is autocorrelated, and each candidate tempo is scored by the envelope's
+// self-similarity over the next eight beats. A prior centred on 120 BPM settles octave confusion,
+// e.g. 64 against 128.
+
+use crate::dsp::{self, Spectrogram};
+use crate::metadata::AudioFormat;
+
+const SAMPLE_RATE: u32 = 11_025;
+const FRAME_LEN: usize = 512;
+const HOP: usize = 64;
+const MAX_SECS: u32 = 120;
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 200.0;
+const PREFERRED_BPM: f64 = 120.0;
+/// Width of the prior in octaves
+const PRIOR_OCTAVES: f64 = 0.9;
+const BEATS_COMPARED: usize = 8;
+/// Frames the envelope's running mean is taken over, about a third of a second
+const MEAN_FRAMES: usize = 57;
+const SPREAD: [f64; 5] = [0.25, 0.5, 1.0, 0.5, 0.25];
+
+pub fn detect(format: AudioFormat, data: Vec<u8>) -> anyhow::Result<Option<f32>> {
+    Ok(from_samples(&dsp::decode_mono(format, data, SAMPLE_RATE, MAX_SECS)?))
+}
+
+/// BPM of 11025 Hz mono samples to one decimal, None when there's no pulse to find
+pub fn from_samples(samples: &[f32]) -> Option<f32> {
+    let frames_per_sec = SAMPLE_RATE as f64 / HOP as f64;
+    let envelope = onset_envelope(samples);
+    let max_lag = (BEATS_COMPARED as f64 * frames_per_sec * 60.0 / MIN_BPM).ceil() as usize + 2;
+    if envelope.len() < max_lag * 2 {
+        return None;
+    }
+
+    let autocorrelation: Vec<f64> = (0..=max_lag)
+        .map(|lag| {
+            let sum: f64 = envelope.iter().zip(&envelope[lag..]).map(|(a, b)| a * b).sum();
+            sum / (envelope.len() - lag) as f64
+        })
+        .collect();
+    if autocorrelation[0] <= 1e-12 {
+        return None;
+    }
+    let at = |lag: f64| {
+        let (whole, fraction) = (lag.floor() as usize, lag.fract());
+        autocorrelation[whole] * (1.0 - fraction) + autocorrelation[whole + 1] * fraction
+    };
+    let score = |bpm: f64| {
+        let beat = frames_per_sec * 60.0 / bpm;
+        let similarity: f64 = (1..=BEATS_COMPARED).map(|beats| at(beat * beats as f64)).sum();
+        let octaves = (bpm / PREFERRED_BPM).log2() / PRIOR_OCTAVES;
+        similarity * (-0.5 * octaves * octaves).exp()
+    };
+    let best = |from: f64, to: f64, step: f64| {
+        (0..=((to - from) / step).round() as usize)
+            .map(|i| from + i as f64 * step)
+            .max_by(|&a, &b| score(a).total_cmp(&score(b)))
+            .unwrap_or(from)
+    };
+
+    let coarse = best(MIN_BPM, MAX_BPM, 0.5);
+    let fine = best((coarse - 0.5).max(MIN_BPM), (coarse + 0.5).min(MAX_BPM), 0.02);
+    Some((fine * 10.0).round() as f32 / 10.0)
+}
+
+/// Spectral flux of log magnitudes, less its running mean and clipped at zero so that only
+/// sudden rises count
+fn onset_envelope(samples: &[f32]) -> Vec<f64> {
+    let mut flux = Vec::new();
+    let mut previous: Option<Vec<f64>> = None;
+    Spectrogram::new(FRAME_LEN).for_each_frame(samples, HOP, |power| {
+        let magnitudes: Vec<f64> = power.iter().map(|power| (1.0 + 1000.0 * power.sqrt()).ln()).collect();
+        if let Some(previous) = &previous {
+            flux.push(magnitudes.iter().zip(previous).map(|(now, before)| (now - before).max(0.0)).sum());
+        }
+        previous = Some(magnitudes);
+    });
+
+    let half = MEAN_FRAMES / 2;
+    let onsets: Vec<f64> = (0..flux.len())
+        .map(|i| {
+            let window = &flux[i.saturating_sub(half)..(i + half + 1).min(flux.len())];
+            let mean = window.iter().sum::<f64>() / window.len() as f64;
+            (flux[i] - mean).max(0.0)
+        })
+        .collect();
+    // spreading each onset over neighbouring frames keeps the autocorrelation from favouring
+    // beats that happen to be a whole number of frames long
+    (0..onsets.len())
+        .map(|i| {
+            SPREAD.iter().enumerate()
+                .filter_map(|(j, weight)| Some(onsets.get((i + j).checked_sub(SPREAD.len() / 2)?)? * weight))
+                .sum()
+        })
+        .collect()
+}
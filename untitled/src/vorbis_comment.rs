@@ -0,0 +1,80 @@
+// Vorbis comment block, shared by FLAC, Ogg Vorbis and Opus.
+// https://xiph.org/vorbis/doc/v-comment.html
+
+use crate::key::Key;
+use crate::lyrics::Lyrics;
+use crate::metadata::{ReplayGain, SongMetadata};
+
+#[derive(Debug, Clone, Default)]
+pub struct VorbisComments {
+    /// (upper-cased field name, value) in file order
+    pub comments: Vec<(String, String)>,
+}
+
+impl VorbisComments {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let vendor_len = le_u32(data, 0)? as usize;
+        // lengths come from the file, so every step is checked against usize overflow on wasm32
+        let mut pos = 4usize.checked_add(vendor_len)?;
+        let count = le_u32(data, pos)?;
+        pos += 4;
+
+        let mut comments = Vec::new();
+        for _ in 0..count {
+            let len = le_u32(data, pos)? as usize;
+            pos += 4;
+            let comment = data.get(pos..pos.checked_add(len)?)?;
+            pos += len;
+            if let Some((key, value)) = String::from_utf8_lossy(comment).split_once('=') {
+                comments.push((key.to_ascii_uppercase(), value.to_string()));
+            }
+        }
+        Some(Self { comments })
+    }
+
+    /// First non-empty value of a field, field names are case-insensitive
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.comments.iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.trim())
+            .find(|value| !value.is_empty())
+    }
+
+    pub fn to_metadata(&self) -> SongMetadata {
+        let text = |key: &str| self.get(key).map(str::to_string);
+        SongMetadata {
+            title: text("TITLE"),
+            artist: text("ARTIST"),
+            album_artist: text("ALBUMARTIST").or_else(|| text("ALBUM ARTIST")),
+            album: text("ALBUM"),
+            track: self.get("TRACKNUMBER").and_then(|track| track.split('/').next()?.trim().parse().ok()),
+            year: self.get("DATE")
+                .or_else(|| self.get("YEAR"))
+                .and_then(|date| date.get(..4)?.parse().ok()),
+            genre: text("GENRE"),
+            replay_gain: self.replay_gain(),
+            bpm: self.get("BPM").and_then(SongMetadata::parse_bpm),
+            key: self.get("INITIALKEY").or_else(|| self.get("KEY")).and_then(Key::parse),
+            ..Default::default()
+        }
+    }
+
+    /// LYRICS, or UNSYNCEDLYRICS as some taggers call it; either may hold LRC
+    pub fn lyrics(&self) -> Option<Lyrics> {
+        self.get("LYRICS").or_else(|| self.get("UNSYNCEDLYRICS")).and_then(Lyrics::parse)
+    }
+
+    /// REPLAYGAIN_* fields, or failing those Opus' R128_*_GAIN: Q7.8 dB relative to -23 LUFS
+    fn replay_gain(&self) -> ReplayGain {
+        let mut replay_gain = ReplayGain::from_fields(self.comments.iter().map(|(key, value)| (key.as_str(), value.as_str())));
+        let r128 = |key: &str| self.get(key)?.parse::<i16>().ok().map(|gain| gain as f32 / 256.0 + 5.0);
+        replay_gain.track_gain_db = replay_gain.track_gain_db.or_else(|| r128("R128_TRACK_GAIN"));
+        replay_gain.album_gain_db = replay_gain.album_gain_db.or_else(|| r128("R128_ALBUM_GAIN"));
+        replay_gain
+    }
+}
+
+fn le_u32(data: &[u8], pos: usize) -> Option<u32> {
+    let bytes = data.get(pos..pos.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
@@ -0,0 +1,111 @@
+// RIFF/WAVE chunks, with metadata from LIST/INFO or an embedded "id3 " chunk.
+// http://soundfile.sapp.org/doc/WaveFormat/
+
+use crate::chapters::ChapterMark;
+use crate::id3;
+use crate::lyrics::Lyrics;
+use crate::metadata::{AudioProperties, BitrateMode, Picture, SongMetadata};
+
+/// (chunk id, chunk body) for every top level chunk after the RIFF/WAVE header
+pub fn chunks(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return chunks;
+    }
+    let mut pos = 12;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let id = [header[0], header[1], header[2], header[3]];
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let start = pos + 8;
+        // streaming writers leave the data size at 0 or 0xFFFFFFFF
+        let end = start.saturating_add(len).min(data.len());
+        chunks.push((id, &data[start..end]));
+        pos = end + (len & 1);
+    }
+    chunks
+}
+
+fn chunk<'a>(data: &'a [u8], id: &[u8; 4]) -> Option<&'a [u8]> {
+    chunks(data).into_iter().find(|(chunk_id, _)| chunk_id == id).map(|(_, body)| body)
+}
+
+/// LIST/INFO sub-chunks as (id, text)
+fn info(data: &[u8]) -> Vec<([u8; 4], String)> {
+    let mut values = Vec::new();
+    for (id, body) in chunks(data) {
+        if &id != b"LIST" || body.get(0..4) != Some(b"INFO") {
+            continue;
+        }
+        let mut pos = 4;
+        while let Some(header) = body.get(pos..pos + 8) {
+            let sub_id = [header[0], header[1], header[2], header[3]];
+            let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            let start = pos + 8;
+            let end = start.saturating_add(len).min(body.len());
+            let text = String::from_utf8_lossy(&body[start..end]).trim_end_matches('\0').trim().to_string();
+            if !text.is_empty() {
+                values.push((sub_id, text));
+            }
+            pos = end + (len & 1);
+        }
+    }
+    values
+}
+
+fn id3_chunk(data: &[u8]) -> Option<&[u8]> {
+    chunk(data, b"id3 ").or_else(|| chunk(data, b"ID3 "))
+}
+
+pub fn read_picture(data: &[u8]) -> Option<Picture> {
+    id3::read_picture(id3_chunk(data)?)
+}
+
+pub fn read_chapters(data: &[u8]) -> Vec<ChapterMark> {
+    id3_chunk(data).map(id3::read_chapters).unwrap_or_default()
+}
+
+pub fn read_lyrics(data: &[u8]) -> Option<Lyrics> {
+    id3::read_lyrics(id3_chunk(data)?)
+}
+
+pub fn read_metadata(data: &[u8]) -> SongMetadata {
+    let mut metadata = id3_chunk(data)
+        .map(id3::read_metadata)
+        .unwrap_or_default();
+
+    let info = info(data);
+    let text = |id: &[u8; 4]| info.iter().find(|(sub_id, _)| sub_id == id).map(|(_, text)| text.clone());
+    metadata.fill_missing(SongMetadata {
+        title: text(b"INAM"),
+        artist: text(b"IART"),
+        album: text(b"IPRD"),
+        track: text(b"ITRK").or_else(|| text(b"IPRT")).and_then(|track| track.split('/').next()?.trim().parse().ok()),
+        year: text(b"ICRD").and_then(|date| date.get(..4)?.parse().ok()),
+        genre: text(b"IGNR"),
+        ..Default::default()
+    });
+    metadata
+}
+
+pub fn read_properties(data: &[u8]) -> Option<AudioProperties> {
+    let fmt = chunk(data, b"fmt ")?;
+    if fmt.len() < 16 {
+        return None;
+    }
+    let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+    let sample_rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+    let byte_rate = u32::from_le_bytes([fmt[8], fmt[9], fmt[10], fmt[11]]);
+    let data_len = chunk(data, b"data")?.len() as u64;
+    if byte_rate == 0 {
+        return None;
+    }
+
+    Some(AudioProperties {
+        duration_ms: data_len * 1000 / byte_rate as u64,
+        bitrate_kbps: byte_rate * 8 / 1000,
+        bitrate_mode: BitrateMode::Cbr,
+        sample_rate,
+        channels: channels as u8,
+        channel_mode: None,
+    })
+}
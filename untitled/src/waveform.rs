@@ -0,0 +1,86 @@
+// Downsampled min/max peaks for drawing a song's waveform, and the sidecar file they're kept in:
+// "PEAK", a version byte, the pair count as u32 LE, then (min, max) pairs of i16 LE.
+
+use std::ops::ControlFlow;
+
+use crate::decode;
+use crate::metadata::AudioFormat;
+
+pub const PEAK_COUNT: usize = 1000;
+
+const MAGIC: &[u8; 4] = b"PEAK";
+const VERSION: u8 = 1;
+/// We don't know a song's length until it's decoded, so peaks are first gathered into small
+/// buckets that double in size whenever there are this many of them, then merged down to `count`.
+const MAX_FINE_BUCKETS: usize = 1 << 16;
+
+/// (min, max) over all channels for `count` equal slices of the song, in -1.0..=1.0
+pub fn compute_peaks(format: AudioFormat, data: Vec<u8>, count: usize) -> anyhow::Result<Vec<(f32, f32)>> {
+    let mut fine: Vec<(f32, f32)> = Vec::new();
+    let mut current = (0.0f32, 0.0f32);
+    let mut frames_in_bucket = 0;
+    let mut bucket_frames = 16;
+    decode::for_each_block(format, data, |samples, channels, _| {
+        for frame in samples.chunks(channels.max(1)) {
+            for &sample in frame {
+                current.0 = current.0.min(sample);
+                current.1 = current.1.max(sample);
+            }
+            frames_in_bucket += 1;
+            if frames_in_bucket == bucket_frames {
+                fine.push(current);
+                current = (0.0, 0.0);
+                frames_in_bucket = 0;
+                if fine.len() == MAX_FINE_BUCKETS {
+                    fine = fine.chunks(2).map(merge).collect();
+                    bucket_frames *= 2;
+                }
+            }
+        }
+        ControlFlow::Continue(())
+    })?;
+    if frames_in_bucket > 0 {
+        fine.push(current);
+    }
+    if fine.is_empty() {
+        anyhow::bail!("No audio could be decoded");
+    }
+
+    let count = count.min(fine.len());
+    Ok((0..count).map(|i| {
+        merge(&fine[i * fine.len() / count..(i + 1) * fine.len() / count])
+    }).collect())
+}
+
+fn merge(peaks: &[(f32, f32)]) -> (f32, f32) {
+    peaks.iter().fold((0.0f32, 0.0f32), |(min, max), &(lo, hi)| (min.min(lo), max.max(hi)))
+}
+
+pub fn encode(peaks: &[(f32, f32)]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(9 + peaks.len() * 4);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&(peaks.len() as u32).to_le_bytes());
+    for &(min, max) in peaks {
+        bytes.extend_from_slice(&quantize(min).to_le_bytes());
+        bytes.extend_from_slice(&quantize(max).to_le_bytes());
+    }
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> Option<Vec<(f32, f32)>> {
+    if bytes.get(0..4)? != MAGIC || *bytes.get(4)? != VERSION {
+        return None;
+    }
+    let count = u32::from_le_bytes(bytes.get(5..9)?.try_into().ok()?) as usize;
+    let body = bytes.get(9..9 + count.checked_mul(4)?)?;
+    Some(body.chunks_exact(4).map(|pair| {
+        let min = i16::from_le_bytes([pair[0], pair[1]]);
+        let max = i16::from_le_bytes([pair[2], pair[3]]);
+        (min as f32 / i16::MAX as f32, max as f32 / i16::MAX as f32)
+    }).collect())
+}
+
+fn quantize(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
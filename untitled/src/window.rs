@@ -0,0 +1,116 @@
+// A time window cut out of a song without re-encoding, so a chapter can be streamed on its own
+// while the file is stored once. MP3 and FLAC are cut on frame boundaries, so the window can
+// start a little early and end a little late; WAV is cut to the sample.
+
+use crate::flac;
+use crate::metadata::AudioFormat;
+use crate::mp3;
+use crate::wav;
+
+/// None when the format can't be cut or the window holds no audio
+pub fn cut(format: AudioFormat, data: &[u8], start_ms: u64, end_ms: u64) -> Option<Vec<u8>> {
+    if end_ms <= start_ms {
+        return None;
+    }
+    match format {
+        AudioFormat::Mp3 => cut_mp3(data, start_ms, end_ms),
+        AudioFormat::Flac => cut_flac(data, start_ms, end_ms),
+        AudioFormat::Wav => cut_wav(data, start_ms, end_ms),
+        AudioFormat::OggVorbis | AudioFormat::Opus | AudioFormat::M4a => None,
+    }
+}
+
+/// Every frame that has samples in the window; the ID3 tag and Xing/Info frame are left out
+fn cut_mp3(data: &[u8], start_ms: u64, end_ms: u64) -> Option<Vec<u8>> {
+    let mut frames: Vec<mp3::Frame> = mp3::frames(data).collect();
+    let first = *frames.first()?;
+    if mp3::read_vbr_header(data, &first).is_some() {
+        frames.remove(0);
+    }
+    let sample_rate = first.header.sample_rate as u64;
+    let (start, end) = (sample_at(start_ms, sample_rate), sample_at(end_ms, sample_rate));
+
+    let mut position = 0;
+    let mut bytes: Option<(usize, usize)> = None;
+    for frame in &frames {
+        let next = position + frame.header.samples as u64;
+        if next > start {
+            let frame_end = frame.offset + frame.header.frame_len;
+            bytes = Some((bytes.map_or(frame.offset, |(from, _)| from), frame_end));
+        }
+        position = next;
+        if position >= end {
+            break;
+        }
+    }
+    let (from, to) = bytes?;
+    Some(data[from..to.min(data.len())].to_vec())
+}
+
+/// STREAMINFO, then the frames from the one holding the first sample of the window up to the
+/// one starting after it. Other metadata blocks are left out.
+fn cut_flac(data: &[u8], start_ms: u64, end_ms: u64) -> Option<Vec<u8>> {
+    let (blocks, _) = flac::blocks(data)?;
+    let info = blocks.first().filter(|block| block.block_type == flac::STREAMINFO && block.data.len() == 34)?.data;
+    let sample_rate = ((info[10] as u64) << 12) | ((info[11] as u64) << 4) | (info[12] as u64 >> 4);
+    let (start, end) = (sample_at(start_ms, sample_rate), sample_at(end_ms, sample_rate));
+
+    let frames = flac::frame_starts(data);
+    let from = frames.iter().rev().find(|frame| frame.sample <= start).or(frames.first())?.offset;
+    let to = frames.iter().find(|frame| frame.sample >= end).map_or(data.len(), |frame| frame.offset);
+    if to <= from {
+        return None;
+    }
+
+    let mut info = info.to_vec();
+    // the total sample count and MD5 are the whole stream's; zero means unknown for both
+    info[13] &= 0xF0;
+    info[14..18].fill(0);
+    info[18..34].fill(0);
+    let mut cut = Vec::with_capacity(8 + info.len() + to - from);
+    cut.extend_from_slice(b"fLaC");
+    cut.extend_from_slice(&[0x80 | flac::STREAMINFO, 0, 0, info.len() as u8]);
+    cut.extend_from_slice(&info);
+    cut.extend_from_slice(&data[from..to]);
+    Some(cut)
+}
+
+/// A new RIFF file with the original fmt chunk and the window of the data chunk
+fn cut_wav(data: &[u8], start_ms: u64, end_ms: u64) -> Option<Vec<u8>> {
+    let chunks = wav::chunks(data);
+    let fmt = chunks.iter().find(|(id, _)| id == b"fmt ")?.1;
+    let pcm = chunks.iter().find(|(id, _)| id == b"data")?.1;
+    let sample_rate = u32::from_le_bytes(fmt.get(4..8)?.try_into().ok()?) as u64;
+    let block_align = u16::from_le_bytes(fmt.get(12..14)?.try_into().ok()?) as u64;
+    if block_align == 0 {
+        return None;
+    }
+    let whole_frames = pcm.len() as u64 / block_align;
+    let offset = |ms: u64| (sample_at(ms, sample_rate).min(whole_frames) * block_align) as usize;
+    let (from, to) = (offset(start_ms), offset(end_ms));
+    if from >= to {
+        return None;
+    }
+    let pcm = &pcm[from..to];
+
+    let padded = |len: usize| len + (len & 1);
+    let mut cut = Vec::with_capacity(12 + 8 + padded(fmt.len()) + 8 + padded(pcm.len()));
+    cut.extend_from_slice(b"RIFF");
+    cut.extend_from_slice(&((4 + 8 + padded(fmt.len()) + 8 + padded(pcm.len())) as u32).to_le_bytes());
+    cut.extend_from_slice(b"WAVE");
+    for (id, body) in [(b"fmt ", fmt), (b"data", pcm)] {
+        cut.extend_from_slice(id);
+        cut.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        cut.extend_from_slice(body);
+        if body.len() & 1 == 1 {
+            cut.push(0);
+        }
+    }
+    Some(cut)
+}
+
+/// Index of the sample at `ms`; windows come from query strings, so anything past the end of
+/// time just lands past the end of the song
+fn sample_at(ms: u64, sample_rate: u64) -> u64 {
+    ms.saturating_mul(sample_rate) / 1000
+}